@@ -0,0 +1,124 @@
+//! Compares writing events from many tasks at once through the `DbWriter` with every task
+//! writing through its own pooled connection (how the server used to do it).
+//!
+//! Run with `cargo bench --bench db_writer`.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use r2d2::Pool;
+use r2d2_sqlite::SqliteConnectionManager;
+use reitunes_workspace::{
+    open_connection_pool, save_raw_event_to_db, Event, EventStore, EventWithMetadata, PlayDetails,
+    RawEvent, SqliteEventStore,
+};
+use uuid::Uuid;
+
+/// Concurrent writers per burst: one user, a handful of tabs, a bulk import
+const CLIENT_COUNTS: [usize; 3] = [1, 8, 64];
+const WRITES_PER_CLIENT: usize = 50;
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    println!(
+        "{:<8} {:>8} {:>12} {:>10} {:>10} {:>10}",
+        "via", "clients", "events/sec", "p50", "p99", "max"
+    );
+    for clients in CLIENT_COUNTS {
+        let dir = tempfile::tempdir()?;
+        let pool = open_connection_pool(dir.path().join("pool.db").to_str().unwrap())?;
+        report("pool", clients, burst_through_pool(pool, clients).await?);
+
+        let pool = open_connection_pool(dir.path().join("writer.db").to_str().unwrap())?;
+        let store = Arc::new(SqliteEventStore::new(pool)?);
+        report(
+            "writer",
+            clients,
+            burst_through_writer(store, clients).await?,
+        );
+    }
+    Ok(())
+}
+
+struct Burst {
+    elapsed: Duration,
+    /// How long each write took to be acknowledged
+    latencies: Vec<Duration>,
+}
+
+fn played_event() -> Result<RawEvent> {
+    EventWithMetadata::new(
+        Uuid::new_v4(),
+        Event::LibraryItemPlayedEvent(PlayDetails::default()),
+    )?
+    .to_raw()
+}
+
+async fn burst_through_pool(pool: Pool<SqliteConnectionManager>, clients: usize) -> Result<Burst> {
+    let start = Instant::now();
+    let mut tasks = Vec::new();
+    for _ in 0..clients {
+        let pool = pool.clone();
+        tasks.push(tokio::task::spawn_blocking(
+            move || -> Result<Vec<Duration>> {
+                let mut latencies = Vec::new();
+                for _ in 0..WRITES_PER_CLIENT {
+                    let event = played_event()?;
+                    let write_start = Instant::now();
+                    save_raw_event_to_db(&*pool.get()?, &event)?;
+                    latencies.push(write_start.elapsed());
+                }
+                Ok(latencies)
+            },
+        ));
+    }
+    collect(start, tasks).await
+}
+
+async fn burst_through_writer(store: Arc<SqliteEventStore>, clients: usize) -> Result<Burst> {
+    let start = Instant::now();
+    let mut tasks = Vec::new();
+    for _ in 0..clients {
+        let store = store.clone();
+        tasks.push(tokio::spawn(async move {
+            let mut latencies = Vec::new();
+            for _ in 0..WRITES_PER_CLIENT {
+                let event = played_event()?;
+                let write_start = Instant::now();
+                store.append(&event).await?;
+                latencies.push(write_start.elapsed());
+            }
+            Ok(latencies)
+        }));
+    }
+    collect(start, tasks).await
+}
+
+async fn collect(
+    start: Instant,
+    tasks: Vec<tokio::task::JoinHandle<Result<Vec<Duration>>>>,
+) -> Result<Burst> {
+    let mut latencies = Vec::new();
+    for task in tasks {
+        latencies.extend(task.await??);
+    }
+    Ok(Burst {
+        elapsed: start.elapsed(),
+        latencies,
+    })
+}
+
+fn report(via: &str, clients: usize, mut burst: Burst) {
+    burst.latencies.sort();
+    let percentile = |p: usize| burst.latencies[(burst.latencies.len() - 1) * p / 100];
+    println!(
+        "{:<8} {:>8} {:>12.0} {:>10.2?} {:>10.2?} {:>10.2?}",
+        via,
+        clients,
+        burst.latencies.len() as f64 / burst.elapsed.as_secs_f64(),
+        percentile(50),
+        percentile(99),
+        percentile(100),
+    );
+}
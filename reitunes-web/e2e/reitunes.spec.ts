@@ -14,17 +14,20 @@ const libraryItems = [
     file_path: 'northern-sky.mp3',
     track_number: 7,
     play_count: 12,
+    tags: [],
     is_favorite: true,
     url: '/audio/northern-sky.mp3',
     bookmarks: {
       [BOOKMARK_ID]: {
         position: 70,
+        end_position: null,
         emoji: '🎸',
         label: 'Guitar entrance',
         created_time_utc: '2026-08-01T12:00:00',
       },
       [UNLABELLED_BOOKMARK_ID]: {
         position: 145,
+        end_position: null,
         emoji: '🎵',
         label: null,
         created_time_utc: '2026-07-01T12:00:00',
@@ -1,7 +1,7 @@
 import { useEffect, useRef, useCallback, useState } from 'react';
 import { usePlayerStore } from '../stores/playerStore';
 import { useQueueStore } from '../hooks/useQueue';
-import { getItemUrl, markPlayed, addBookmark } from '../hooks/useLibrary';
+import { getItemUrl, markPlayed, addBookmark, saveResumePosition } from '../hooks/useLibrary';
 import { usePlayback } from '../hooks/usePlayback';
 import { useSonosControls } from '../hooks/useSonosControls';
 import { usePlaybackTargetStore } from '../stores/playbackTargetStore';
@@ -112,6 +112,8 @@ export function AudioPlayer({ onChooseOutput, items }: AudioPlayerProps) {
     isMuted,
     setIsPlaying,
     clearPendingSeek,
+    sectionEnd,
+    clearSectionEnd,
     resumePosition,
     setResumePosition,
     setVolume,
@@ -224,13 +226,21 @@ export function AudioPlayer({ onChooseOutput, items }: AudioPlayerProps) {
       const position = audioRef.current.currentTime;
       setCurrentTimeLocal(position);
 
+      if (sectionEnd !== null && position >= sectionEnd) {
+        clearSectionEnd();
+        audioRef.current.pause();
+      }
+
       const checkpoint = Math.floor(position / 5);
       if (checkpoint !== lastCheckpointRef.current) {
         lastCheckpointRef.current = checkpoint;
         setResumePosition(position);
+        if (currentItem && checkpoint % 3 === 0) {
+          saveResumePosition(currentItem.id, position).catch(console.error);
+        }
       }
     }
-  }, [setResumePosition]);
+  }, [clearSectionEnd, currentItem, sectionEnd, setResumePosition]);
 
   const handleLoadedMetadata = useCallback(() => {
     if (audioRef.current) {
@@ -245,6 +255,10 @@ export function AudioPlayer({ onChooseOutput, items }: AudioPlayerProps) {
 
   const handleEnded = useCallback(() => {
     setResumePosition(0);
+    if (currentItem && audioRef.current) {
+      // the server clears positions this close to the end
+      saveResumePosition(currentItem.id, audioRef.current.currentTime).catch(console.error);
+    }
     if (repeatMode === 'one' && audioRef.current) {
       audioRef.current.currentTime = 0;
       audioRef.current.play();
@@ -252,7 +266,7 @@ export function AudioPlayer({ onChooseOutput, items }: AudioPlayerProps) {
     }
     const nextItem = playNext();
     if (nextItem) void play(nextItem);
-  }, [playNext, play, repeatMode, setResumePosition]);
+  }, [currentItem, playNext, play, repeatMode, setResumePosition]);
 
   const handlePlayPause = useCallback(() => {
     if (!audioRef.current) return;
@@ -340,8 +354,12 @@ export function AudioPlayer({ onChooseOutput, items }: AudioPlayerProps) {
   const handleAudioPause = useCallback(() => {
     if (isChangingSourceRef.current) return;
     setIsPlaying(false);
-    if (audioRef.current) setResumePosition(audioRef.current.currentTime);
-  }, [setIsPlaying, setResumePosition]);
+    if (!audioRef.current) return;
+    setResumePosition(audioRef.current.currentTime);
+    if (currentItem) {
+      saveResumePosition(currentItem.id, audioRef.current.currentTime).catch(console.error);
+    }
+  }, [currentItem, setIsPlaying, setResumePosition]);
 
   const handleAudioPlay = useCallback(() => {
     isChangingSourceRef.current = false;
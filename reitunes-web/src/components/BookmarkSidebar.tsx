@@ -1,21 +1,25 @@
 import { useMemo, useState } from 'react';
-import { deleteBookmark, updateBookmark } from '../hooks/useLibrary';
-import type { LibraryItem } from '../types';
+import { deleteBookmark, moveBookmark, updateBookmark } from '../hooks/useLibrary';
+import type { Bookmark, LibraryItem } from '../types';
 import {
   bookmarkEntries,
   filterBookmarkEntries,
   formatBookmarkPosition,
+  formatBookmarkTime,
+  parseBookmarkPosition,
 } from '../utils/bookmarks';
 
 interface BookmarkSidebarProps {
   items: LibraryItem[];
-  onPlay: (item: LibraryItem, position: number) => void;
+  onPlay: (item: LibraryItem, position: number, sectionEnd: number | null) => void;
 }
 
 interface EditState {
   bookmarkId: string;
   label: string;
   emoji: string;
+  start: string;
+  end: string;
 }
 
 export function BookmarkSidebar({ items, onPlay }: BookmarkSidebarProps) {
@@ -29,12 +33,22 @@ export function BookmarkSidebar({ items, onPlay }: BookmarkSidebarProps) {
     [items, query]
   );
 
-  const saveEdit = async (itemId: string) => {
+  const saveEdit = async (itemId: string, bookmark: Bookmark) => {
     if (!editing) return;
+    const start = parseBookmarkPosition(editing.start);
+    const end = editing.end.trim() ? parseBookmarkPosition(editing.end) : null;
+    if (start === null || (editing.end.trim() && (end === null || end <= start))) {
+      setError('Bookmarks need a start time, and ranges must end after they start.');
+      return;
+    }
     setPendingBookmarkId(editing.bookmarkId);
     setError(null);
     try {
       await updateBookmark(itemId, editing.bookmarkId, editing.label, editing.emoji);
+      if (editing.start !== formatBookmarkPosition(bookmark.position) ||
+          end !== (bookmark.end_position ?? null)) {
+        await moveBookmark(itemId, editing.bookmarkId, start, end);
+      }
       setEditing(null);
     } catch {
       setError('Could not save the bookmark.');
@@ -99,7 +113,7 @@ export function BookmarkSidebar({ items, onPlay }: BookmarkSidebarProps) {
                     className="space-y-2"
                     onSubmit={(event) => {
                       event.preventDefault();
-                      void saveEdit(item.id);
+                      void saveEdit(item.id, bookmark);
                     }}
                   >
                     <div className="flex gap-1">
@@ -122,6 +136,26 @@ export function BookmarkSidebar({ items, onPlay }: BookmarkSidebarProps) {
                         className="min-w-0 flex-grow px-2 py-1 bg-solarized-base03 border border-solarized-blue text-sm"
                       />
                     </div>
+                    <div className="flex items-center gap-1 text-xs">
+                      <input
+                        value={editing.start}
+                        onChange={(event) =>
+                          setEditing({ ...editing, start: event.target.value })
+                        }
+                        aria-label={`Bookmark start for ${item.name}`}
+                        className="w-20 px-2 py-1 bg-solarized-base03 border border-solarized-blue"
+                      />
+                      <span className="text-solarized-base0">to</span>
+                      <input
+                        value={editing.end}
+                        onChange={(event) =>
+                          setEditing({ ...editing, end: event.target.value })
+                        }
+                        aria-label={`Bookmark end for ${item.name}`}
+                        placeholder="end (optional)"
+                        className="min-w-0 flex-grow px-2 py-1 bg-solarized-base03 border border-solarized-blue"
+                      />
+                    </div>
                     <div className="flex justify-end gap-2 text-xs">
                       <button
                         type="button"
@@ -143,7 +177,7 @@ export function BookmarkSidebar({ items, onPlay }: BookmarkSidebarProps) {
                   <>
                     <button
                       type="button"
-                      onClick={() => onPlay(item, bookmark.position)}
+                      onClick={() => onPlay(item, bookmark.position, bookmark.end_position)}
                       className="w-full text-left"
                       aria-label={`Play ${item.name} from ${displayLabel}`}
                     >
@@ -155,7 +189,7 @@ export function BookmarkSidebar({ items, onPlay }: BookmarkSidebarProps) {
                           </div>
                           <div className="truncate text-xs text-solarized-blue">{item.name}</div>
                           <div className="truncate text-xs text-solarized-base0">
-                            {[item.artist, formatBookmarkPosition(bookmark.position)]
+                            {[item.artist, formatBookmarkTime(bookmark)]
                               .filter(Boolean)
                               .join(' · ')}
                           </div>
@@ -170,6 +204,10 @@ export function BookmarkSidebar({ items, onPlay }: BookmarkSidebarProps) {
                             bookmarkId,
                             label: bookmark.label || '',
                             emoji: bookmark.emoji || '🔖',
+                            start: formatBookmarkPosition(bookmark.position),
+                            end: bookmark.end_position == null
+                              ? ''
+                              : formatBookmarkPosition(bookmark.end_position),
                           })
                         }
                         className="text-solarized-base0 hover:text-solarized-cyan"
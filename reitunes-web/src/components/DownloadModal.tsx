@@ -1,4 +1,5 @@
 import { useState, useCallback, useRef, useEffect } from 'react';
+import { libraryPath } from '../utils/libraryPath';
 
 type DownloadType = 'Audio' | 'Video';
 
@@ -57,7 +58,7 @@ export function DownloadModal({ isOpen, onClose }: DownloadModalProps) {
     setMessage(null);
 
     try {
-      const response = await fetch('/api/download', {
+      const response = await fetch(libraryPath('/api/download'), {
         method: 'POST',
         headers: { 'Content-Type': 'application/json' },
         body: JSON.stringify({ url: url.trim(), dl_type: dlType }),
@@ -16,8 +16,11 @@ import { useQueueStore } from '../hooks/useQueue';
 import { usePlayback } from '../hooks/usePlayback';
 import { updateLibraryItem, deleteItem as apiDeleteItem } from '../hooks/useLibrary';
 import { FavoriteButton } from './FavoriteButton';
+import { RatingStars } from './RatingStars';
 import { Tooltip } from './Tooltip';
+import { formatBookmarkPosition, formatBookmarkTime } from '../utils/bookmarks';
 import { useAddToPlaylist } from './PlaylistSidebar';
+import { libraryPath } from '../utils/libraryPath';
 
 interface Playlist {
   id: string;
@@ -37,31 +40,36 @@ interface LibraryTableProps {
 interface ParsedSearch {
   artist: string | null;
   album: string | null;
+  tag: string | null;
   text: string;
 }
 
 /**
- * Parse a search query that may contain field filters like artist:"Beatles" or album:"Abbey Road"
+ * Parse a search query that may contain field filters like artist:"Beatles", album:"Abbey Road"
+ * or tag:"workout"
  * Returns the extracted field values and any remaining text
  */
 function parseSearchQuery(query: string): ParsedSearch {
   let artist: string | null = null;
   let album: string | null = null;
+  let tag: string | null = null;
   const textParts: string[] = [];
 
   // Regex to match field:"value" (with escaped quotes) or unquoted words
-  const regex = /(artist|album):"((?:[^"\\]|\\.)*)"|(\S+)/gi;
+  const regex = /(artist|album|tag):"((?:[^"\\]|\\.)*)"|(\S+)/gi;
   let match;
 
   while ((match = regex.exec(query)) !== null) {
     if (match[1] && match[2] !== undefined) {
-      // Field filter: artist:"value" or album:"value"
+      // Field filter: artist:"value", album:"value" or tag:"value"
       const field = match[1].toLowerCase();
       const value = match[2].replace(/\\"/g, '"');
       if (field === 'artist') {
         artist = value;
       } else if (field === 'album') {
         album = value;
+      } else if (field === 'tag') {
+        tag = value;
       }
     } else if (match[3]) {
       // Regular word
@@ -69,19 +77,18 @@ function parseSearchQuery(query: string): ParsedSearch {
     }
   }
 
-  return { artist, album, text: textParts.join(' ') };
+  return { artist, album, tag, text: textParts.join(' ') };
 }
 
 function formatBookmarks(bookmarks: Record<string, Bookmark>): React.ReactNode {
   return Object.entries(bookmarks).map(([id, bookmark]) => {
-    const minutes = Math.floor(bookmark.position / 60);
-    const seconds = Math.floor(bookmark.position % 60);
-    const timeString = `${minutes}:${seconds.toString().padStart(2, '0')}`;
+    const timeString = formatBookmarkTime(bookmark);
     return (
       <span
         key={id}
         className="bookmark-emoji cursor-pointer hover:underline decoration-solarized-blue decoration-2 rounded"
         data-position={bookmark.position}
+        data-end-position={bookmark.end_position ?? undefined}
         title={bookmark.label ? `${bookmark.label} · ${timeString}` : timeString}
       >
         {bookmark.emoji || '\u{1F516}'}
@@ -132,7 +139,7 @@ export function LibraryTable({ items, searchQuery, playlistId, onSearchChange }:
   const { data: playlists = [] } = useQuery<Playlist[]>({
     queryKey: ['playlists'],
     queryFn: async () => {
-      const response = await fetch('/api/playlists');
+      const response = await fetch(libraryPath('/api/playlists'));
       if (!response.ok) throw new Error('Failed to fetch playlists');
       return response.json();
     },
@@ -163,7 +170,7 @@ export function LibraryTable({ items, searchQuery, playlistId, onSearchChange }:
 
     // Then filter by search query (supports field filters like artist:"Beatles")
     if (searchQuery) {
-      const { artist, album, text } = parseSearchQuery(searchQuery);
+      const { artist, album, tag, text } = parseSearchQuery(searchQuery);
       result = result.filter(item => {
         // Field-specific filters (case-insensitive contains match)
         if (artist && !item.artist.toLowerCase().includes(artist.toLowerCase())) {
@@ -172,12 +179,17 @@ export function LibraryTable({ items, searchQuery, playlistId, onSearchChange }:
         if (album && !item.album.toLowerCase().includes(album.toLowerCase())) {
           return false;
         }
+        // Tags match exactly (apart from case), so "run" doesn't find "running"
+        if (tag && !item.tags.some(t => t.toLowerCase() === tag.toLowerCase())) {
+          return false;
+        }
         // General text search across all fields
         if (text) {
           const query = text.toLowerCase();
           return item.name.toLowerCase().includes(query) ||
                  item.artist.toLowerCase().includes(query) ||
-                 item.album.toLowerCase().includes(query);
+                 item.album.toLowerCase().includes(query) ||
+                 item.tags.some(t => t.toLowerCase().includes(query));
         }
         return true;
       });
@@ -218,6 +230,38 @@ export function LibraryTable({ items, searchQuery, playlistId, onSearchChange }:
       cell: (info) => info.getValue() ?? '',
       size: 40,
     }),
+    columnHelper.accessor('duration', {
+      header: 'Time',
+      cell: (info) => {
+        const duration = info.getValue();
+        return duration == null ? '' : formatBookmarkPosition(duration);
+      },
+      size: 60,
+    }),
+    columnHelper.accessor('year', {
+      header: 'Year',
+      cell: (info) => info.getValue() ?? '',
+      size: 50,
+    }),
+    columnHelper.accessor('genre', {
+      header: 'Genre',
+      cell: (info) => <Tooltip content={info.getValue()}>{info.getValue()}</Tooltip>,
+      size: 100,
+    }),
+    columnHelper.accessor('tags', {
+      header: 'Tags',
+      cell: (info) => {
+        const tags = info.getValue().join(', ');
+        return <Tooltip content={tags}>{tags}</Tooltip>;
+      },
+      size: 120,
+      enableSorting: false,
+    }),
+    columnHelper.accessor('rating', {
+      header: 'Rating',
+      cell: (info) => <RatingStars itemId={info.row.original.id} rating={info.getValue()} />,
+      size: 90,
+    }),
     columnHelper.accessor('play_count', {
       header: 'Plays',
       cell: (info) => info.getValue(),
@@ -270,13 +314,18 @@ export function LibraryTable({ items, searchQuery, playlistId, onSearchChange }:
     void play(item);
   }, [play, editingCell, table, setContext, selectedPlaylist]);
 
-  const handleBookmarkClick = useCallback((item: LibraryItem, position: number, e: React.MouseEvent) => {
+  const handleBookmarkClick = useCallback((
+    item: LibraryItem,
+    position: number,
+    sectionEnd: number | null,
+    e: React.MouseEvent
+  ) => {
     e.stopPropagation();
-    void play(item, position);
+    void play(item, position, sectionEnd);
   }, [play]);
 
   const handleCellDoubleClick = useCallback((rowId: string, field: string, currentValue: string) => {
-    if (['name', 'artist', 'album'].includes(field)) {
+    if (['name', 'artist', 'album', 'genre'].includes(field)) {
       setEditingCell({ rowId, field });
       setEditValue(currentValue);
     }
@@ -428,7 +477,7 @@ export function LibraryTable({ items, searchQuery, playlistId, onSearchChange }:
                   {row.getVisibleCells().map((cell) => {
                     const field = cell.column.id;
                     const isEditing = editingCell?.rowId === row.id && editingCell?.field === field;
-                    const isEditable = ['name', 'artist', 'album'].includes(field);
+                    const isEditable = ['name', 'artist', 'album', 'genre'].includes(field);
 
                     return (
                       <td
@@ -445,7 +494,8 @@ export function LibraryTable({ items, searchQuery, playlistId, onSearchChange }:
                           const target = e.target as HTMLElement;
                           if (target.classList.contains('bookmark-emoji')) {
                             const position = parseFloat(target.getAttribute('data-position') || '0');
-                            handleBookmarkClick(row.original, position, e);
+                            const end = target.getAttribute('data-end-position');
+                            handleBookmarkClick(row.original, position, end ? parseFloat(end) : null, e);
                           }
                         }}
                       >
@@ -1,5 +1,6 @@
 import { useState, useCallback } from 'react';
 import { useQuery, useMutation, useQueryClient } from '@tanstack/react-query';
+import { libraryPath } from '../utils/libraryPath';
 
 interface Playlist {
   id: string;
@@ -9,13 +10,13 @@ interface Playlist {
 }
 
 async function fetchPlaylists(): Promise<Playlist[]> {
-  const response = await fetch('/api/playlists');
+  const response = await fetch(libraryPath('/api/playlists'));
   if (!response.ok) throw new Error('Failed to fetch playlists');
   return response.json();
 }
 
 async function createPlaylist(name: string): Promise<Playlist> {
-  const response = await fetch('/api/playlists', {
+  const response = await fetch(libraryPath('/api/playlists'), {
     method: 'POST',
     headers: { 'Content-Type': 'application/json' },
     body: JSON.stringify({ name }),
@@ -25,12 +26,12 @@ async function createPlaylist(name: string): Promise<Playlist> {
 }
 
 async function deletePlaylist(id: string): Promise<void> {
-  const response = await fetch(`/api/playlists/${id}`, { method: 'DELETE' });
+  const response = await fetch(libraryPath(`/api/playlists/${id}`), { method: 'DELETE' });
   if (!response.ok) throw new Error('Failed to delete playlist');
 }
 
 async function addToPlaylist(playlistId: string, libraryItemId: string): Promise<void> {
-  const response = await fetch(`/api/playlists/${playlistId}/items`, {
+  const response = await fetch(libraryPath(`/api/playlists/${playlistId}/items`), {
     method: 'POST',
     headers: { 'Content-Type': 'application/json' },
     body: JSON.stringify({ library_item_id: libraryItemId }),
@@ -0,0 +1,41 @@
+import { useCallback } from 'react';
+import { setRating } from '../hooks/useLibrary';
+
+const MAX_RATING = 5;
+
+interface RatingStarsProps {
+  itemId: string;
+  rating: number;
+}
+
+export function RatingStars({ itemId, rating }: RatingStarsProps) {
+  const handleClick = useCallback(async (e: React.MouseEvent, stars: number) => {
+    e.stopPropagation();
+    try {
+      // Clicking the current rating clears it
+      await setRating(itemId, stars === rating ? 0 : stars);
+    } catch (err) {
+      console.error('Failed to set rating:', err);
+      alert('Failed to set rating');
+    }
+  }, [itemId, rating]);
+
+  return (
+    <span className="whitespace-nowrap">
+      {Array.from({ length: MAX_RATING }, (_, i) => i + 1).map((stars) => (
+        <button
+          key={stars}
+          onClick={(e) => handleClick(e, stars)}
+          className={`transition-colors duration-200 ${
+            stars <= rating
+              ? 'text-solarized-yellow hover:text-solarized-orange'
+              : 'text-solarized-base01 hover:text-solarized-yellow'
+          }`}
+          title={stars === rating ? 'Clear rating' : `Rate ${stars} of ${MAX_RATING}`}
+        >
+          {stars <= rating ? '★' : '☆'}
+        </button>
+      ))}
+    </span>
+  );
+}
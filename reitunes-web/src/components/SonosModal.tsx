@@ -1,6 +1,7 @@
 import { useCallback, useEffect, useState } from 'react';
 import { usePlaybackTargetStore } from '../stores/playbackTargetStore';
 import { usePlayerStore } from '../stores/playerStore';
+import { libraryPath } from '../utils/libraryPath';
 
 interface SonosStatus {
   configured: boolean;
@@ -73,7 +74,7 @@ export function SonosModal({ isOpen, onClose }: SonosModalProps) {
     setIsLoading(true);
     setError(null);
     try {
-      const nextStatus = await fetchJson<SonosStatus>('/api/sonos/status');
+      const nextStatus = await fetchJson<SonosStatus>(libraryPath('/api/sonos/status'));
       setStatus(nextStatus);
       if (!nextStatus.connected) {
         setHouseholds([]);
@@ -81,13 +82,13 @@ export function SonosModal({ isOpen, onClose }: SonosModalProps) {
       }
 
       const householdResponse = await fetchJson<{ households: SonosHousehold[] }>(
-        '/api/sonos/households'
+        libraryPath('/api/sonos/households')
       );
       const discovered = await Promise.all(
         householdResponse.households.map(async (household) => ({
           household,
           discovery: await fetchJson<GroupsResponse>(
-            `/api/sonos/households/${encodeURIComponent(household.id)}/groups`
+            libraryPath(`/api/sonos/households/${encodeURIComponent(household.id)}/groups`)
           ),
         }))
       );
@@ -107,7 +108,7 @@ export function SonosModal({ isOpen, onClose }: SonosModalProps) {
     setIsLoading(true);
     setError(null);
     try {
-      const response = await fetch('/api/sonos/connection', {
+      const response = await fetch(libraryPath('/api/sonos/connection'), {
         method: 'DELETE',
         credentials: 'include',
       });
@@ -236,7 +237,7 @@ export function SonosModal({ isOpen, onClose }: SonosModalProps) {
                 Connect the Sonos household that ReiTunes should be allowed to discover.
               </p>
               <a
-                href="/api/sonos/authorize"
+                href={libraryPath('/api/sonos/authorize')}
                 className="inline-block px-4 py-2 bg-solarized-blue text-solarized-base03 rounded hover:bg-solarized-cyan transition-colors"
               >
                 Connect Sonos
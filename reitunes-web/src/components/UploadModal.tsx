@@ -1,5 +1,6 @@
 import { useState, useCallback, useRef } from 'react';
 import { useQueryClient } from '@tanstack/react-query';
+import { libraryPath } from '../utils/libraryPath';
 
 interface UploadResponse {
   id: string;
@@ -41,7 +42,7 @@ export function UploadModal({ isOpen, onClose }: UploadModalProps) {
     formData.append('file', file);
 
     try {
-      const response = await fetch('/api/upload', {
+      const response = await fetch(libraryPath('/api/upload'), {
         method: 'POST',
         body: formData,
       });
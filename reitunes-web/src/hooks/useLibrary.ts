@@ -1,6 +1,7 @@
 import { useQuery, useQueryClient } from '@tanstack/react-query';
 import { useEffect, useRef } from 'react';
-import type { LibraryItem, RealtimeUpdate } from '../types';
+import type { LibraryItem, PlaySource, RealtimeUpdate } from '../types';
+import { libraryPath } from '../utils/libraryPath';
 
 export const SONOS_REALTIME_EVENT = 'reitunes:sonos';
 
@@ -13,7 +14,7 @@ export function getItemUrl(item: LibraryItem): string {
 async function fetchLibraryItems(): Promise<LibraryItem[]> {
   // The backend serves items embedded in the HTML, but we'll use the API
   // For now, we'll parse items from the initial HTML data
-  const response = await fetch('/api/items');
+  const response = await fetch(libraryPath('/api/items'));
   if (!response.ok) {
     throw new Error('Failed to fetch library items');
   }
@@ -34,7 +35,7 @@ export function useLibrary() {
   useEffect(() => {
     let mounted = true;
     const protocol = window.location.protocol === 'https:' ? 'wss:' : 'ws:';
-    const wsUrl = `${protocol}//${window.location.host}/updates`;
+    const wsUrl = `${protocol}//${window.location.host}${libraryPath('/updates')}`;
 
     const connect = () => {
       if (!mounted) return;
@@ -108,7 +109,7 @@ export async function updateLibraryItem(
   field: string,
   value: string
 ): Promise<void> {
-  const response = await fetch('/ui/update', {
+  const response = await fetch(libraryPath('/ui/update'), {
     method: 'POST',
     headers: { 'Content-Type': 'application/json' },
     body: JSON.stringify({ id, field, value }),
@@ -119,11 +120,11 @@ export async function updateLibraryItem(
   }
 }
 
-export async function markPlayed(id: string): Promise<void> {
-  const response = await fetch('/ui/play', {
+export async function markPlayed(id: string, source: PlaySource = 'Browser'): Promise<void> {
+  const response = await fetch(libraryPath('/ui/play'), {
     method: 'POST',
     headers: { 'Content-Type': 'application/json' },
-    body: JSON.stringify({ id }),
+    body: JSON.stringify({ id, source }),
     credentials: 'include',
   });
   if (!response.ok) {
@@ -131,8 +132,22 @@ export async function markPlayed(id: string): Promise<void> {
   }
 }
 
+// Tell the server how far into an item playback has got. It only records positions that
+// have moved far enough, so this can be called as often as is convenient.
+export async function saveResumePosition(id: string, position: number): Promise<void> {
+  const response = await fetch(libraryPath(`/ui/${id}/position`), {
+    method: 'POST',
+    headers: { 'Content-Type': 'application/json' },
+    body: JSON.stringify({ position }),
+    credentials: 'include',
+  });
+  if (!response.ok) {
+    throw new Error('Failed to save resume position');
+  }
+}
+
 export async function deleteItem(id: string): Promise<void> {
-  const response = await fetch('/ui/delete', {
+  const response = await fetch(libraryPath('/ui/delete'), {
     method: 'POST',
     headers: { 'Content-Type': 'application/json' },
     body: JSON.stringify({ id }),
@@ -143,11 +158,15 @@ export async function deleteItem(id: string): Promise<void> {
   }
 }
 
-export async function addBookmark(id: string, position: number): Promise<void> {
-  const response = await fetch(`/ui/${id}/bookmarks`, {
+export async function addBookmark(
+  id: string,
+  position: number,
+  endPosition: number | null = null
+): Promise<void> {
+  const response = await fetch(libraryPath(`/ui/${id}/bookmarks`), {
     method: 'POST',
     headers: { 'Content-Type': 'application/json' },
-    body: JSON.stringify({ position }),
+    body: JSON.stringify({ position, end_position: endPosition }),
     credentials: 'include',
   });
   if (!response.ok) {
@@ -161,7 +180,7 @@ export async function updateBookmark(
   label: string,
   emoji: string
 ): Promise<void> {
-  const response = await fetch(`/ui/${itemId}/bookmarks/${bookmarkId}`, {
+  const response = await fetch(libraryPath(`/ui/${itemId}/bookmarks/${bookmarkId}`), {
     method: 'PUT',
     headers: { 'Content-Type': 'application/json' },
     body: JSON.stringify({ label: label.trim() || null, emoji }),
@@ -172,8 +191,26 @@ export async function updateBookmark(
   }
 }
 
+// Move a bookmark, keeping its emoji and label. A null end turns a range into a plain bookmark.
+export async function moveBookmark(
+  itemId: string,
+  bookmarkId: string,
+  position: number,
+  endPosition: number | null
+): Promise<void> {
+  const response = await fetch(libraryPath(`/ui/${itemId}/bookmarks/${bookmarkId}/position`), {
+    method: 'PUT',
+    headers: { 'Content-Type': 'application/json' },
+    body: JSON.stringify({ position, end_position: endPosition }),
+    credentials: 'include',
+  });
+  if (!response.ok) {
+    throw new Error('Failed to move bookmark');
+  }
+}
+
 export async function deleteBookmark(itemId: string, bookmarkId: string): Promise<void> {
-  const response = await fetch(`/ui/${itemId}/bookmarks/${bookmarkId}`, {
+  const response = await fetch(libraryPath(`/ui/${itemId}/bookmarks/${bookmarkId}`), {
     method: 'DELETE',
     credentials: 'include',
   });
@@ -183,7 +220,7 @@ export async function deleteBookmark(itemId: string, bookmarkId: string): Promis
 }
 
 export async function toggleFavorite(id: string, isFavorite: boolean): Promise<void> {
-  const endpoint = isFavorite ? `/ui/${id}/unfavorite` : `/ui/${id}/favorite`;
+  const endpoint = isFavorite ? libraryPath(`/ui/${id}/unfavorite`) : libraryPath(`/ui/${id}/favorite`);
   const response = await fetch(endpoint, {
     method: 'POST',
     headers: { 'Content-Type': 'application/json' },
@@ -193,3 +230,15 @@ export async function toggleFavorite(id: string, isFavorite: boolean): Promise<v
     throw new Error('Failed to toggle favorite');
   }
 }
+
+export async function setRating(id: string, rating: number): Promise<void> {
+  const response = await fetch(libraryPath(`/ui/${id}/rating`), {
+    method: 'POST',
+    headers: { 'Content-Type': 'application/json' },
+    body: JSON.stringify({ rating }),
+    credentials: 'include',
+  });
+  if (!response.ok) {
+    throw new Error('Failed to set rating');
+  }
+}
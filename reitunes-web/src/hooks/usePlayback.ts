@@ -4,6 +4,7 @@ import { useQueueStore } from './useQueue';
 import { markPlayed } from './useLibrary';
 import { usePlayerStore } from '../stores/playerStore';
 import { usePlaybackTargetStore } from '../stores/playbackTargetStore';
+import { libraryPath } from '../utils/libraryPath';
 
 function sonosQueueFor(item: LibraryItem): LibraryItem[] {
   const queue = useQueueStore.getState();
@@ -22,11 +23,17 @@ async function responseError(response: Response): Promise<string> {
 }
 
 export function usePlayback() {
-  return useCallback(async (item: LibraryItem, startPosition = 0): Promise<void> => {
+  // Items start where they were last left off, on whichever player that was. Sonos can't
+  // be told to stop partway through, so sections only end early in the browser.
+  return useCallback(async (
+    item: LibraryItem,
+    startPosition = item.resume_position ?? 0,
+    sectionEnd: number | null = null,
+  ): Promise<void> => {
     const targetState = usePlaybackTargetStore.getState();
     if (targetState.target.kind === 'browser') {
       targetState.clearError();
-      usePlayerStore.getState().play(item, startPosition);
+      usePlayerStore.getState().play(item, startPosition, sectionEnd);
       return;
     }
 
@@ -38,7 +45,7 @@ export function usePlayback() {
     targetState.beginSending();
 
     try {
-      const response = await fetch('/api/sonos/play', {
+      const response = await fetch(libraryPath('/api/sonos/play'), {
         method: 'POST',
         credentials: 'include',
         headers: { 'Content-Type': 'application/json' },
@@ -57,7 +64,7 @@ export function usePlayback() {
       }
 
       usePlaybackTargetStore.getState().finishSending();
-      void markPlayed(item.id).catch((error) => {
+      void markPlayed(item.id, 'Sonos').catch((error) => {
         console.error('Sonos playback started, but the play count could not be updated:', error);
       });
     } catch (error) {
@@ -2,6 +2,7 @@ import { useCallback, useEffect, useRef, useState } from 'react';
 import { SONOS_REALTIME_EVENT } from './useLibrary';
 import { usePlaybackTargetStore } from '../stores/playbackTargetStore';
 import type { SonosRealtimeUpdate } from '../types';
+import { libraryPath } from '../utils/libraryPath';
 
 const PLAYBACK_POLL_MILLIS = 30_000;
 const VOLUME_POLL_MILLIS = 60_000;
@@ -68,7 +69,7 @@ export function useSonosControls(groupId: string | null) {
     if (!groupId) return;
     const requestedGroup = groupId;
     const response = await fetch(
-      `/api/sonos/groups/${encodeURIComponent(requestedGroup)}/playback`,
+      libraryPath(`/api/sonos/groups/${encodeURIComponent(requestedGroup)}/playback`),
       { credentials: 'include' }
     );
     if (!response.ok) throw new Error(await responseError(response));
@@ -80,7 +81,7 @@ export function useSonosControls(groupId: string | null) {
     if (!groupId) return;
     const requestedGroup = groupId;
     const response = await fetch(
-      `/api/sonos/groups/${encodeURIComponent(requestedGroup)}/volume`,
+      libraryPath(`/api/sonos/groups/${encodeURIComponent(requestedGroup)}/volume`),
       { credentials: 'include' }
     );
     if (!response.ok) throw new Error(await responseError(response));
@@ -180,7 +181,7 @@ export function useSonosControls(groupId: string | null) {
 
       try {
         const response = await fetch(
-          `/api/sonos/groups/${encodeURIComponent(groupId)}/playback/${command}`,
+          libraryPath(`/api/sonos/groups/${encodeURIComponent(groupId)}/playback/${command}`),
           { method: 'POST', credentials: 'include' }
         );
         if (!response.ok) {
@@ -211,7 +212,7 @@ export function useSonosControls(groupId: string | null) {
       );
       try {
         const response = await fetch(
-          `/api/sonos/groups/${encodeURIComponent(groupId)}/volume`,
+          libraryPath(`/api/sonos/groups/${encodeURIComponent(groupId)}/volume`),
           {
             method: 'POST',
             credentials: 'include',
@@ -239,7 +240,7 @@ export function useSonosControls(groupId: string | null) {
       setVolumeState((current) => (current ? { ...current, muted } : current));
       try {
         const response = await fetch(
-          `/api/sonos/groups/${encodeURIComponent(groupId)}/mute`,
+          libraryPath(`/api/sonos/groups/${encodeURIComponent(groupId)}/mute`),
           {
             method: 'POST',
             credentials: 'include',
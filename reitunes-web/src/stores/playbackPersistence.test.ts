@@ -27,7 +27,14 @@ function item(id: string, name = id): LibraryItem {
     artist: '',
     album: '',
     track_number: null,
+    duration: null,
+    year: null,
+    genre: '',
     play_count: 0,
+    last_played_utc: null,
+    resume_position: null,
+    rating: 0,
+    tags: [],
     bookmarks: {},
     url: `https://example.com/${id}.mp3`,
   };
@@ -41,6 +48,7 @@ describe('playback persistence', () => {
       currentItemId: null,
       isPlaying: false,
       pendingSeek: null,
+      sectionEnd: null,
       resumePosition: 0,
       volume: 1,
       isMuted: false,
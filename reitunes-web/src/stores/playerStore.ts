@@ -15,13 +15,16 @@ interface PlayerState extends PersistedPlayerState {
   currentItem: LibraryItem | null;
   isPlaying: boolean;
   pendingSeek: number | null;
+  // Where to pause, when playing just the section a range bookmark marks
+  sectionEnd: number | null;
 
   setIsPlaying: (playing: boolean) => void;
   clearPendingSeek: () => void;
+  clearSectionEnd: () => void;
   setResumePosition: (position: number) => void;
   setVolume: (volume: number) => void;
   setMuted: (muted: boolean) => void;
-  play: (item: LibraryItem, startPosition?: number) => void;
+  play: (item: LibraryItem, startPosition?: number, sectionEnd?: number | null) => void;
   selectRemoteItem: (item: LibraryItem, startPosition?: number) => void;
   restoreCurrentItem: (item: LibraryItem) => void;
   refreshCurrentItem: (item: LibraryItem) => void;
@@ -40,23 +43,26 @@ export const usePlayerStore = create<PlayerState>()(
       currentItemId: null,
       isPlaying: false,
       pendingSeek: null,
+      sectionEnd: null,
       resumePosition: 0,
       volume: 1,
       isMuted: false,
 
       setIsPlaying: (playing) => set({ isPlaying: playing }),
       clearPendingSeek: () => set({ pendingSeek: null }),
+      clearSectionEnd: () => set({ sectionEnd: null }),
       setResumePosition: (position) => set({ resumePosition: normalizePosition(position) }),
       setVolume: (volume) => set({ volume: Math.min(1, Math.max(0, volume)) }),
       setMuted: (muted) => set({ isMuted: muted }),
 
-      play: (item, startPosition = 0) => {
+      play: (item, startPosition = 0, sectionEnd = null) => {
         const position = normalizePosition(startPosition);
         set({
           currentItem: item,
           currentItemId: item.id,
           isPlaying: true,
           pendingSeek: position,
+          sectionEnd,
           resumePosition: position,
         });
       },
@@ -68,6 +74,7 @@ export const usePlayerStore = create<PlayerState>()(
           currentItemId: item.id,
           isPlaying: false,
           pendingSeek: null,
+          sectionEnd: null,
           resumePosition: position,
         });
       },
@@ -89,6 +96,7 @@ export const usePlayerStore = create<PlayerState>()(
         currentItemId: null,
         isPlaying: false,
         pendingSeek: null,
+        sectionEnd: null,
         resumePosition: 0,
       }),
 
@@ -1,6 +1,7 @@
 // Library item matching the Rust backend structure
 export interface Bookmark {
   position: number; // in seconds
+  end_position: number | null; // in seconds; set when the bookmark marks a section
   emoji: string;
   label: string | null;
   created_time_utc: string;
@@ -14,12 +15,40 @@ export interface LibraryItem {
   artist: string;
   album: string;
   track_number: number | null;
+  duration: number | null;  // in seconds
+  year: number | null;
+  genre: string;
   play_count: number;
+  last_played_utc: string | null;
+  resume_position: number | null;  // in seconds; where playback left off
+  rating: number;  // 0 (unrated) to 5 stars
+  tags: string[];
   bookmarks: Record<string, Bookmark>;
   is_favorite?: boolean;
   url: string;  // Full URL provided by backend
 }
 
+export type PlaySource = 'Browser' | 'Sonos' | 'Tui' | 'Other';
+
+// One play, from /api/items/{id}/plays or /api/history
+export interface Play {
+  id: string;
+  item_id: string;
+  played_time_utc: string;
+  machine_name: string;
+  source: PlaySource | null;
+  seconds_listened: number | null;
+  completed: boolean | null;
+  name: string | null;  // null once the item is gone
+  artist: string | null;
+}
+
+// From /api/tags
+export interface Tag {
+  name: string;
+  count: number;
+}
+
 // WebSocket update messages
 export type LibraryUpdate =
   | { type: 'update'; item: LibraryItem }
@@ -4,6 +4,8 @@ import {
   bookmarkEntries,
   filterBookmarkEntries,
   formatBookmarkPosition,
+  formatBookmarkTime,
+  parseBookmarkPosition,
 } from './bookmarks';
 
 function item(
@@ -22,7 +24,14 @@ function item(
     created_time_utc: '2026-01-01T00:00:00',
     file_path: `${id}.mp3`,
     track_number: null,
+    duration: null,
+    year: null,
+    genre: '',
     play_count: 0,
+    last_played_utc: null,
+    resume_position: null,
+    rating: 0,
+    tags: [],
     is_favorite: false,
     url: `/${id}.mp3`,
   };
@@ -33,6 +42,7 @@ describe('bookmark entries', () => {
     item('one', 'Northern Sky', 'Nick Drake', 'Bryter Layter', {
       older: {
         position: 70,
+        end_position: null,
         emoji: '🎸',
         label: 'Guitar entrance',
         created_time_utc: '2026-01-01T00:00:00',
@@ -41,6 +51,7 @@ describe('bookmark entries', () => {
     item('two', 'River Man', 'Nick Drake', 'Five Leaves Left', {
       newer: {
         position: 3723,
+        end_position: 3900,
         emoji: '🎻',
         label: null,
         created_time_utc: '2026-02-01T00:00:00',
@@ -67,4 +78,13 @@ describe('bookmark entries', () => {
     expect(formatBookmarkPosition(70.9)).toBe('1:10');
     expect(formatBookmarkPosition(3723)).toBe('1:02:03');
   });
+
+  it('formats ranges and parses typed positions', () => {
+    expect(formatBookmarkTime(items[1].bookmarks.newer)).toBe('1:02:03–1:05:00');
+    expect(formatBookmarkTime(items[0].bookmarks.older)).toBe('1:10');
+    expect(parseBookmarkPosition('1:02:03')).toBe(3723);
+    expect(parseBookmarkPosition(' 90.5 ')).toBe(90.5);
+    expect(parseBookmarkPosition('1:xx')).toBeNull();
+    expect(parseBookmarkPosition('')).toBeNull();
+  });
 });
@@ -38,6 +38,20 @@ export function filterBookmarkEntries(
   );
 }
 
+// Parse a position typed as seconds, m:ss or h:mm:ss
+export function parseBookmarkPosition(value: string): number | null {
+  const parts = value.trim().split(':');
+  if (parts.length > 3 || parts.some((part) => !/^\d+(\.\d+)?$/.test(part))) return null;
+  return parts.reduce((total, part) => total * 60 + Number(part), 0);
+}
+
+export function formatBookmarkTime(bookmark: Bookmark): string {
+  const start = formatBookmarkPosition(bookmark.position);
+  return bookmark.end_position == null
+    ? start
+    : `${start}–${formatBookmarkPosition(bookmark.end_position)}`;
+}
+
 export function formatBookmarkPosition(position: number): string {
   const totalSeconds = Math.max(0, Math.floor(position));
   const hours = Math.floor(totalSeconds / 3600);
@@ -0,0 +1,9 @@
+/**
+ * Libraries other than the default one are served under /l/<name>, and every request the UI
+ * makes has to stay in the library the page was loaded from.
+ */
+const LIBRARY_BASE = window.location.pathname.match(/^\/l\/[^/]+/)?.[0] ?? '';
+
+export function libraryPath(path: string): string {
+  return `${LIBRARY_BASE}${path}`;
+}
@@ -3,6 +3,8 @@
  * This allows viewing frontend logs alongside backend logs with `just logs`.
  */
 
+import { libraryPath } from './libraryPath';
+
 type LogLevel = 'log' | 'info' | 'warn' | 'error' | 'debug';
 
 const originalConsole = {
@@ -35,7 +37,7 @@ function sendToBackend(level: LogLevel, args: unknown[]) {
   if (message.includes('[vite]') || message.includes('[HMR]')) return;
 
   // Send asynchronously, don't wait for response
-  fetch('/api/log', {
+  fetch(libraryPath('/api/log'), {
     method: 'POST',
     headers: { 'Content-Type': 'application/json' },
     body: JSON.stringify({
@@ -35,6 +35,12 @@ export default defineConfig({
         target: 'ws://localhost:5000',
         ws: true,
       },
+      // libraries other than the default one
+      '/l': {
+        target: 'http://localhost:5000',
+        changeOrigin: true,
+        ws: true,
+      },
       '/music': {
         target: 'http://localhost:5000',
         changeOrigin: true,
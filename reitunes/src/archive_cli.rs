@@ -0,0 +1,83 @@
+//! `reitunes export` and `reitunes import`: move the whole library in and out of a portable
+//! archive (see `reitunes_workspace::archive` for the format)
+
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use reitunes_workspace::{import_archive, open_connection, ArchiveWriter};
+use tracing::warn;
+
+use crate::config::{Config, LibrarySettings};
+use crate::storage::S3Storage;
+
+pub async fn export(
+    config: &Config,
+    library: &LibrarySettings,
+    out: &Path,
+    with_media: bool,
+) -> Result<()> {
+    let conn = open_connection(&library.db_path)?;
+    let storage = if with_media {
+        Some(S3Storage::from_config(&config.storage, library.prefix.as_deref()).await?)
+    } else {
+        None
+    };
+
+    let file = File::create(out).with_context(|| format!("Failed to create {}", out.display()))?;
+    let mut writer = ArchiveWriter::new(BufWriter::new(file))?;
+    let items = writer.write_library(&conn)?;
+    if let Some(storage) = storage {
+        for (i, item) in items.iter().enumerate() {
+            eprintln!("[{}/{}] {}", i + 1, items.len(), item.file_path);
+            match storage.download(&item.file_path).await {
+                Ok(data) => writer.add_media(&item.file_path, &data)?,
+                Err(e) => {
+                    warn!(file_path = item.file_path, error = ?e, "Failed to export media");
+                    writer.add_missing_media(&item.file_path);
+                }
+            }
+        }
+    }
+    let manifest = writer.finish()?;
+
+    eprintln!(
+        "Exported {} events, {} items, {} playlists and {} media files to {}",
+        manifest.event_count,
+        manifest.item_count,
+        manifest.playlist_count,
+        manifest.media_count,
+        out.display()
+    );
+    if !manifest.missing_media.is_empty() {
+        eprintln!(
+            "{} media files couldn't be exported; they're listed in manifest.json",
+            manifest.missing_media.len()
+        );
+    }
+    Ok(())
+}
+
+pub fn import(db_path: &str, archive: &Path) -> Result<()> {
+    let conn = open_connection(db_path)?;
+    let file =
+        File::open(archive).with_context(|| format!("Failed to open {}", archive.display()))?;
+    let manifest = import_archive(&conn, BufReader::new(file))?;
+
+    eprintln!(
+        "Imported {} events, {} items and {} playlists exported from {} at {}",
+        manifest.event_count,
+        manifest.item_count,
+        manifest.playlist_count,
+        manifest.machine_name,
+        manifest.exported_at
+    );
+    if manifest.media_count > 0 {
+        eprintln!(
+            "The archive's {} media files were verified but not uploaded; extract media/ to restore them",
+            manifest.media_count
+        );
+    }
+    Ok(())
+}
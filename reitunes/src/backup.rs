@@ -0,0 +1,394 @@
+//! Scheduled online backups of the database (events, Sonos tokens, cloud queues: everything).
+//!
+//! Backups are taken with SQLite's online backup API, so the server keeps running while they
+//! happen. The newest few generations are kept in a local directory and, optionally, in the
+//! S3 bucket under `backups/`.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use anyhow::{ensure, Context, Result};
+use clap::Subcommand;
+use jiff::Timestamp;
+use r2d2::Pool;
+use r2d2_sqlite::SqliteConnectionManager;
+use reitunes_workspace::{load_library_from_db, migrate, open_connection, SCHEMA_VERSION};
+use rusqlite::backup::Backup;
+use rusqlite::{Connection, OpenFlags};
+use tracing::{info, warn};
+
+use crate::config::{BackupSettings, Config, LibrarySettings};
+use crate::storage::S3Storage;
+
+/// Scheduled backups are named `reitunes-library-<UTC time>.db`, so sorting by name sorts by age
+const BACKUP_FILE_PREFIX: &str = "reitunes-library-";
+/// The copy of the database taken just before a restore. These aren't rotated.
+const PRE_RESTORE_FILE_PREFIX: &str = "pre-restore-";
+
+/// Copy this many pages at a time, pausing in between so writers aren't held up
+const PAGES_PER_STEP: i32 = 256;
+const PAUSE_BETWEEN_STEPS: Duration = Duration::from_millis(10);
+
+#[derive(Subcommand)]
+pub enum BackupCommand {
+    /// Back up the database now (and rotate old local backups)
+    Create,
+    /// Replace the database with a backup. Stop the server first.
+    Restore {
+        /// The backup file to restore
+        file: PathBuf,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct BackupConfig {
+    pub dir: PathBuf,
+    pub interval: Duration,
+    /// How many backups to keep (locally and in S3)
+    pub generations: usize,
+    /// Also upload backups to S3
+    pub upload: bool,
+}
+
+impl BackupConfig {
+    /// Backups are on by default; set `backup.interval_hours = 0` to turn them off
+    pub fn from_settings(settings: &BackupSettings) -> Result<Option<Self>> {
+        if settings.interval_hours == 0 {
+            return Ok(None);
+        }
+        ensure!(
+            settings.generations > 0,
+            "backup.generations (or REITUNES_BACKUP_GENERATIONS) must be at least 1"
+        );
+
+        Ok(Some(BackupConfig {
+            dir: settings.dir.clone(),
+            interval: Duration::from_secs(settings.interval_hours * 60 * 60),
+            generations: settings.generations,
+            upload: settings.upload,
+        }))
+    }
+}
+
+/// The default library's backups go straight in `backup.dir`; other libraries get a
+/// subdirectory each
+pub fn library_backup_dir(config: &Config, library: &LibrarySettings) -> PathBuf {
+    if library.name == config.server.library {
+        config.backup.dir.clone()
+    } else {
+        config.backup.dir.join(&library.name)
+    }
+}
+
+pub fn run(config: &Config, library: &LibrarySettings, command: BackupCommand) -> Result<()> {
+    let db_path = library.db_path.as_str();
+    let dir = &library_backup_dir(config, library);
+    match command {
+        BackupCommand::Create => {
+            let conn = open_connection(db_path)?;
+            let path = backup_database(&conn, dir)?;
+            println!("Backed up to {}", path.display());
+            for removed in rotate_backups(dir, config.backup.generations.max(1))? {
+                println!("Removed old backup {}", removed.display());
+            }
+        }
+        BackupCommand::Restore { file } => {
+            let safety_copy = restore_database(&file, db_path, dir)?;
+            if let Some(safety_copy) = safety_copy {
+                println!(
+                    "Saved the database as it was before the restore to {}",
+                    safety_copy.display()
+                );
+            }
+            let library = load_library_from_db(&open_connection(db_path)?)?;
+            println!(
+                "Restored {} from {} ({} library items)",
+                db_path,
+                file.display(),
+                library.items.len()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Back up the database `conn` is connected to into a new file in `dir`, returning its path
+pub fn backup_database(conn: &Connection, dir: &Path) -> Result<PathBuf> {
+    fs::create_dir_all(dir)
+        .with_context(|| format!("Failed to create backup directory {}", dir.display()))?;
+    let path = dir.join(format!("{BACKUP_FILE_PREFIX}{}.db", file_timestamp()));
+    copy_to_file(conn, &path)?;
+    info!(path = %path.display(), "Backed up database");
+    Ok(path)
+}
+
+/// Replace the database at `db_path` with the backup at `backup_path`, after checking that
+/// the backup is intact. The database as it was is saved in `dir` first (if there was one),
+/// and its path returned.
+pub fn restore_database(backup_path: &Path, db_path: &str, dir: &Path) -> Result<Option<PathBuf>> {
+    let backup = Connection::open_with_flags(backup_path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .with_context(|| format!("Failed to open {}", backup_path.display()))?;
+    let integrity: String = backup.query_row("PRAGMA integrity_check", [], |row| row.get(0))?;
+    ensure!(
+        integrity == "ok",
+        "{} is damaged: {integrity}",
+        backup_path.display()
+    );
+    backup
+        .query_row("SELECT COUNT(*) FROM events", [], |row| {
+            row.get::<_, i64>(0)
+        })
+        .with_context(|| format!("{} isn't a reitunes database", backup_path.display()))?;
+    // older backups are migrated once restored, but there's no going back from a newer one,
+    // so refuse before the live database is touched
+    let version: i64 = backup.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    ensure!(
+        version <= SCHEMA_VERSION,
+        "{} is from a newer version of reitunes (schema version {version}; this build \
+         supports up to {SCHEMA_VERSION})",
+        backup_path.display()
+    );
+
+    let safety_copy = if Path::new(db_path).exists() {
+        fs::create_dir_all(dir)?;
+        let path = dir.join(format!("{PRE_RESTORE_FILE_PREFIX}{}.db", file_timestamp()));
+        copy_to_file(&Connection::open(db_path)?, &path)?;
+        Some(path)
+    } else {
+        None
+    };
+
+    let mut live = Connection::open(db_path)?;
+    copy(&backup, &mut live)?;
+    // backups from older versions need bringing up to date
+    migrate(&live)?;
+    info!(from = %backup_path.display(), to = db_path, "Restored database");
+    Ok(safety_copy)
+}
+
+/// Delete all but the newest `generations` backups in `dir`, returning the deleted paths
+pub fn rotate_backups(dir: &Path, generations: usize) -> Result<Vec<PathBuf>> {
+    let mut file_names = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        file_names.push(entry?.file_name().to_string_lossy().into_owned());
+    }
+
+    let mut removed = Vec::new();
+    for file_name in expired_backups(file_names, generations) {
+        let path = dir.join(file_name);
+        fs::remove_file(&path)?;
+        removed.push(path);
+    }
+    Ok(removed)
+}
+
+/// The scheduled backups in `file_names` that are older than the newest `generations`
+fn expired_backups(file_names: Vec<String>, generations: usize) -> Vec<String> {
+    let mut backups: Vec<_> = file_names
+        .into_iter()
+        .filter(|name| is_scheduled_backup(name))
+        .collect();
+    backups.sort();
+    let expired = backups.len().saturating_sub(generations);
+    backups.truncate(expired);
+    backups
+}
+
+/// Back up on a schedule until the server stops. Failures are logged and retried at the next
+/// interval.
+pub fn spawn_scheduled_backups(
+    config: BackupConfig,
+    pool: Pool<SqliteConnectionManager>,
+    storage: Arc<S3Storage>,
+) {
+    info!(
+        dir = %config.dir.display(),
+        interval = ?config.interval,
+        generations = config.generations,
+        upload = config.upload,
+        "Scheduled backups are configured"
+    );
+    tokio::spawn(async move {
+        // pick up where the last run left off, so restarts don't reset the schedule
+        let mut delay = config
+            .interval
+            .saturating_sub(time_since_last_backup(&config.dir));
+        loop {
+            tokio::time::sleep(delay).await;
+            delay = config.interval;
+            if let Err(e) = scheduled_backup(&config, &pool, &storage).await {
+                warn!(error = ?e, "Scheduled backup failed");
+            }
+        }
+    });
+}
+
+async fn scheduled_backup(
+    config: &BackupConfig,
+    pool: &Pool<SqliteConnectionManager>,
+    storage: &S3Storage,
+) -> Result<()> {
+    let path = {
+        let (pool, config) = (pool.clone(), config.clone());
+        tokio::task::spawn_blocking(move || -> Result<PathBuf> {
+            let path = backup_database(&*pool.get()?, &config.dir)?;
+            for removed in rotate_backups(&config.dir, config.generations)? {
+                info!(path = %removed.display(), "Removed old backup");
+            }
+            Ok(path)
+        })
+        .await??
+    };
+
+    if config.upload {
+        let file_name = path
+            .file_name()
+            .context("Backup has no file name")?
+            .to_string_lossy()
+            .into_owned();
+        storage
+            .upload_backup(&file_name, tokio::fs::read(&path).await?)
+            .await?;
+        for expired in expired_backups(storage.list_backups().await?, config.generations) {
+            storage.delete_backup(&expired).await?;
+            info!(file_name = expired, "Removed old backup from S3");
+        }
+    }
+    Ok(())
+}
+
+fn is_scheduled_backup(file_name: &str) -> bool {
+    file_name.starts_with(BACKUP_FILE_PREFIX) && file_name.ends_with(".db")
+}
+
+fn time_since_last_backup(dir: &Path) -> Duration {
+    let newest = fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|entry| is_scheduled_backup(&entry.file_name().to_string_lossy()))
+        .filter_map(|entry| entry.metadata().ok()?.modified().ok())
+        .max();
+    newest.map_or(Duration::MAX, |modified| {
+        SystemTime::now()
+            .duration_since(modified)
+            .unwrap_or_default()
+    })
+}
+
+/// Copy the database `conn` is connected to into a new file at `path`. The copy is written
+/// next to `path` and renamed into place, so `path` never holds a partial backup.
+fn copy_to_file(conn: &Connection, path: &Path) -> Result<()> {
+    let partial = path.with_extension("db.partial");
+    let mut dest = Connection::open(&partial)?;
+    copy(conn, &mut dest)?;
+    drop(dest);
+    fs::rename(&partial, path)?;
+    Ok(())
+}
+
+fn copy(from: &Connection, to: &mut Connection) -> Result<()> {
+    Backup::new(from, to)?.run_to_completion(PAGES_PER_STEP, PAUSE_BETWEEN_STEPS, None)?;
+    Ok(())
+}
+
+/// e.g. 20240601T120000.123Z
+fn file_timestamp() -> String {
+    Timestamp::now().strftime("%Y%m%dT%H%M%S%.3fZ").to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::created;
+    use reitunes_workspace::{save_event_to_db, Event, EventWithMetadata, PlayDetails};
+    use uuid::Uuid;
+
+    fn create_item(conn: &Connection, name: &str) -> Result<Uuid> {
+        let id = Uuid::new_v4();
+        save_event_to_db(conn, &EventWithMetadata::new(id, created(name))?)?;
+        Ok(id)
+    }
+
+    #[test]
+    fn a_restored_backup_rebuilds_an_identical_library() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let db_path = temp_dir.path().join("reitunes-library.db");
+        let db_path = db_path.to_str().unwrap();
+        let backup_dir = temp_dir.path().join("backups");
+
+        let conn = open_connection(db_path)?;
+        let kept = create_item(&conn, "kept")?;
+        save_event_to_db(
+            &conn,
+            &EventWithMetadata::new(kept, Event::LibraryItemPlayedEvent(PlayDetails::default()))?,
+        )?;
+        let deleted = create_item(&conn, "deleted")?;
+        save_event_to_db(
+            &conn,
+            &EventWithMetadata::new(deleted, Event::LibraryItemDeletedEvent)?,
+        )?;
+        let backup = backup_database(&conn, &backup_dir)?;
+        let library = load_library_from_db(&conn)?;
+
+        // changes after the backup are undone by restoring it
+        create_item(&conn, "after the backup")?;
+        drop(conn);
+        let safety_copy = restore_database(&backup, db_path, &backup_dir)?.unwrap();
+
+        let restored = load_library_from_db(&open_connection(db_path)?)?;
+        assert_eq!(restored.items, library.items);
+        assert_eq!(restored.deleted_items, library.deleted_items);
+        let before_restore =
+            load_library_from_db(&open_connection(safety_copy.to_str().unwrap())?)?;
+        assert_eq!(before_restore.items.len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn backups_from_newer_versions_are_refused_without_touching_the_database() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let db_path = temp_dir.path().join("reitunes-library.db");
+        let db_path = db_path.to_str().unwrap();
+        let backup_dir = temp_dir.path().join("backups");
+
+        let conn = open_connection(db_path)?;
+        let kept = create_item(&conn, "kept")?;
+        let backup = backup_database(&conn, &backup_dir)?;
+        drop(conn);
+        Connection::open(&backup)?.pragma_update(None, "user_version", SCHEMA_VERSION + 1)?;
+
+        let error = restore_database(&backup, db_path, &backup_dir).unwrap_err();
+        assert!(error.to_string().contains("newer"), "{error:#}");
+        let conn = Connection::open(db_path)?;
+        let version: i64 = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        assert_eq!(version, SCHEMA_VERSION);
+        assert!(load_library_from_db(&conn)?.items.contains_key(&kept));
+        assert_eq!(fs::read_dir(&backup_dir)?.count(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn only_the_newest_scheduled_backups_are_kept() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let names = [
+            "reitunes-library-20240101T000000.000Z.db",
+            "reitunes-library-20240102T000000.000Z.db",
+            "reitunes-library-20240103T000000.000Z.db",
+            "pre-restore-20240101T000000.000Z.db",
+            "notes.txt",
+        ];
+        for name in names {
+            fs::write(temp_dir.path().join(name), "")?;
+        }
+
+        let removed = rotate_backups(temp_dir.path(), 2)?;
+        assert_eq!(removed, vec![temp_dir.path().join(names[0])]);
+        for name in &names[1..] {
+            assert!(temp_dir.path().join(name).exists(), "{name}");
+        }
+        Ok(())
+    }
+}
@@ -4,7 +4,7 @@ use std::{
     time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
-use anyhow::{bail, Context, Result};
+use anyhow::{Context, Result};
 use openssl::memcmp;
 use r2d2::Pool;
 use r2d2_sqlite::SqliteConnectionManager;
@@ -21,11 +21,14 @@ const MAX_WINDOW_ITEMS_EACH_SIDE: usize = 100;
 #[derive(Debug, Clone)]
 pub struct QueueTrack {
     pub source_id: Uuid,
+    /// The library the item is from (its name in `/l/<name>/`)
+    pub library: String,
     pub queue_item_id: Uuid,
     pub name: String,
     pub artist: Option<String>,
     pub album: Option<String>,
     pub track_number: Option<u32>,
+    pub duration: Option<Duration>,
     pub media_url: String,
     pub content_type: String,
 }
@@ -104,6 +107,10 @@ pub struct ItemWindow {
 struct QueueItem {
     #[serde(skip_serializing)]
     source_id: Uuid,
+    /// `None` for queues stored before this was recorded, which were all from the default
+    /// library
+    #[serde(skip_serializing)]
+    library: Option<String>,
     id: String,
     track: TrackMetadata,
 }
@@ -122,6 +129,8 @@ struct TrackMetadata {
     album: Option<NamedMetadata>,
     #[serde(skip_serializing_if = "Option::is_none")]
     track_number: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    duration_millis: Option<u64>,
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -141,6 +150,26 @@ struct WindowPlayhead {
     item_id: String,
 }
 
+/// What a player POSTs to `timePlayed` while it plays items from a queue. Only the parts
+/// we use are parsed, and all of them are optional so that a report we don't understand is
+/// still accepted.
+#[derive(Debug, Default, Deserialize)]
+pub struct TimePlayedReport {
+    #[serde(default)]
+    pub items: Vec<TimePlayedItem>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TimePlayedItem {
+    /// The queue item id, not the library item id
+    #[serde(default)]
+    pub id: String,
+    /// How far into the item playback had got when the report was made
+    #[serde(default)]
+    pub position_millis: Option<u64>,
+}
+
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct ItemWindowQuery {
@@ -200,6 +229,8 @@ struct StoredQueueSnapshot {
 #[derive(Debug, Serialize, Deserialize)]
 struct StoredQueueItem {
     source_id: Uuid,
+    #[serde(default)]
+    library: Option<String>,
     id: String,
     name: String,
     media_url: String,
@@ -207,6 +238,9 @@ struct StoredQueueItem {
     artist: Option<String>,
     album: Option<String>,
     track_number: Option<u32>,
+    /// Missing from queues stored before durations were recorded
+    #[serde(default)]
+    duration_millis: Option<u64>,
 }
 
 pub struct CloudQueueStore {
@@ -216,24 +250,9 @@ pub struct CloudQueueStore {
 }
 
 impl CloudQueueStore {
-    pub fn from_env(db: Pool<SqliteConnectionManager>) -> Result<Self> {
-        let scheme = configured_value("URL_SCHEME", option_env!("URL_SCHEME"));
-        let hostname = configured_value("REITUNES_HOSTNAME", option_env!("REITUNES_HOSTNAME"));
-        let public_base_url = match (scheme, hostname) {
-            (None, None) => None,
-            (Some(scheme), Some(hostname)) => {
-                let url = Url::parse(&format!("{scheme}://{hostname}/"))
-                    .context("URL_SCHEME and REITUNES_HOSTNAME do not form a valid URL")?;
-                if url.scheme() != "https" && url.host_str() != Some("localhost") {
-                    bail!("Sonos Cloud Queue requires HTTPS (except on localhost)");
-                }
-                Some(url)
-            }
-            _ => {
-                bail!("URL_SCHEME and REITUNES_HOSTNAME must either both be set or both be absent")
-            }
-        };
-
+    /// `public_base_url` is where Sonos speakers can reach this server (see
+    /// `Config::public_base_url`); without it, cloud queues can't be created
+    pub fn new(public_base_url: Option<Url>, db: Pool<SqliteConnectionManager>) -> Result<Self> {
         let queues = load_queues(&db)?;
         Ok(Self {
             public_base_url,
@@ -391,13 +410,29 @@ impl CloudQueueStore {
         })
     }
 
+    /// Accept a `timePlayed` report, returning the latest reported position of each library
+    /// item in it, grouped by the library the items are from (`None` for the default library)
     pub fn accept_report(
         &self,
         queue_id: Uuid,
         authorization: Option<&str>,
-    ) -> Result<(), CloudQueueError> {
-        self.authorized_snapshot(queue_id, authorization)?;
-        Ok(())
+        report: &TimePlayedReport,
+    ) -> Result<HashMap<Option<String>, HashMap<Uuid, Duration>>, CloudQueueError> {
+        let snapshot = self.authorized_snapshot(queue_id, authorization)?;
+        let mut positions: HashMap<_, HashMap<_, _>> = HashMap::new();
+        for reported in &report.items {
+            let Some(item) = snapshot.items.iter().find(|item| item.id == reported.id) else {
+                continue;
+            };
+            let Some(position_millis) = reported.position_millis else {
+                continue;
+            };
+            positions
+                .entry(item.library.clone())
+                .or_default()
+                .insert(item.source_id, Duration::from_millis(position_millis));
+        }
+        Ok(positions)
     }
 
     pub fn context(
@@ -540,6 +575,7 @@ impl From<&QueueItem> for StoredQueueItem {
     fn from(item: &QueueItem) -> Self {
         Self {
             source_id: item.source_id,
+            library: item.library.clone(),
             id: item.id.clone(),
             name: item.track.name.clone(),
             media_url: item.track.media_url.clone(),
@@ -547,6 +583,7 @@ impl From<&QueueItem> for StoredQueueItem {
             artist: item.track.artist.as_ref().map(|artist| artist.name.clone()),
             album: item.track.album.as_ref().map(|album| album.name.clone()),
             track_number: item.track.track_number,
+            duration_millis: item.track.duration_millis,
         }
     }
 }
@@ -570,6 +607,7 @@ impl From<StoredQueueItem> for QueueItem {
     fn from(item: StoredQueueItem) -> Self {
         Self {
             source_id: item.source_id,
+            library: item.library,
             id: item.id,
             track: TrackMetadata {
                 track_type: "track",
@@ -579,6 +617,7 @@ impl From<StoredQueueItem> for QueueItem {
                 artist: item.artist.map(|name| NamedMetadata { name }),
                 album: item.album.map(|name| NamedMetadata { name }),
                 track_number: item.track_number,
+                duration_millis: item.duration_millis,
             },
         }
     }
@@ -588,6 +627,7 @@ impl From<QueueTrack> for QueueItem {
     fn from(track: QueueTrack) -> Self {
         Self {
             source_id: track.source_id,
+            library: Some(track.library),
             id: track.queue_item_id.to_string(),
             track: TrackMetadata {
                 track_type: "track",
@@ -597,6 +637,9 @@ impl From<QueueTrack> for QueueItem {
                 artist: track.artist.map(|name| NamedMetadata { name }),
                 album: track.album.map(|name| NamedMetadata { name }),
                 track_number: track.track_number,
+                duration_millis: track
+                    .duration
+                    .map(|duration| duration.as_millis().try_into().unwrap_or(u64::MAX)),
             },
         }
     }
@@ -641,14 +684,6 @@ fn queue_is_expired(queue: &QueueSnapshot) -> bool {
     unix_timestamp().saturating_sub(queue.created_at_unix) >= QUEUE_LIFETIME.as_secs()
 }
 
-fn configured_value(name: &str, compile_time_value: Option<&'static str>) -> Option<String> {
-    compile_time_value
-        .filter(|value| !value.trim().is_empty())
-        .map(ToOwned::to_owned)
-        .or_else(|| std::env::var(name).ok())
-        .filter(|value| !value.trim().is_empty())
-}
-
 fn random_secret() -> String {
     let mut bytes = [0_u8; 32];
     OsRng.fill_bytes(&mut bytes);
@@ -663,11 +698,13 @@ mod tests {
     fn track(number: u128) -> QueueTrack {
         QueueTrack {
             source_id: Uuid::from_u128(number),
+            library: "music".to_string(),
             queue_item_id: Uuid::from_u128(number + 100),
             name: format!("Track {number}"),
             artist: Some("Artist".to_string()),
             album: Some("Album".to_string()),
             track_number: Some(number as u32),
+            duration: Some(Duration::from_secs(180)),
             media_url: format!("https://media.example.com/{number}.mp3"),
             content_type: "audio/mpeg".to_string(),
         }
@@ -709,8 +746,43 @@ mod tests {
 
         let playback = store.playback_parameters(prepared.queue_id).unwrap();
         store
-            .accept_report(prepared.queue_id, Some(&playback.http_authorization))
+            .accept_report(
+                prepared.queue_id,
+                Some(&playback.http_authorization),
+                &TimePlayedReport::default(),
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn reports_positions_of_the_library_items_played() {
+        let store = CloudQueueStore::with_base_url("https://reitunes.example.com/");
+        let prepared = store.prepare(vec![track(1), track(2)], None).unwrap();
+        let playback = store.playback_parameters(prepared.queue_id).unwrap();
+        let report: TimePlayedReport = serde_json::from_value(serde_json::json!({
+            "items": [
+                { "id": Uuid::from_u128(101).to_string(), "type": "update", "positionMillis": 5000 },
+                { "id": Uuid::from_u128(101).to_string(), "type": "final", "positionMillis": 61000 },
+                { "id": Uuid::from_u128(102).to_string(), "type": "start" },
+                { "id": "not-in-this-queue", "positionMillis": 1000 }
+            ]
+        }))
+        .unwrap();
+
+        let positions = store
+            .accept_report(
+                prepared.queue_id,
+                Some(&playback.http_authorization),
+                &report,
+            )
             .unwrap();
+        assert_eq!(
+            positions,
+            HashMap::from([(
+                Some("music".to_string()),
+                HashMap::from([(Uuid::from_u128(1), Duration::from_secs(61))])
+            )])
+        );
     }
 
     #[test]
@@ -743,6 +815,7 @@ mod tests {
             json["items"][0]["track"]["mediaUrl"],
             "https://media.example.com/2.mp3"
         );
+        assert_eq!(json["items"][0]["track"]["durationMillis"], 180_000);
         assert_eq!(
             json["windowPlayhead"]["itemId"],
             Uuid::from_u128(103).to_string()
@@ -0,0 +1,571 @@
+//! Runtime configuration: a TOML file (`reitunes.toml` in the working directory, or
+//! `--config <path>`) with environment variables layered on top. Nothing is baked into the
+//! binary, so the same build can serve dev and prod.
+//!
+//! See `reitunes.example.toml` for every setting and the environment variable that overrides
+//! it.
+
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use anyhow::{bail, Context, Result};
+use clap::Subcommand;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+
+use crate::backup::BackupConfig;
+use crate::sonos::SonosConfig;
+
+pub const DEFAULT_CONFIG_PATH: &str = "reitunes.toml";
+pub const DEFAULT_LIBRARY: &str = "default";
+
+#[derive(Subcommand)]
+pub enum ConfigCommand {
+    /// Load and validate the configuration, then print it (with secrets hidden)
+    Check,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerSettings,
+    pub storage: StorageSettings,
+    pub openai: OpenAiSettings,
+    pub downloader: DownloaderSettings,
+    pub sonos: SonosSettings,
+    pub backup: BackupSettings,
+    /// More libraries, each with its own database, media and credentials. The `server` and
+    /// `storage` settings describe the default library.
+    #[serde(rename = "library")]
+    pub libraries: Vec<LibrarySettings>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerSettings {
+    pub bind: SocketAddr,
+    /// The default library's name. It's served at `/l/<name>/` like every library, and also at
+    /// `/` for older clients.
+    pub library: String,
+    pub db_path: String,
+    /// For logging in to the web UI
+    pub password: Option<String>,
+    /// For the `X-API-Key` routes (sync, fsck)
+    pub api_key: Option<String>,
+    /// The public address, e.g. `https` and `reitunes.example.com`. Sonos cloud queues need it.
+    pub url_scheme: Option<String>,
+    pub hostname: Option<String>,
+}
+
+impl Default for ServerSettings {
+    fn default() -> Self {
+        ServerSettings {
+            bind: SocketAddr::from(([127, 0, 0, 1], 5000)),
+            library: DEFAULT_LIBRARY.to_string(),
+            db_path: "reitunes-library.db".to_string(),
+            password: None,
+            api_key: None,
+            url_scheme: None,
+            hostname: None,
+        }
+    }
+}
+
+/// A library other than the default one
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LibrarySettings {
+    /// Used in URLs (`/l/<name>/`), so only lowercase letters, digits, `-` and `_`
+    pub name: String,
+    pub db_path: String,
+    /// Where this library's media goes in the bucket. Must differ from every other library's.
+    #[serde(default)]
+    pub prefix: Option<String>,
+    #[serde(default)]
+    pub password: Option<String>,
+    /// Only grants access to this library, so it must differ from every other library's
+    #[serde(default)]
+    pub api_key: Option<String>,
+}
+
+impl LibrarySettings {
+    pub fn password(&self) -> &str {
+        self.password.as_deref().unwrap_or_default()
+    }
+
+    pub fn api_key(&self) -> &str {
+        self.api_key.as_deref().unwrap_or_default()
+    }
+}
+
+/// S3-compatible storage for media (and optionally backups)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageSettings {
+    pub endpoint: Option<String>,
+    pub bucket: Option<String>,
+    /// e.g. "dev" or "prod", to keep environments apart in one bucket
+    pub prefix: Option<String>,
+    pub region: String,
+    pub access_key: Option<String>,
+    pub secret_key: Option<String>,
+}
+
+impl Default for StorageSettings {
+    fn default() -> Self {
+        StorageSettings {
+            endpoint: None,
+            bucket: None,
+            prefix: None,
+            region: "ca-east-tor".to_string(),
+            access_key: None,
+            secret_key: None,
+        }
+    }
+}
+
+/// Used to guess song metadata from file names. Optional: without it, file names are used as-is.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OpenAiSettings {
+    pub api_key: Option<String>,
+}
+
+/// The service that fetches audio/video from arbitrary URLs
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DownloaderSettings {
+    pub url: String,
+}
+
+impl Default for DownloaderSettings {
+    fn default() -> Self {
+        DownloaderSettings {
+            url: "http://potato-pi:3000/download".to_string(),
+        }
+    }
+}
+
+/// Sonos Direct Control. Optional, but all or nothing.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SonosSettings {
+    pub client_id: Option<String>,
+    pub client_secret: Option<String>,
+    pub redirect_uri: Option<String>,
+    /// At least 32 characters. Encrypts OAuth tokens before they're saved in SQLite.
+    pub token_encryption_secret: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BackupSettings {
+    pub dir: PathBuf,
+    /// 0 turns scheduled backups off
+    pub interval_hours: u64,
+    /// How many backups to keep (locally and in S3)
+    pub generations: usize,
+    /// Also upload backups to the storage bucket
+    pub upload: bool,
+}
+
+impl Default for BackupSettings {
+    fn default() -> Self {
+        BackupSettings {
+            dir: PathBuf::from("backups"),
+            interval_hours: 24,
+            generations: 7,
+            upload: false,
+        }
+    }
+}
+
+impl Config {
+    /// Read the config file (if there is one) and apply environment variable overrides. This
+    /// only checks that values are well-formed; `validate` checks the server has what it needs.
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        let mut config = match path {
+            Some(path) => Self::from_file(path)?,
+            None if Path::new(DEFAULT_CONFIG_PATH).exists() => {
+                Self::from_file(Path::new(DEFAULT_CONFIG_PATH))?
+            }
+            None => Config::default(),
+        };
+        config.apply_env(|name| std::env::var(name).ok())?;
+
+        // so `cargo run` works without any setup
+        #[cfg(debug_assertions)]
+        {
+            config
+                .server
+                .password
+                .get_or_insert_with(|| "password".to_string());
+            config
+                .server
+                .api_key
+                .get_or_insert_with(|| "development-only-api-key".to_string());
+        }
+        Ok(config)
+    }
+
+    fn from_file(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file {}", path.display()))?;
+        toml::from_str(&text).with_context(|| format!("Invalid config file {}", path.display()))
+    }
+
+    /// Override settings with environment variables (the names the server has always used)
+    fn apply_env(&mut self, env: impl Fn(&str) -> Option<String>) -> Result<()> {
+        let env = |name: &str| env(name).filter(|value| !value.trim().is_empty());
+        let set = |setting: &mut Option<String>, name: &str| {
+            if let Some(value) = env(name) {
+                *setting = Some(value);
+            }
+        };
+        if let Some(library) = env("REITUNES_LIBRARY") {
+            self.server.library = library;
+        }
+        set(&mut self.server.password, "REITUNES_PASSWORD");
+        set(&mut self.server.api_key, "REITUNES_API_KEY");
+        set(&mut self.server.url_scheme, "URL_SCHEME");
+        set(&mut self.server.hostname, "REITUNES_HOSTNAME");
+        set(&mut self.storage.endpoint, "S3_ENDPOINT");
+        set(&mut self.storage.bucket, "S3_BUCKET");
+        set(&mut self.storage.prefix, "S3_PREFIX");
+        set(&mut self.storage.access_key, "S3_ACCESS_KEY");
+        set(&mut self.storage.secret_key, "S3_SECRET_KEY");
+        set(&mut self.openai.api_key, "OPENAI_API_KEY");
+        set(&mut self.sonos.client_id, "SONOS_CLIENT_ID");
+        set(&mut self.sonos.client_secret, "SONOS_CLIENT_SECRET");
+        set(&mut self.sonos.redirect_uri, "SONOS_REDIRECT_URI");
+        set(
+            &mut self.sonos.token_encryption_secret,
+            "SONOS_TOKEN_ENCRYPTION_SECRET",
+        );
+
+        let parse = |name: &str| env(name).map(|value| (name.to_string(), value));
+        override_parsed(&mut self.server.bind, parse("REITUNES_BIND"))?;
+        override_parsed(&mut self.server.db_path, parse("REITUNES_DB_PATH"))?;
+        override_parsed(&mut self.storage.region, parse("S3_REGION"))?;
+        override_parsed(&mut self.downloader.url, parse("DOWNLOADER_URL"))?;
+        override_parsed(&mut self.backup.dir, parse("REITUNES_BACKUP_DIR"))?;
+        override_parsed(
+            &mut self.backup.interval_hours,
+            parse("REITUNES_BACKUP_INTERVAL_HOURS"),
+        )?;
+        override_parsed(
+            &mut self.backup.generations,
+            parse("REITUNES_BACKUP_GENERATIONS"),
+        )?;
+        if let Some(upload) = env("REITUNES_BACKUP_UPLOAD") {
+            self.backup.upload = matches!(upload.as_str(), "1" | "true");
+        }
+        Ok(())
+    }
+
+    /// Check that the server has everything it needs, reporting every problem at once
+    pub fn validate(&self) -> Result<()> {
+        let mut problems = Vec::new();
+        for (value, key, env) in [
+            (
+                &self.server.password,
+                "server.password",
+                "REITUNES_PASSWORD",
+            ),
+            (&self.server.api_key, "server.api_key", "REITUNES_API_KEY"),
+            (&self.storage.endpoint, "storage.endpoint", "S3_ENDPOINT"),
+            (&self.storage.bucket, "storage.bucket", "S3_BUCKET"),
+            (
+                &self.storage.access_key,
+                "storage.access_key",
+                "S3_ACCESS_KEY",
+            ),
+            (
+                &self.storage.secret_key,
+                "storage.secret_key",
+                "S3_SECRET_KEY",
+            ),
+        ] {
+            if let Err(e) = required(value, key, env) {
+                problems.push(e.to_string());
+            }
+        }
+        problems.extend(self.library_problems());
+        if let Err(e) = self.public_base_url() {
+            problems.push(format!("{e:#}"));
+        }
+        if let Err(e) = SonosConfig::from_settings(&self.sonos) {
+            problems.push(format!("{e:#}"));
+        }
+        if let Err(e) = BackupConfig::from_settings(&self.backup) {
+            problems.push(format!("{e:#}"));
+        }
+
+        if !problems.is_empty() {
+            bail!(
+                "Invalid configuration:\n{}",
+                problems
+                    .iter()
+                    .map(|problem| format!("  - {problem}"))
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            );
+        }
+        Ok(())
+    }
+
+    /// Where Sonos speakers can reach this server, if it's reachable from outside
+    pub fn public_base_url(&self) -> Result<Option<Url>> {
+        match (&self.server.url_scheme, &self.server.hostname) {
+            (None, None) => Ok(None),
+            (Some(scheme), Some(hostname)) => {
+                let url = Url::parse(&format!("{scheme}://{hostname}/")).context(
+                    "server.url_scheme and server.hostname do not form a valid URL",
+                )?;
+                if url.scheme() != "https" && url.host_str() != Some("localhost") {
+                    bail!("Sonos Cloud Queue requires HTTPS (except on localhost)");
+                }
+                Ok(Some(url))
+            }
+            _ => bail!(
+                "server.url_scheme (URL_SCHEME) and server.hostname (REITUNES_HOSTNAME) must either both be set or both be absent"
+            ),
+        }
+    }
+
+    /// Every library, the default one first
+    pub fn libraries(&self) -> Vec<LibrarySettings> {
+        let default = LibrarySettings {
+            name: self.server.library.clone(),
+            db_path: self.server.db_path.clone(),
+            prefix: self.storage.prefix.clone(),
+            password: self.server.password.clone(),
+            api_key: self.server.api_key.clone(),
+        };
+        std::iter::once(default)
+            .chain(self.libraries.iter().cloned())
+            .collect()
+    }
+
+    /// The library a CLI command should work on (the default one unless one is named)
+    pub fn library(&self, name: Option<&str>) -> Result<LibrarySettings> {
+        let name = name.unwrap_or(&self.server.library);
+        self.libraries()
+            .into_iter()
+            .find(|library| library.name == name)
+            .with_context(|| format!("There's no library named {name:?}"))
+    }
+
+    fn library_problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        for library in &self.libraries {
+            let key = |setting: &str| format!("library.{}.{setting}", library.name);
+            if library.password.is_none() {
+                problems.push(format!("{} must be set", key("password")));
+            }
+            if library.api_key.is_none() {
+                problems.push(format!("{} must be set", key("api_key")));
+            }
+        }
+
+        let libraries = self.libraries();
+        for (i, library) in libraries.iter().enumerate() {
+            let name = &library.name;
+            let valid_name = !name.is_empty()
+                && name
+                    .chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_');
+            if !valid_name {
+                problems.push(format!(
+                    "Library name {name:?} may only contain lowercase letters, digits, - and _"
+                ));
+            }
+            for other in &libraries[..i] {
+                if other.name == library.name {
+                    problems.push(format!("There's more than one library named {name:?}"));
+                }
+                if other.db_path == library.db_path {
+                    problems.push(format!(
+                        "Libraries {:?} and {name:?} have the same db_path",
+                        other.name
+                    ));
+                }
+                if other.prefix == library.prefix {
+                    problems.push(format!(
+                        "Libraries {:?} and {name:?} have the same storage prefix",
+                        other.name
+                    ));
+                }
+                if other.api_key.is_some() && other.api_key == library.api_key {
+                    problems.push(format!(
+                        "Libraries {:?} and {name:?} have the same api_key",
+                        other.name
+                    ));
+                }
+            }
+        }
+        problems
+    }
+
+    /// A copy that's safe to print
+    fn redacted(&self) -> Config {
+        let mut config = self.clone();
+        for library in &mut config.libraries {
+            for secret in [&mut library.password, &mut library.api_key] {
+                if secret.is_some() {
+                    *secret = Some("********".to_string());
+                }
+            }
+        }
+        for secret in [
+            &mut config.server.password,
+            &mut config.server.api_key,
+            &mut config.storage.access_key,
+            &mut config.storage.secret_key,
+            &mut config.openai.api_key,
+            &mut config.sonos.client_secret,
+            &mut config.sonos.token_encryption_secret,
+        ] {
+            if secret.is_some() {
+                *secret = Some("********".to_string());
+            }
+        }
+        config
+    }
+}
+
+/// A setting the caller can't do without
+pub fn required<'a>(value: &'a Option<String>, key: &str, env: &str) -> Result<&'a str> {
+    value
+        .as_deref()
+        .with_context(|| format!("{key} (or {env}) must be set"))
+}
+
+fn override_parsed<T>(setting: &mut T, value: Option<(String, String)>) -> Result<()>
+where
+    T: FromStr,
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    if let Some((name, value)) = value {
+        *setting = value
+            .parse()
+            .with_context(|| format!("{name} has an invalid value: {value}"))?;
+    }
+    Ok(())
+}
+
+pub fn run(config: &Config, command: ConfigCommand) -> Result<()> {
+    match command {
+        ConfigCommand::Check => {
+            print!("{}", toml::to_string_pretty(&config.redacted())?);
+            config.validate()?;
+            if config.openai.api_key.is_none() {
+                eprintln!("Note: openai.api_key isn't set, so uploads without tags will be named after their files");
+            }
+            eprintln!("Configuration is valid");
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn env_overrides_the_file_and_every_problem_is_reported() -> Result<()> {
+        let mut config: Config = toml::from_str(
+            r#"
+            [server]
+            password = "from the file"
+            url_scheme = "https"
+
+            [sonos]
+            client_id = "sonos"
+            "#,
+        )?;
+        let env: HashMap<_, _> = [
+            ("REITUNES_PASSWORD", "from the env"),
+            ("REITUNES_API_KEY", ""),
+            ("REITUNES_BIND", "0.0.0.0:8080"),
+            ("S3_REGION", "us-east-1"),
+        ]
+        .into_iter()
+        .collect();
+        config.apply_env(|name| env.get(name).map(|value| value.to_string()))?;
+
+        assert_eq!(config.server.password.as_deref(), Some("from the env"));
+        assert_eq!(config.server.bind, "0.0.0.0:8080".parse()?);
+        assert_eq!(config.storage.region, "us-east-1");
+        assert_eq!(config.server.db_path, "reitunes-library.db");
+
+        let error = config.validate().unwrap_err().to_string();
+        for expected in [
+            "server.api_key (or REITUNES_API_KEY) must be set",
+            "storage.endpoint (or S3_ENDPOINT) must be set",
+            "server.hostname (REITUNES_HOSTNAME) must either both be set",
+            "sonos.client_secret (or SONOS_CLIENT_SECRET) must be set",
+        ] {
+            assert!(error.contains(expected), "{expected:?} not in:\n{error}");
+        }
+        assert!(!error.contains("server.password"), "{error}");
+
+        let bad_bind: HashMap<_, _> = [("REITUNES_BIND", "nope")].into_iter().collect();
+        assert!(config
+            .apply_env(|name| bad_bind.get(name).map(|value| value.to_string()))
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn libraries_must_not_share_data_or_credentials() -> Result<()> {
+        let config: Config = toml::from_str(
+            r#"
+            [server]
+            library = "music"
+            api_key = "music key"
+
+            [storage]
+            prefix = "music"
+
+            [[library]]
+            name = "podcasts"
+            db_path = "podcasts.db"
+            prefix = "podcasts"
+            password = "podcasts password"
+            api_key = "music key"
+
+            [[library]]
+            name = "Partner's"
+            db_path = "partner.db"
+            prefix = "music"
+            "#,
+        )?;
+
+        let names: Vec<_> = config.libraries().into_iter().map(|l| l.name).collect();
+        assert_eq!(names, ["music", "podcasts", "Partner's"]);
+        assert_eq!(config.library(None)?.db_path, "reitunes-library.db");
+        assert_eq!(config.library(Some("podcasts"))?.db_path, "podcasts.db");
+        assert!(config.library(Some("audiobooks")).is_err());
+
+        let error = config.validate().unwrap_err().to_string();
+        for expected in [
+            "library.Partner's.password must be set",
+            "library.Partner's.api_key must be set",
+            "Library name \"Partner's\" may only contain",
+            "Libraries \"music\" and \"podcasts\" have the same api_key",
+            "Libraries \"music\" and \"Partner's\" have the same storage prefix",
+        ] {
+            assert!(error.contains(expected), "{expected:?} not in:\n{error}");
+        }
+        assert!(!error.contains("library.podcasts"), "{error}");
+        Ok(())
+    }
+
+    #[test]
+    fn unknown_settings_are_rejected() {
+        assert!(toml::from_str::<Config>("[server]\npasword = \"typo\"").is_err());
+    }
+}
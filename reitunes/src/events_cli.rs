@@ -0,0 +1,315 @@
+//! `reitunes events ...`: poke at the event log without opening the SQLite file by hand
+
+use anyhow::{Context, Result};
+use clap::Subcommand;
+use jiff::civil::DateTime;
+use reitunes_workspace::{
+    load_all_events_from_db, load_all_raw_events, load_raw_event_by_id, open_read_only_connection,
+    Library, LibraryItem, RawEvent,
+};
+use uuid::Uuid;
+
+#[derive(Subcommand)]
+pub enum EventsCommand {
+    /// List events in the order they're applied
+    List {
+        #[command(flatten)]
+        filter: EventFilter,
+
+        /// Print JSON lines instead of a table
+        #[arg(long)]
+        json: bool,
+    },
+    /// Print a single event
+    Show {
+        /// Event ID
+        id: Uuid,
+    },
+    /// Rebuild the library from events created up to a point in time and print the items
+    Replay {
+        /// Only apply events created at or before this time (UTC, e.g. 2024-06-01T12:00:00)
+        #[arg(long)]
+        until: DateTime,
+
+        /// Only print this library item
+        #[arg(long)]
+        item: Option<Uuid>,
+
+        /// Print JSON lines instead of a table
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+#[derive(Debug, Default, clap::Args)]
+pub struct EventFilter {
+    /// Only events for this aggregate (library item or playlist ID)
+    #[arg(long)]
+    aggregate_id: Option<Uuid>,
+
+    /// Only events with this aggregate type (e.g. LibraryItem, Playlist) or event type (e.g.
+    /// LibraryItemPlayedEvent)
+    #[arg(long = "type")]
+    type_name: Option<String>,
+
+    /// Only events created on this machine
+    #[arg(long)]
+    machine: Option<String>,
+
+    /// Only events created at or after this time (UTC)
+    #[arg(long)]
+    since: Option<DateTime>,
+
+    /// Only events created at or before this time (UTC)
+    #[arg(long)]
+    until: Option<DateTime>,
+}
+
+impl EventFilter {
+    fn matches(&self, event: &RawEvent) -> bool {
+        self.aggregate_id.is_none_or(|id| event.aggregate_id == id)
+            && self.type_name.as_deref().is_none_or(|type_name| {
+                event.aggregate_type == type_name || event_type_name(event) == type_name
+            })
+            && self
+                .machine
+                .as_deref()
+                .is_none_or(|machine| event.machine_name == machine)
+            && self
+                .since
+                .is_none_or(|since| event.created_time_utc >= since)
+            && self
+                .until
+                .is_none_or(|until| event.created_time_utc <= until)
+    }
+}
+
+/// The `$type` of an event's payload
+fn event_type_name(event: &RawEvent) -> &str {
+    event
+        .event
+        .get("$type")
+        .and_then(|type_name| type_name.as_str())
+        .unwrap_or("?")
+}
+
+pub fn run(db_path: &str, command: EventsCommand) -> Result<()> {
+    // read-only so that poking at a database (maybe a copy from somewhere else) can't change it
+    let conn = open_read_only_connection(db_path)?;
+    match command {
+        EventsCommand::List { filter, json } => {
+            let events: Vec<_> = load_all_raw_events(&conn)?
+                .into_iter()
+                .filter(|event| filter.matches(event))
+                .collect();
+            if json {
+                for event in &events {
+                    println!("{}", serde_json::to_string(event)?);
+                }
+            } else {
+                print_events_table(&events);
+            }
+        }
+        EventsCommand::Show { id } => {
+            let event = load_raw_event_by_id(&conn, id)?
+                .with_context(|| format!("No event with ID {id}"))?;
+            println!("{}", serde_json::to_string_pretty(&event)?);
+        }
+        EventsCommand::Replay { until, item, json } => {
+            let mut library = Library::new();
+            for event in load_all_events_from_db(&conn)? {
+                if event.created_time_utc <= until {
+                    library.apply(&event);
+                }
+            }
+
+            let mut items: Vec<_> = library
+                .items
+                .values()
+                .filter(|library_item| item.is_none_or(|id| library_item.id == id))
+                .collect();
+            if let Some(id) = item {
+                anyhow::ensure!(!items.is_empty(), "Item {id} did not exist at {until}");
+            }
+            items.sort_by_key(|library_item| library_item.created_time_utc);
+
+            if json {
+                for item in items {
+                    println!("{}", serde_json::to_string(item)?);
+                }
+            } else {
+                print_items_table(&items);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn print_events_table(events: &[RawEvent]) {
+    let rows: Vec<_> = events
+        .iter()
+        .map(|event| {
+            let hlc = event.hlc();
+            vec![
+                event.created_time_utc.to_string(),
+                format!("{}.{}", hlc.millis, hlc.counter),
+                event.machine_name.clone(),
+                event.aggregate_type.clone(),
+                event.aggregate_id.to_string(),
+                event_type_name(event).to_string(),
+                event.id.to_string(),
+            ]
+        })
+        .collect();
+    print_table(
+        &[
+            "CREATED (UTC)",
+            "HLC",
+            "MACHINE",
+            "AGGREGATE",
+            "AGGREGATE ID",
+            "TYPE",
+            "ID",
+        ],
+        &rows,
+    );
+}
+
+fn print_items_table(items: &[&LibraryItem]) {
+    let rows: Vec<_> = items
+        .iter()
+        .map(|item| {
+            vec![
+                item.id.to_string(),
+                item.name.clone(),
+                item.artist.clone(),
+                item.album.clone(),
+                item.play_count.to_string(),
+                item.bookmarks.len().to_string(),
+                if item.is_favorite { "yes" } else { "" }.to_string(),
+            ]
+        })
+        .collect();
+    print_table(
+        &[
+            "ID",
+            "NAME",
+            "ARTIST",
+            "ALBUM",
+            "PLAYS",
+            "BOOKMARKS",
+            "FAVORITE",
+        ],
+        &rows,
+    );
+}
+
+/// Print left-aligned columns padded to the widest cell
+fn print_table(headers: &[&str], rows: &[Vec<String>]) {
+    let mut widths: Vec<_> = headers
+        .iter()
+        .map(|header| header.chars().count())
+        .collect();
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let format_row = |cells: Vec<&str>| {
+        let padded: Vec<_> = cells
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{cell:width$}"))
+            .collect();
+        padded.join("  ").trim_end().to_string()
+    };
+    println!("{}", format_row(headers.to_vec()));
+    for row in rows {
+        println!("{}", format_row(row.iter().map(String::as_str).collect()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reitunes_workspace::{
+        open_connection, save_event_to_db, Event, EventWithMetadata, PlayDetails,
+    };
+
+    #[test]
+    fn events_are_shown_from_a_read_only_connection() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let db_path = dir.path().join("reitunes.db");
+        let db_path = db_path.to_str().unwrap();
+        let played = EventWithMetadata::new(
+            Uuid::new_v4(),
+            Event::LibraryItemPlayedEvent(PlayDetails::default()),
+        )?;
+        save_event_to_db(&open_connection(db_path)?, &played)?;
+
+        let conn = open_read_only_connection(db_path)?;
+        let shown = load_raw_event_by_id(&conn, played.id)?.unwrap();
+        assert_eq!(shown.aggregate_id, played.aggregate_id);
+        assert!(load_raw_event_by_id(&conn, Uuid::new_v4())?.is_none());
+        assert!(conn.execute("DELETE FROM events", []).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn filters_match_aggregate_and_event_types() -> Result<()> {
+        let item_id = Uuid::new_v4();
+        let mut event = EventWithMetadata::new(
+            item_id,
+            Event::LibraryItemPlayedEvent(PlayDetails::default()),
+        )?
+        .to_raw()?;
+        event.created_time_utc = "2024-06-01T12:00:00".parse()?;
+
+        let matching = [
+            EventFilter::default(),
+            EventFilter {
+                aggregate_id: Some(item_id),
+                ..Default::default()
+            },
+            EventFilter {
+                type_name: Some("LibraryItem".to_string()),
+                ..Default::default()
+            },
+            EventFilter {
+                type_name: Some("LibraryItemPlayedEvent".to_string()),
+                since: Some("2024-06-01T00:00:00".parse()?),
+                until: Some("2024-06-01T12:00:00".parse()?),
+                ..Default::default()
+            },
+        ];
+        for filter in matching {
+            assert!(filter.matches(&event), "{filter:?}");
+        }
+
+        let not_matching = [
+            EventFilter {
+                aggregate_id: Some(Uuid::new_v4()),
+                ..Default::default()
+            },
+            EventFilter {
+                type_name: Some("Playlist".to_string()),
+                ..Default::default()
+            },
+            EventFilter {
+                machine: Some("some-other-machine".to_string()),
+                ..Default::default()
+            },
+            EventFilter {
+                until: Some("2024-06-01T11:59:59".parse()?),
+                ..Default::default()
+            },
+        ];
+        for filter in not_matching {
+            assert!(!filter.matches(&event), "{filter:?}");
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,58 @@
+//! `reitunes fsck`: check the event log for problems, and optionally fix the ones that can be
+//! fixed with new events
+
+use anyhow::{bail, Result};
+use reitunes_workspace::{check_database, open_connection, save_raw_event_to_db, FsckReport};
+
+pub fn run(db_path: &str, repair: bool, json: bool) -> Result<()> {
+    let conn = open_connection(db_path)?;
+    let report = check_database(&conn)?;
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        print_report(&report);
+    }
+
+    let repairs = report.repairs();
+    if repair && !repairs.is_empty() {
+        let tx = conn.unchecked_transaction()?;
+        for event in &repairs {
+            save_raw_event_to_db(&tx, event)?;
+        }
+        tx.commit()?;
+        eprintln!("Wrote {} repair events", repairs.len());
+    }
+
+    let remaining = report.problems.len() - if repair { repairs.len() } else { 0 };
+    if remaining > 0 {
+        bail!("{remaining} problems need attention");
+    }
+    Ok(())
+}
+
+fn print_report(report: &FsckReport) {
+    for problem in &report.problems {
+        let fixable = if problem.repair.is_some() {
+            " (repairable)"
+        } else {
+            ""
+        };
+        println!(
+            "{:?}: {}{fixable}\n    event: {}  aggregate: {}",
+            problem.kind,
+            problem.description,
+            problem
+                .event_id
+                .map_or("-".to_string(), |id| id.to_string()),
+            problem
+                .aggregate_id
+                .map_or("-".to_string(), |id| id.to_string()),
+        );
+    }
+    println!(
+        "Checked {} events, found {} problems",
+        report.events_checked,
+        report.problems.len()
+    );
+}
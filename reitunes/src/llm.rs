@@ -28,14 +28,10 @@ pub struct SongMetadata {
     pub album: Option<String>,
 }
 
-pub async fn extract_song_metadata(filename: &str) -> Result<SongMetadata> {
-    // Compile-time key first (baked in via `just publish`), then runtime env var (for dev).
-    let api_key = option_env!("OPENAI_API_KEY")
-        .map(String::from)
-        .or_else(|| std::env::var("OPENAI_API_KEY").ok())
-        .context("OPENAI_API_KEY must be set (compile-time or runtime)")?;
+pub async fn extract_song_metadata(api_key: Option<&str>, filename: &str) -> Result<SongMetadata> {
+    let api_key = api_key.context("openai.api_key (or OPENAI_API_KEY) must be set")?;
     let client: openai::Client =
-        openai::Client::new(&api_key).context("Failed to create OpenAI client")?;
+        openai::Client::new(api_key).context("Failed to create OpenAI client")?;
 
     // Create the extractor for the SongMetadata struct
     let extractor = client
@@ -114,6 +110,11 @@ mod tests {
 
     use pretty_assertions::assert_eq;
 
+    async fn extract_song_metadata(filename: &str) -> Result<SongMetadata> {
+        let api_key = std::env::var("OPENAI_API_KEY").ok();
+        super::extract_song_metadata(api_key.as_deref(), filename).await
+    }
+
     #[tokio::test]
     async fn test_full_album() {
         let metadata =
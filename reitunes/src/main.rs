@@ -4,22 +4,26 @@ use axum::extract::ws::Utf8Bytes;
 use axum::http::HeaderMap;
 use axum::{
     body::Body,
-    extract::{ConnectInfo, DefaultBodyLimit, Form, Json as JsonExtractor, Path, State, WebSocketUpgrade},
+    extract::{ConnectInfo, DefaultBodyLimit, Form, Json as JsonExtractor, NestedPath, Path, State, WebSocketUpgrade},
     http::{Request, StatusCode},
     middleware::{self, Next},
     response::{Html, IntoResponse, Json, Redirect, Response},
     routing::{get, post},
-    Router,
+    Extension, Router,
 };
 use axum::extract::Query;
 use axum_extra::extract::Multipart;
 use axum_macros::debug_handler;
 use clap::{Parser, Subcommand};
+use indexmap::{IndexMap, IndexSet};
+use r2d2::Pool;
+use r2d2_sqlite::SqliteConnectionManager;
 use reitunes_workspace::*;
 use serde::{Deserialize, Serialize};
 use vite_rs_axum_0_8::ViteServe;
 
-use std::sync::{Arc, LazyLock};
+use std::path::PathBuf;
+use std::sync::Arc;
 use std::time::Duration;
 use std::{fmt, net::SocketAddr};
 use tokio::sync::broadcast;
@@ -32,6 +36,11 @@ use crate::llm::SongMetadata;
 use crate::metadata::extract_metadata;
 use crate::storage::S3Storage;
 
+mod archive_cli;
+mod backup;
+mod config;
+mod events_cli;
+mod fsck_cli;
 mod llm;
 mod metadata;
 mod smapi;
@@ -39,45 +48,19 @@ mod sonos;
 mod cloud_queue;
 mod storage;
 mod systemd;
+mod time_travel;
+mod undo;
 
 #[derive(vite_rs::Embed)]
 #[root = "../reitunes-web"]
 struct Assets;
 
-const DB_PATH: &str = "reitunes-library.db";
-const PASSWORD: &str = match option_env!("REITUNES_PASSWORD") {
-    Some(password) => password,
-    None => "password",
-};
-
-#[cfg(debug_assertions)]
-const API_KEY: &str = match option_env!("REITUNES_API_KEY") {
-    Some(api_key) => api_key,
-    None => "development-only-api-key",
-};
-
-#[cfg(not(debug_assertions))]
-const API_KEY: &str = env!(
-    "REITUNES_API_KEY",
-    "REITUNES_API_KEY must be set when building a release binary"
-);
-
-static PASSWORD_HASH: LazyLock<String> = LazyLock::new(|| hash_with_rotating_salt(PASSWORD));
-
-const SESSION_COOKIE_NAME: &str = "reitunes_session";
-
-/// URL of the downloader service that fetches audio/video from arbitrary URLs.
-/// Resolved at compile time via `option_env!` (baked in via `just publish`),
-/// then falling back to a runtime env var (for dev), then a hardcoded default.
-fn downloader_url() -> String {
-    option_env!("DOWNLOADER_URL")
-        .map(String::from)
-        .or_else(|| std::env::var("DOWNLOADER_URL").ok())
-        .unwrap_or_else(|| "http://potato-pi:3000/download".to_string())
-}
+/// Each library has its own session cookie, named this plus the library's name
+const SESSION_COOKIE_PREFIX: &str = "reitunes_session_";
 
-static DB: LazyLock<r2d2::Pool<r2d2_sqlite::SqliteConnectionManager>> =
-    LazyLock::new(|| open_connection_pool(DB_PATH).expect("Failed to create connection pool"));
+/// The session cookie from before there were several libraries. It's still accepted for the
+/// default library, so upgrading doesn't log everyone out.
+const LEGACY_SESSION_COOKIE_NAME: &str = "reitunes_session";
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None, styles = clap_v3_style())]
@@ -85,6 +68,14 @@ struct Cli {
     #[command(subcommand)]
     command: Option<Commands>,
 
+    /// The config file to use (default: reitunes.toml, if it exists)
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+
+    /// The library a command works on (default: the default library)
+    #[arg(long, global = true)]
+    library: Option<String>,
+
     /// Disable authentication (for local development)
     #[arg(long)]
     no_auth: bool,
@@ -97,6 +88,46 @@ static NO_AUTH: std::sync::OnceLock<bool> = std::sync::OnceLock::new();
 enum Commands {
     /// Install this executable as a (user) systemd service
     Install,
+    /// Inspect the event log
+    Events {
+        #[command(subcommand)]
+        command: events_cli::EventsCommand,
+    },
+    /// Check the event log for problems
+    Fsck {
+        /// Write events that fix the problems that can be fixed
+        #[arg(long)]
+        repair: bool,
+
+        /// Print the report as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Export every event, the library and playlists (and optionally media) to a tar archive
+    Export {
+        /// Where to write the archive
+        #[arg(long)]
+        out: PathBuf,
+
+        /// Also download every library item's file from storage
+        #[arg(long)]
+        with_media: bool,
+    },
+    /// Restore an archive written by `export` into an empty database
+    Import {
+        /// The archive to import
+        archive: PathBuf,
+    },
+    /// Back up the database, or restore a backup
+    Backup {
+        #[command(subcommand)]
+        command: backup::BackupCommand,
+    },
+    /// Inspect the configuration
+    Config {
+        #[command(subcommand)]
+        command: config::ConfigCommand,
+    },
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -106,6 +137,8 @@ enum FrontendUpdate {
     Update { item: Box<LibraryItemResponse> },
     #[serde(rename = "delete")]
     Delete { id: Uuid },
+    #[serde(rename = "playlist")]
+    Playlist { playlist: Box<Playlist> },
     #[serde(rename = "sonos")]
     Sonos {
         namespace: String,
@@ -124,10 +157,54 @@ struct AppState {
     storage: Arc<S3Storage>,
     sonos: Option<Arc<sonos::SonosControl>>,
     cloud_queues: Arc<cloud_queue::CloudQueueStore>,
+    events: Arc<dyn EventStore>,
+    time_travel: Arc<time_travel::TimeTravelCache>,
+    undo: Arc<undo::UndoStacks>,
+    config: Arc<config::Config>,
+    /// Which library this is, and the credentials that grant access to it
+    library_settings: Arc<config::LibrarySettings>,
 }
 
+/// Every library served, the default first, for Sonos callbacks that can concern any of them
+#[derive(Clone)]
+struct Libraries(Arc<Vec<AppState>>);
+
 #[tokio::main]
 async fn main() -> Result<()> {
+    let cli = Cli::parse();
+    let config = config::Config::load(cli.config.as_deref())?;
+    let library = config.library(cli.library.as_deref())?;
+    let db_path = library.db_path.as_str();
+
+    // CLI commands print their output to stdout, so keep logs out of the way
+    match cli.command {
+        Some(Commands::Events { command }) => {
+            init_cli_tracing();
+            return events_cli::run(db_path, command);
+        }
+        Some(Commands::Fsck { repair, json }) => {
+            init_cli_tracing();
+            return fsck_cli::run(db_path, repair, json);
+        }
+        Some(Commands::Export { out, with_media }) => {
+            init_cli_tracing();
+            return archive_cli::export(&config, &library, &out, with_media).await;
+        }
+        Some(Commands::Import { archive }) => {
+            init_cli_tracing();
+            return archive_cli::import(db_path, &archive);
+        }
+        Some(Commands::Backup { command }) => {
+            init_cli_tracing();
+            return backup::run(&config, &library, command);
+        }
+        Some(Commands::Config { command }) => {
+            init_cli_tracing();
+            return config::run(&config, command);
+        }
+        _ => {}
+    }
+
     init_tracing();
 
     info!("Starting reitunes v{}", env!("CARGO_PKG_VERSION"));
@@ -136,8 +213,6 @@ async fn main() -> Result<()> {
     #[cfg(debug_assertions)]
     let _guard = Assets::start_dev_server(true);
 
-    let cli = Cli::parse();
-
     // Set global no-auth flag
     NO_AUTH.set(cli.no_auth).expect("NO_AUTH already set");
     if cli.no_auth {
@@ -149,170 +224,69 @@ async fn main() -> Result<()> {
             systemd::install()?;
             println!("Systemd service installed successfully.");
         }
+        Some(
+            Commands::Events { .. }
+            | Commands::Fsck { .. }
+            | Commands::Export { .. }
+            | Commands::Import { .. }
+            | Commands::Backup { .. }
+            | Commands::Config { .. },
+        ) => {
+            unreachable!("handled before startup")
+        }
         None => {
             // Start the web server
-            let conn = DB.get()?;
-            let library = load_library_from_db(&conn)?;
-            let playlists = load_playlists_from_db(&conn)?;
-            // important to drop after using to return the connection to the pool
-            // leaving this connection open slows writes down ~100x (from 0.2 ms to 20 ms)
-            drop(conn);
-
-            // Initialize S3 storage backend
-            // Try compile-time env vars first (baked in via `just publish`),
-            // then fall back to runtime env vars (for dev).
-            let s3_endpoint = option_env!("S3_ENDPOINT")
-                .map(String::from)
-                .or_else(|| std::env::var("S3_ENDPOINT").ok())
-                .expect("S3_ENDPOINT must be set (compile-time or runtime)");
-            let s3_bucket = option_env!("S3_BUCKET")
-                .map(String::from)
-                .or_else(|| std::env::var("S3_BUCKET").ok())
-                .expect("S3_BUCKET must be set (compile-time or runtime)");
-            let s3_access_key = option_env!("S3_ACCESS_KEY")
-                .map(String::from)
-                .or_else(|| std::env::var("S3_ACCESS_KEY").ok())
-                .expect("S3_ACCESS_KEY must be set (compile-time or runtime)");
-            let s3_secret_key = option_env!("S3_SECRET_KEY")
-                .map(String::from)
-                .or_else(|| std::env::var("S3_SECRET_KEY").ok())
-                .expect("S3_SECRET_KEY must be set (compile-time or runtime)");
-            let s3_prefix = option_env!("S3_PREFIX")
-                .map(String::from)
-                .or_else(|| std::env::var("S3_PREFIX").ok());
-
-            info!(
-                "Using S3 storage: {} / {} (prefix: {:?})",
-                s3_endpoint, s3_bucket, s3_prefix
-            );
-            let storage = S3Storage::new(
-                &s3_endpoint,
-                &s3_bucket,
-                s3_prefix.as_deref(),
-                &s3_access_key,
-                &s3_secret_key,
-            )
-            .await
-            .expect("Failed to initialize S3 storage");
-
-            let app_state = AppState {
-                library: Arc::new(RwLock::new(library)),
-                playlists: Arc::new(RwLock::new(playlists)),
-                update_tx: broadcast::channel(100).0,
-                storage: Arc::new(storage),
-                sonos: sonos::SonosControl::from_env(DB.clone())?,
-                cloud_queues: Arc::new(cloud_queue::CloudQueueStore::from_env(DB.clone())?),
-            };
-
-            if app_state.sonos.is_some() {
+            config.validate()?;
+            let config = Arc::new(config);
+            let libraries = config.libraries();
+            let pools = libraries
+                .iter()
+                .map(|library| open_connection_pool(&library.db_path))
+                .collect::<Result<Vec<_>>>()?;
+
+            // Sonos is shared by every library, and keeps its state in the default one's database
+            let sonos = sonos::SonosControl::from_config(&config.sonos, pools[0].clone())?;
+            let cloud_queues = Arc::new(cloud_queue::CloudQueueStore::new(
+                config.public_base_url()?,
+                pools[0].clone(),
+            )?);
+            if sonos.is_some() {
                 info!("Sonos Direct Control is configured");
             } else {
                 info!("Sonos Direct Control is not configured");
             }
 
-            let api_router = Router::new()
-                .route("/add", post(add_item_handler))
-                .route("/allevents", get(all_events_handler))
-                .route_layer(middleware::from_fn(api_key_auth));
-
-            let smapi_router =
-                Router::new().route("/v1/soap", post(smapi::smapi_soap_handler));
+            let backups = backup::BackupConfig::from_settings(&config.backup)?;
+            if backups.is_none() {
+                info!("Scheduled backups are turned off");
+            }
 
-            let cloud_queue_router = Router::new()
-                .route("/{queue_id}/v2.3/context", get(cloud_queue_context_handler))
-                .route("/{queue_id}/v2.3/version", get(cloud_queue_version_handler))
-                .route(
-                    "/{queue_id}/v2.3/itemWindow",
-                    get(cloud_queue_item_window_handler),
-                )
-                .route(
-                    "/{queue_id}/v2.3/timePlayed",
-                    post(cloud_queue_time_played_handler),
-                );
-
-            // Private API routes require the same session as the React frontend.
-            let protected_api_router = Router::new()
-                .route("/items", get(items_handler))
-                .route("/upload", post(upload_handler))
-                // Allow uploads up to 500MB
-                .layer(DefaultBodyLimit::max(500 * 1024 * 1024))
-                .route("/download", post(download_handler))
-                .route("/log", post(frontend_log_handler))
-                .route("/playlists", get(list_playlists_handler).post(create_playlist_handler))
-                .route("/playlists/{id}", axum::routing::put(rename_playlist_handler).delete(delete_playlist_handler))
-                .route("/playlists/{id}/items", post(add_playlist_item_handler))
-                .route("/playlists/{playlist_id}/items/{item_id}", axum::routing::delete(remove_playlist_item_handler))
-                .route("/sonos/status", get(sonos_status_handler))
-                .route("/sonos/authorize", get(sonos_authorize_handler))
-                .route("/sonos/callback", get(sonos_callback_handler))
-                .route("/sonos/households", get(sonos_households_handler))
-                .route("/sonos/cloud-queues", post(prepare_cloud_queue_handler))
-                .route("/sonos/play", post(sonos_play_handler))
-                .route(
-                    "/sonos/groups/{group_id}/playback",
-                    get(sonos_group_playback_handler),
+            let mut states = Vec::new();
+            for (library, pool) in libraries.into_iter().zip(pools) {
+                let app_state = load_library_state(
+                    library,
+                    pool.clone(),
+                    config.clone(),
+                    sonos.clone(),
+                    cloud_queues.clone(),
                 )
-                .route(
-                    "/sonos/groups/{group_id}/playback/play",
-                    post(sonos_group_play_handler),
-                )
-                .route(
-                    "/sonos/groups/{group_id}/playback/pause",
-                    post(sonos_group_pause_handler),
-                )
-                .route(
-                    "/sonos/groups/{group_id}/volume",
-                    get(sonos_group_volume_handler).post(sonos_set_group_volume_handler),
-                )
-                .route(
-                    "/sonos/groups/{group_id}/mute",
-                    post(sonos_set_group_mute_handler),
-                )
-                .route(
-                    "/sonos/households/{household_id}/groups",
-                    get(sonos_groups_handler),
-                )
-                .route(
-                    "/sonos/connection",
-                    axum::routing::delete(sonos_disconnect_handler),
-                )
-                .route_layer(middleware::from_fn(api_session_auth));
-
-            // Build vite service for frontend
-            let vite = ViteServe::new(Assets::boxed());
-
-            let app = Router::new()
-                .route("/login", get(login_handler).post(login_post_handler))
-                .route("/ui/update", post(update_handler))
-                .route("/ui/play", post(play_handler))
-                .route("/ui/delete", post(delete_handler))
-                .route("/ui/{id}/bookmarks", post(add_bookmark_handler))
-                .route(
-                    "/ui/{item_id}/bookmarks/{bookmark_id}",
-                    axum::routing::put(update_bookmark_handler)
-                        .delete(delete_bookmark_handler),
-                )
-                .route("/ui/{id}/favorite", post(favorite_handler))
-                .route("/ui/{id}/unfavorite", post(unfavorite_handler))
-                .route("/updates", get(updates_handler))
-                // Frontend requires auth (must be above route_layer)
-                .route_service("/", vite.clone())
-                .route_service("/{*path}", vite)
-                .route_layer(middleware::from_fn(auth))
-                // Service and API-key routes stay outside session auth.
-                .route("/api/sonos/events", post(sonos_event_handler))
-                .nest("/api", api_router)
-                .nest("/smapi", smapi_router)
-                .nest("/sonos/cloud-queue", cloud_queue_router)
-                .nest("/api", protected_api_router)
-                // Cookie extraction is used by both frontend and API auth middleware.
-                .layer(CookieManagerLayer::new())
-                .with_state(app_state);
-
-            let listener = tokio::net::TcpListener::bind("127.0.0.1:5000")
+                .await?;
+
+                if let Some(backups) = &backups {
+                    let backups = backup::BackupConfig {
+                        dir: backup::library_backup_dir(&config, &app_state.library_settings),
+                        ..backups.clone()
+                    };
+                    backup::spawn_scheduled_backups(backups, pool, app_state.storage.clone());
+                }
+                states.push(app_state);
+            }
+            let app = app_router(states, ViteServe::new(Assets::boxed()));
+
+            let listener = tokio::net::TcpListener::bind(config.server.bind)
                 .await
-                .unwrap();
-            info!("Server running on http://localhost:5000");
+                .with_context(|| format!("Failed to listen on {}", config.server.bind))?;
+            info!("Server running on http://{}", config.server.bind);
             // this is needed to make SocketAddr available to handlers
             let make_service = app.into_make_service_with_connect_info::<SocketAddr>();
             axum::serve(listener, make_service).await.unwrap();
@@ -322,6 +296,213 @@ async fn main() -> Result<()> {
     Ok(())
 }
 
+/// Serve each library under `/l/<name>/`. The default library (the first) is also served at
+/// the root, for clients from before there were several. Sonos calls back there too.
+fn app_router(libraries: Vec<AppState>, vite: ViteServe) -> Router {
+    let default = libraries[0].clone();
+    let mut app = library_router(default.clone(), vite.clone())
+        .merge(sonos_router(&libraries))
+        // rather than falling through to the default library's UI
+        .route(
+            "/l/{*path}",
+            axum::routing::any(|| async { StatusCode::NOT_FOUND }),
+        );
+    for app_state in libraries {
+        let name = app_state.library_settings.name.clone();
+        let base = format!("/l/{name}");
+        app = app
+            .nest(&base, library_router(app_state, vite.clone()))
+            // nesting only matches the base without the trailing slash
+            .route(
+                &format!("{base}/"),
+                get({
+                    let base = base.clone();
+                    move || async move { Redirect::permanent(&base) }
+                }),
+            );
+        info!(library = name, "Serving library at /l/{name}/");
+    }
+    // Cookie extraction is used by both frontend and API auth middleware.
+    app.layer(CookieManagerLayer::new())
+}
+
+/// Every route for one library: its UI, its API and its update channel
+fn library_router(app_state: AppState, vite: ViteServe) -> Router {
+    let api_router = Router::new()
+        .route("/add", post(add_item_handler))
+        .route("/allevents", get(all_events_handler))
+        .route("/events", get(events_handler).post(push_events_handler))
+        .route("/fsck", get(fsck_handler))
+        .route("/fsck/repair", post(fsck_repair_handler))
+        .route_layer(middleware::from_fn_with_state(
+            app_state.clone(),
+            api_key_auth,
+        ));
+
+    // Private API routes require the same session as the React frontend.
+    let protected_api_router = Router::new()
+        .route("/items", get(items_handler))
+        .route("/items/{id}/history", get(item_history_handler))
+        .route("/items/{id}/plays", get(item_plays_handler))
+        .route("/history", get(play_history_handler))
+        .route("/tags", get(tags_handler))
+        .route("/tags/rename", post(rename_tag_handler))
+        .route("/tags/merge", post(merge_tags_handler))
+        .route("/upload", post(upload_handler))
+        // Allow uploads up to 500MB
+        .layer(DefaultBodyLimit::max(500 * 1024 * 1024))
+        .route("/download", post(download_handler))
+        .route("/log", post(frontend_log_handler))
+        .route("/undo", post(undo_handler))
+        .route("/redo", post(redo_handler))
+        .route(
+            "/playlists",
+            get(list_playlists_handler).post(create_playlist_handler),
+        )
+        .route(
+            "/playlists/{id}",
+            axum::routing::put(rename_playlist_handler).delete(delete_playlist_handler),
+        )
+        .route("/playlists/{id}/items", post(add_playlist_item_handler))
+        .route("/playlists/{id}/history", get(playlist_history_handler))
+        .route(
+            "/playlists/{playlist_id}/items/{item_id}",
+            axum::routing::delete(remove_playlist_item_handler),
+        )
+        .route("/sonos/status", get(sonos_status_handler))
+        .route("/sonos/authorize", get(sonos_authorize_handler))
+        .route("/sonos/callback", get(sonos_callback_handler))
+        .route("/sonos/households", get(sonos_households_handler))
+        .route("/sonos/cloud-queues", post(prepare_cloud_queue_handler))
+        .route("/sonos/play", post(sonos_play_handler))
+        .route(
+            "/sonos/groups/{group_id}/playback",
+            get(sonos_group_playback_handler),
+        )
+        .route(
+            "/sonos/groups/{group_id}/playback/play",
+            post(sonos_group_play_handler),
+        )
+        .route(
+            "/sonos/groups/{group_id}/playback/pause",
+            post(sonos_group_pause_handler),
+        )
+        .route(
+            "/sonos/groups/{group_id}/volume",
+            get(sonos_group_volume_handler).post(sonos_set_group_volume_handler),
+        )
+        .route(
+            "/sonos/groups/{group_id}/mute",
+            post(sonos_set_group_mute_handler),
+        )
+        .route(
+            "/sonos/households/{household_id}/groups",
+            get(sonos_groups_handler),
+        )
+        .route(
+            "/sonos/connection",
+            axum::routing::delete(sonos_disconnect_handler),
+        )
+        .route_layer(middleware::from_fn_with_state(
+            app_state.clone(),
+            api_session_auth,
+        ));
+
+    Router::new()
+        .route("/login", get(login_handler).post(login_post_handler))
+        .route("/ui/update", post(update_handler))
+        .route("/ui/play", post(play_handler))
+        .route("/ui/delete", post(delete_handler))
+        .route("/ui/{id}/bookmarks", post(add_bookmark_handler))
+        .route(
+            "/ui/{item_id}/bookmarks/{bookmark_id}",
+            axum::routing::put(update_bookmark_handler).delete(delete_bookmark_handler),
+        )
+        .route(
+            "/ui/{item_id}/bookmarks/{bookmark_id}/position",
+            axum::routing::put(move_bookmark_handler),
+        )
+        .route("/ui/{id}/favorite", post(favorite_handler))
+        .route("/ui/{id}/unfavorite", post(unfavorite_handler))
+        .route("/ui/{id}/rating", post(rating_handler))
+        .route("/ui/{id}/position", post(resume_position_handler))
+        .route("/ui/{id}/tags", post(add_tag_handler))
+        .route(
+            "/ui/{id}/tags/{tag}",
+            axum::routing::delete(remove_tag_handler),
+        )
+        .route("/updates", get(updates_handler))
+        // Frontend requires auth (must be above route_layer)
+        .route_service("/", vite.clone())
+        .route_service("/{*path}", vite.clone())
+        .route_layer(middleware::from_fn_with_state(app_state.clone(), auth))
+        // API-key routes stay outside session auth.
+        .nest("/api", api_router)
+        .nest("/api", protected_api_router)
+        // The UI's scripts and styles are the same for every library, and index.html
+        // loads them from the root, so they can't require a particular library's session
+        .route_service("/assets/{*path}", vite)
+        .with_state(app_state)
+}
+
+/// Routes Sonos calls. Sonos is shared by every library, but browsing (SMAPI) shows the
+/// default library, and its events are broadcast to the default library's clients. Play
+/// reports go to the library each played track is from.
+fn sonos_router(libraries: &[AppState]) -> Router {
+    let app_state = libraries[0].clone();
+    let smapi_router = Router::new().route("/v1/soap", post(smapi::smapi_soap_handler));
+
+    let cloud_queue_router = Router::new()
+        .route("/{queue_id}/v2.3/context", get(cloud_queue_context_handler))
+        .route("/{queue_id}/v2.3/version", get(cloud_queue_version_handler))
+        .route(
+            "/{queue_id}/v2.3/itemWindow",
+            get(cloud_queue_item_window_handler),
+        );
+
+    Router::new()
+        .route("/api/sonos/events", post(sonos_event_handler))
+        .nest("/smapi", smapi_router)
+        .nest("/sonos/cloud-queue", cloud_queue_router)
+        .with_state(app_state)
+        .route(
+            "/sonos/cloud-queue/{queue_id}/v2.3/timePlayed",
+            post(cloud_queue_time_played_handler)
+                .with_state(Libraries(Arc::new(libraries.to_vec()))),
+        )
+}
+
+async fn load_library_state(
+    library: config::LibrarySettings,
+    pool: Pool<SqliteConnectionManager>,
+    config: Arc<config::Config>,
+    sonos: Option<Arc<sonos::SonosControl>>,
+    cloud_queues: Arc<cloud_queue::CloudQueueStore>,
+) -> Result<AppState> {
+    let conn = pool.get()?;
+    let items = load_library_from_db(&conn)?;
+    let playlists = load_playlists_from_db(&conn)?;
+    // important to drop after using to return the connection to the pool
+    // leaving this connection open slows writes down ~100x (from 0.2 ms to 20 ms)
+    drop(conn);
+
+    let storage = S3Storage::from_config(&config.storage, library.prefix.as_deref()).await?;
+
+    Ok(AppState {
+        library: Arc::new(RwLock::new(items)),
+        playlists: Arc::new(RwLock::new(playlists)),
+        update_tx: broadcast::channel(100).0,
+        storage: Arc::new(storage),
+        sonos,
+        cloud_queues,
+        events: Arc::new(SqliteEventStore::new(pool)?),
+        time_travel: Arc::new(time_travel::TimeTravelCache::new()),
+        undo: Arc::new(undo::UndoStacks::new()),
+        config,
+        library_settings: Arc::new(library),
+    })
+}
+
 async fn updates_handler(
     ws: WebSocketUpgrade,
     State(app_state): State<AppState>,
@@ -351,10 +532,215 @@ async fn handle_websocket(
 }
 
 
-async fn all_events_handler() -> Result<impl IntoResponse, AppError> {
-    let conn = DB.get()?;
-    let events = load_all_events_from_db(&conn)?;
-    Ok(Json(events))
+#[derive(Debug, Deserialize)]
+struct AllEventsQuery {
+    /// Only events for this aggregate type, or `all` for every type. Defaults to LibraryItem,
+    /// which is all this endpoint used to serve.
+    aggregate_type: Option<String>,
+}
+
+/// Every event, in the order they're applied
+async fn all_events_handler(
+    State(app_state): State<AppState>,
+    Query(query): Query<AllEventsQuery>,
+) -> Result<Response, AppError> {
+    let raw_events = match query.aggregate_type.as_deref() {
+        Some("all") => app_state.events.load_all()?,
+        Some(aggregate_type) => match aggregate_type.parse() {
+            Ok(aggregate_type) => app_state.events.load_by_aggregate_type(aggregate_type)?,
+            Err(e) => return Ok((StatusCode::BAD_REQUEST, e.to_string()).into_response()),
+        },
+        None => app_state
+            .events
+            .load_by_aggregate_type(AggregateType::LibraryItem)?,
+    };
+
+    let mut events = Vec::with_capacity(raw_events.len());
+    for raw in raw_events {
+        // written by a newer version; there's nothing we could decode it as
+        if raw.aggregate_type.parse::<AggregateType>().is_err() {
+            warn!(
+                id = %raw.id,
+                aggregate_type = raw.aggregate_type,
+                "Skipping event with an unknown aggregate type"
+            );
+            continue;
+        }
+        events.push(AnyEnvelope::from_raw(raw)?);
+    }
+    Ok(Json(events).into_response())
+}
+
+const DEFAULT_EVENT_PAGE_SIZE: usize = 1000;
+const MAX_EVENT_PAGE_SIZE: usize = 10_000;
+
+#[derive(Debug, Deserialize)]
+struct EventsQuery {
+    #[serde(default)]
+    after: i64,
+    limit: Option<usize>,
+}
+
+/// Incremental sync: events of every aggregate type saved after the `after` cursor
+#[instrument(skip(app_state))]
+async fn events_handler(
+    State(app_state): State<AppState>,
+    Query(query): Query<EventsQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_EVENT_PAGE_SIZE)
+        .clamp(1, MAX_EVENT_PAGE_SIZE);
+    let page = app_state.events.load_since(query.after, limit)?;
+    Ok(Json(page))
+}
+
+/// Receive a batch of events that a client created (possibly while offline). Events we
+/// already have are skipped, so clients can safely retry a push.
+#[instrument(skip_all, fields(event_count = events.len()))]
+async fn push_events_handler(
+    State(app_state): State<AppState>,
+    JsonExtractor(events): JsonExtractor<Vec<RawEvent>>,
+) -> Result<impl IntoResponse, AppError> {
+    let saved_count = save_and_apply_raw_events(&app_state, &events).await?;
+    info!(saved_count, "Saved pushed events");
+    Ok(Json(PushResponse { saved_count }))
+}
+
+/// Save events of any aggregate type in one transaction, apply the ones that are new and tell
+/// clients about what changed. Returns how many events were new.
+async fn save_and_apply_raw_events(app_state: &AppState, events: &[RawEvent]) -> Result<usize> {
+    // Decode everything up front so that one bad event rejects the whole batch. Events of
+    // aggregate types from a newer version are saved for other clients but not applied, so
+    // they don't hold up the rest of a client's sync.
+    let decoded = events
+        .iter()
+        .map(|raw| AnyEnvelope::from_raw_if_known(raw.clone()))
+        .collect::<Result<Vec<_>>>()?;
+
+    // Hold the write locks from saving until applying, so that nothing else is saved in
+    // between and the check below sees the log as it will be applied
+    let mut library = app_state.library.write().await;
+    let mut playlists = app_state.playlists.write().await;
+
+    let cursors = app_state.events.append_batch(events).await?;
+    let saved_count = cursors.iter().flatten().count();
+    let mut new_events = Vec::new();
+    for ((cursor, raw), event) in cursors.into_iter().zip(events).zip(decoded) {
+        match (cursor, event) {
+            (None, _) => {}
+            (Some(_), Some(event)) => new_events.push(event),
+            (Some(_), None) => warn!(
+                id = %raw.id,
+                aggregate_type = raw.aggregate_type,
+                "Saved but didn't apply an event with an unknown aggregate type"
+            ),
+        }
+    }
+
+    // Events are applied in HLC order, so an event that sorts before ones we've already
+    // applied (e.g. an edit made on a client that was offline) can't just go on top. Rebuild
+    // the aggregates it belongs to from the store instead.
+    let mut new_ids_by_aggregate: IndexMap<Uuid, Vec<Uuid>> = IndexMap::new();
+    for event in &new_events {
+        let (aggregate_id, id) = match event {
+            AnyEnvelope::Library(event) => (event.aggregate_id, event.id),
+            AnyEnvelope::Playlist(event) => (event.aggregate_id, event.id),
+        };
+        new_ids_by_aggregate
+            .entry(aggregate_id)
+            .or_default()
+            .push(id);
+    }
+    let store = app_state.events.clone();
+    let rebuilds = tokio::task::spawn_blocking(move || -> Result<Vec<_>> {
+        let mut rebuilds = Vec::new();
+        for (aggregate_id, new_ids) in new_ids_by_aggregate {
+            let stored = store.load_by_aggregate_id(aggregate_id)?;
+            let applied_last = stored[stored.len().saturating_sub(new_ids.len())..]
+                .iter()
+                .map(|event| event.id)
+                .eq(new_ids.iter().copied());
+            if !applied_last {
+                let stored = stored
+                    .into_iter()
+                    .filter_map(|raw| AnyEnvelope::from_raw_if_known(raw).transpose())
+                    .collect::<Result<Vec<_>>>()?;
+                rebuilds.push((aggregate_id, stored));
+            }
+        }
+        Ok(rebuilds)
+    })
+    .await??;
+
+    let mut changed_items = Vec::new();
+    let mut changed_playlists = IndexSet::new();
+    for event in &new_events {
+        match event {
+            AnyEnvelope::Library(event) => {
+                if !rebuilds.iter().any(|(id, _)| *id == event.aggregate_id) {
+                    library.apply(event);
+                }
+                changed_items.push(event.aggregate_id);
+            }
+            AnyEnvelope::Playlist(event) => {
+                if !rebuilds.iter().any(|(id, _)| *id == event.aggregate_id) {
+                    playlists.apply(event);
+                }
+                changed_playlists.insert(event.aggregate_id);
+            }
+        }
+    }
+    for (aggregate_id, stored) in rebuilds {
+        info!(%aggregate_id, "Rebuilding aggregate after receiving events out of order");
+        let (mut items, mut lists) = (Vec::new(), Vec::new());
+        for event in stored {
+            match event {
+                AnyEnvelope::Library(event) => items.push(event),
+                AnyEnvelope::Playlist(event) => lists.push(event),
+            }
+        }
+        if !items.is_empty() {
+            library.rebuild_item(aggregate_id, &items);
+        }
+        if !lists.is_empty() {
+            playlists.rebuild_playlist(aggregate_id, &lists);
+        }
+    }
+
+    broadcast_library_items(app_state, &library, changed_items);
+    for id in changed_playlists {
+        if let Some(playlist) = playlists.playlists.get(&id) {
+            let _ = app_state.update_tx.send(FrontendUpdate::Playlist {
+                playlist: Box::new(playlist.clone()),
+            });
+        }
+    }
+
+    Ok(saved_count)
+}
+
+/// Check the event log for problems, including events that can't be read (see `reitunes
+/// fsck`)
+async fn fsck_handler(State(app_state): State<AppState>) -> Result<impl IntoResponse, AppError> {
+    let report = check_event_store_blocking(&app_state).await?;
+    Ok(Json(report))
+}
+
+/// Check the event log and save events that fix the problems that can be fixed
+async fn fsck_repair_handler(
+    State(app_state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let report = check_event_store_blocking(&app_state).await?;
+    let repaired = save_and_apply_raw_events(&app_state, &report.repairs()).await?;
+    info!(repaired, "Repaired event log problems");
+    Ok(Json(report))
+}
+
+/// Checking loads and replays the whole log, so keep it off the async workers
+async fn check_event_store_blocking(app_state: &AppState) -> Result<FsckReport> {
+    let events = app_state.events.clone();
+    tokio::task::spawn_blocking(move || check_event_store(events.as_ref())).await?
 }
 
 /// Receive log messages from frontend
@@ -395,7 +781,16 @@ struct LibraryItemResponse {
     artist: String,
     album: String,
     track_number: Option<u32>,
+    #[serde(with = "reitunes_workspace::duration_serde_seconds::option")]
+    duration: Option<Duration>,
+    year: Option<u32>,
+    genre: String,
     play_count: u32,
+    last_played_utc: Option<jiff::civil::DateTime>,
+    #[serde(with = "reitunes_workspace::duration_serde_seconds::option")]
+    resume_position: Option<Duration>,
+    rating: u8,
+    tags: std::collections::BTreeSet<String>,
     bookmarks: indexmap::IndexMap<Uuid, reitunes_workspace::Bookmark>,
     is_favorite: bool,
     url: String,
@@ -411,7 +806,14 @@ impl LibraryItemResponse {
             artist: item.artist.clone(),
             album: item.album.clone(),
             track_number: item.track_number,
+            duration: item.duration,
+            year: item.year,
+            genre: item.genre.clone(),
             play_count: item.play_count,
+            last_played_utc: item.last_played_utc,
+            resume_position: item.resume_position,
+            rating: item.rating,
+            tags: item.tags.clone(),
             bookmarks: item.bookmarks.clone(),
             is_favorite: item.is_favorite,
             url: storage.url(&item.file_path),
@@ -419,16 +821,230 @@ impl LibraryItemResponse {
     }
 }
 
+#[derive(Debug, Deserialize)]
+struct AsOfQuery {
+    /// Show the library as it was at this time (UTC) instead of as it is now
+    as_of: Option<jiff::civil::DateTime>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ItemsQuery {
+    /// Show the library as it was at this time (UTC) instead of as it is now
+    as_of: Option<jiff::civil::DateTime>,
+    /// Only items rated at least this many stars
+    min_rating: Option<u8>,
+    /// Items are in no particular order without this
+    sort: Option<ItemSort>,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum ItemSort {
+    Name,
+    /// Highest rated first
+    Rating,
+}
+
 /// Get all library items as JSON (for React frontend)
 #[instrument(skip(app_state))]
-async fn items_handler(State(app_state): State<AppState>) -> Result<impl IntoResponse, AppError> {
+async fn items_handler(
+    State(app_state): State<AppState>,
+    Query(query): Query<ItemsQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    let to_responses = |library: &Library| -> Vec<_> {
+        let mut items: Vec<_> = library
+            .items
+            .values()
+            .filter(|item| query.min_rating.is_none_or(|min| item.rating >= min))
+            .collect();
+        match query.sort {
+            Some(ItemSort::Name) => items.sort_by(|a, b| a.name.cmp(&b.name)),
+            Some(ItemSort::Rating) => {
+                items.sort_by(|a, b| b.rating.cmp(&a.rating).then_with(|| a.name.cmp(&b.name)))
+            }
+            None => {}
+        }
+        items
+            .into_iter()
+            .map(|item| LibraryItemResponse::from_item(item, &app_state.storage))
+            .collect()
+    };
+
+    let items = match query.as_of {
+        Some(as_of) => {
+            let past = app_state
+                .time_travel
+                .state_as_of(app_state.events.clone(), as_of)
+                .await?;
+            to_responses(&past.library)
+        }
+        None => to_responses(&*app_state.library.read().await),
+    };
+    Ok(Json(items))
+}
+
+/// One event in an item's or playlist's timeline, with what it changed
+#[derive(Debug, Serialize)]
+struct HistoryEntryResponse<E, S> {
+    id: Uuid,
+    created_time_utc: jiff::civil::DateTime,
+    machine_name: String,
+    event: E,
+    changes: Vec<String>,
+    before: Option<S>,
+    after: Option<S>,
+}
+
+/// Every event for a library item, oldest first
+#[instrument(skip(app_state))]
+async fn item_history_handler(
+    State(app_state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<Response, AppError> {
+    let events = app_state
+        .events
+        .load_by_aggregate_id(id)?
+        .into_iter()
+        .filter(|event| event.aggregate_type == AggregateType::LibraryItem.as_str())
+        .map(EventWithMetadata::from_raw)
+        .collect::<Result<Vec<_>>>()?;
+    if events.is_empty() {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    }
+
+    let history: Vec<_> = Library::item_history(events, id)
+        .into_iter()
+        .map(|entry| HistoryEntryResponse {
+            changes: entry.changes(),
+            id: entry.event.id,
+            created_time_utc: entry.event.created_time_utc,
+            machine_name: entry.event.machine_name,
+            event: entry.event.event,
+            before: entry.before,
+            after: entry.after,
+        })
+        .collect();
+    Ok(Json(history).into_response())
+}
+
+/// One play of a library item
+#[derive(Debug, Serialize)]
+struct PlayResponse {
+    id: Uuid,
+    item_id: Uuid,
+    played_time_utc: jiff::civil::DateTime,
+    machine_name: String,
+    source: Option<PlaySource>,
+    seconds_listened: Option<u32>,
+    completed: Option<bool>,
+    /// The item's current name and artist, or `None` if it no longer exists
+    name: Option<String>,
+    artist: Option<String>,
+}
+
+/// The plays among `events`, newest first
+fn plays_newest_first(events: Vec<RawEvent>, library: &Library) -> Result<Vec<PlayResponse>> {
+    let mut plays = Vec::new();
+    for raw in events {
+        // Skip parsing everything that obviously isn't a play
+        if raw.aggregate_type != AggregateType::LibraryItem.as_str()
+            || raw.event.get("$type").and_then(|t| t.as_str()) != Some("LibraryItemPlayedEvent")
+        {
+            continue;
+        }
+        let event = EventWithMetadata::from_raw(raw)?;
+        let Event::LibraryItemPlayedEvent(details) = event.event else {
+            continue;
+        };
+        let item = library
+            .items
+            .get(&event.aggregate_id)
+            .or_else(|| library.deleted_items.get(&event.aggregate_id));
+        plays.push(PlayResponse {
+            id: event.id,
+            item_id: event.aggregate_id,
+            played_time_utc: event.created_time_utc,
+            machine_name: event.machine_name,
+            source: details.source,
+            seconds_listened: details.seconds_listened,
+            completed: details.completed,
+            name: item.map(|item| item.name.clone()),
+            artist: item.map(|item| item.artist.clone()),
+        });
+    }
+    plays.sort_by_key(|play| std::cmp::Reverse(play.played_time_utc));
+    Ok(plays)
+}
+
+/// Every play of a library item, newest first
+#[instrument(skip(app_state))]
+async fn item_plays_handler(
+    State(app_state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<Response, AppError> {
+    let events = app_state.events.load_by_aggregate_id(id)?;
     let library = app_state.library.read().await;
-    let items: Vec<_> = library
-        .items
-        .values()
-        .map(|item| LibraryItemResponse::from_item(item, &app_state.storage))
+    if !library.items.contains_key(&id) && !library.deleted_items.contains_key(&id) {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    }
+    Ok(Json(plays_newest_first(events, &library)?).into_response())
+}
+
+#[derive(Debug, Deserialize)]
+struct PlayHistoryQuery {
+    /// Only plays before this time (UTC), to page back through the history
+    before: Option<jiff::civil::DateTime>,
+    limit: Option<usize>,
+}
+
+const DEFAULT_PLAY_HISTORY_LIMIT: usize = 50;
+const MAX_PLAY_HISTORY_LIMIT: usize = 500;
+
+/// Recent plays across the whole library, newest first
+#[instrument(skip(app_state))]
+async fn play_history_handler(
+    State(app_state): State<AppState>,
+    Query(query): Query<PlayHistoryQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_PLAY_HISTORY_LIMIT)
+        .min(MAX_PLAY_HISTORY_LIMIT);
+    let events = app_state.events.load_plays(query.before, limit)?;
+    let library = app_state.library.read().await;
+    Ok(Json(plays_newest_first(events, &library)?))
+}
+
+/// Every event for a playlist, oldest first
+#[instrument(skip(app_state))]
+async fn playlist_history_handler(
+    State(app_state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<Response, AppError> {
+    let events = app_state
+        .events
+        .load_by_aggregate_id(id)?
+        .into_iter()
+        .filter(|event| event.aggregate_type == AggregateType::Playlist.as_str())
+        .map(PlaylistEventWithMetadata::from_raw)
+        .collect::<Result<Vec<_>>>()?;
+    if events.is_empty() {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    }
+
+    let history: Vec<_> = PlaylistStore::playlist_history(events, id)
+        .into_iter()
+        .map(|entry| HistoryEntryResponse {
+            changes: entry.changes(),
+            id: entry.event.id,
+            created_time_utc: entry.event.created_time_utc,
+            machine_name: entry.event.machine_name,
+            event: entry.event.event,
+            before: entry.before,
+            after: entry.after,
+        })
         .collect();
-    Ok(Json(items))
+    Ok(Json(history).into_response())
 }
 
 // ============================================================================
@@ -886,11 +1502,13 @@ async fn prepare_cloud_queue(
         })?;
         tracks.push(cloud_queue::QueueTrack {
             source_id: *item_id,
+            library: app_state.library_settings.name.clone(),
             queue_item_id: Uuid::new_v4(),
             name: item.name.clone(),
             artist: non_empty_string(&item.artist),
             album: non_empty_string(&item.album),
             track_number: item.track_number,
+            duration: item.duration,
             media_url: app_state.storage.url(&item.file_path),
             content_type: mime_guess::from_path(&item.file_path)
                 .first_or_octet_stream()
@@ -945,15 +1563,32 @@ async fn cloud_queue_item_window_handler(
 }
 
 async fn cloud_queue_time_played_handler(
-    State(app_state): State<AppState>,
+    State(Libraries(libraries)): State<Libraries>,
     Path(queue_id): Path<Uuid>,
     headers: HeaderMap,
-    JsonExtractor(_report): JsonExtractor<serde_json::Value>,
+    JsonExtractor(report): JsonExtractor<cloud_queue::TimePlayedReport>,
 ) -> SonosApiResult<StatusCode> {
-    app_state
+    // every library shares the one queue store
+    let positions_by_library = libraries[0]
         .cloud_queues
-        .accept_report(queue_id, authorization_header(&headers))
+        .accept_report(queue_id, authorization_header(&headers), &report)
         .map_err(cloud_queue_failure)?;
+    // the player doesn't care whether we kept track, so don't fail the report over it
+    for (library, positions) in positions_by_library {
+        let app_state = match &library {
+            None => Some(&libraries[0]),
+            Some(name) => libraries
+                .iter()
+                .find(|app_state| app_state.library_settings.name == *name),
+        };
+        let Some(app_state) = app_state else {
+            warn!(?library, %queue_id, "Cloud Queue played tracks from a library that is no longer served");
+            continue;
+        };
+        if let Err(error) = save_resume_positions(app_state, positions).await {
+            warn!(error = ?error, %queue_id, "Could not save Cloud Queue resume positions");
+        }
+    }
     Ok(StatusCode::NO_CONTENT)
 }
 
@@ -972,10 +1607,10 @@ fn non_empty_string(value: &str) -> Option<String> {
 async fn favorite_handler(
     State(app_state): State<AppState>,
     Path(id): Path<Uuid>,
+    cookies: Cookies,
 ) -> Result<impl IntoResponse, AppError> {
     let event = Event::LibraryItemFavoritedEvent;
-    let event_with_metadata = EventWithMetadata::new(id, event)?;
-    save_and_broadcast_event(event_with_metadata, app_state).await?;
+    save_and_broadcast_edit(id, vec![event], app_state, undo::undo_session(&cookies)).await?;
     Ok(StatusCode::OK)
 }
 
@@ -984,40 +1619,233 @@ async fn favorite_handler(
 async fn unfavorite_handler(
     State(app_state): State<AppState>,
     Path(id): Path<Uuid>,
+    cookies: Cookies,
 ) -> Result<impl IntoResponse, AppError> {
     let event = Event::LibraryItemUnfavoritedEvent;
-    let event_with_metadata = EventWithMetadata::new(id, event)?;
-    save_and_broadcast_event(event_with_metadata, app_state).await?;
+    save_and_broadcast_edit(id, vec![event], app_state, undo::undo_session(&cookies)).await?;
     Ok(StatusCode::OK)
 }
 
-/// Upload response with extracted metadata
-#[derive(Debug, Serialize)]
-struct UploadResponse {
-    id: Uuid,
-    name: String,
-    artist: Option<String>,
-    album: Option<String>,
-    file_path: String,
+#[derive(Debug, Deserialize)]
+struct RatingRequest {
+    rating: u8,
 }
 
-/// Handle file upload with ID3 metadata extraction
-#[debug_handler]
-async fn upload_handler(
+/// Rate a library item from 0 (unrated) to `MAX_RATING` stars
+#[instrument(skip(app_state))]
+async fn rating_handler(
     State(app_state): State<AppState>,
-    mut multipart: Multipart,
-) -> Result<impl IntoResponse, AppError> {
-    // Process uploaded file
-    if let Some(field) = multipart.next_field().await? {
-        let filename = field.file_name().unwrap_or("unknown").to_string();
-        let data = field.bytes().await?;
-
-        info!(filename = %filename, size = data.len(), "Received file upload");
+    Path(id): Path<Uuid>,
+    cookies: Cookies,
+    JsonExtractor(request): JsonExtractor<RatingRequest>,
+) -> Result<Response, AppError> {
+    if request.rating > MAX_RATING {
+        return Ok((
+            StatusCode::BAD_REQUEST,
+            format!("Ratings go from 0 to {MAX_RATING}"),
+        )
+            .into_response());
+    }
+    let event = Event::LibraryItemRatingChangedEvent {
+        new_rating: request.rating,
+    };
+    save_and_broadcast_edit(id, vec![event], app_state, undo::undo_session(&cookies)).await?;
+    Ok(StatusCode::OK.into_response())
+}
 
-        // Write to temp file for ID3 extraction
-        let temp_dir = tempfile::tempdir()?;
-        let temp_path = temp_dir.path().join(&filename);
-        tokio::fs::write(&temp_path, &data).await?;
+#[derive(Debug, Deserialize)]
+struct ResumePositionRequest {
+    /// Seconds into the item
+    position: f64,
+}
+
+/// Report how far into an item playback has got, so it can be resumed from there later.
+/// Players can call this as often as they like; see `RESUME_POSITION_INTERVAL`.
+#[instrument(skip(app_state))]
+async fn resume_position_handler(
+    State(app_state): State<AppState>,
+    Path(id): Path<Uuid>,
+    JsonExtractor(request): JsonExtractor<ResumePositionRequest>,
+) -> Result<Response, AppError> {
+    let Ok(position) = Duration::try_from_secs_f64(request.position) else {
+        return Ok((StatusCode::BAD_REQUEST, "Invalid position").into_response());
+    };
+    save_resume_positions(&app_state, [(id, position)]).await?;
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+/// Save the resume position of each item that has moved far enough to be worth an event
+async fn save_resume_positions(
+    app_state: &AppState,
+    positions: impl IntoIterator<Item = (Uuid, Duration)>,
+) -> Result<()> {
+    let events = {
+        let library = app_state.library.read().await;
+        positions
+            .into_iter()
+            .filter_map(|(id, position)| {
+                let event = library.items.get(&id)?.resume_position_event(position)?;
+                Some(EventWithMetadata::new(id, event))
+            })
+            .collect::<Result<Vec<_>>>()?
+    };
+    if events.is_empty() {
+        return Ok(());
+    }
+    save_and_broadcast_events(events, app_state.clone()).await
+}
+
+#[derive(Debug, Deserialize)]
+struct AddTagRequest {
+    tag: String,
+}
+
+/// Tag a library item
+#[instrument(skip(app_state))]
+async fn add_tag_handler(
+    State(app_state): State<AppState>,
+    Path(id): Path<Uuid>,
+    cookies: Cookies,
+    JsonExtractor(request): JsonExtractor<AddTagRequest>,
+) -> Result<Response, AppError> {
+    let Some(tag) = clean_tag(&request.tag) else {
+        return Ok((StatusCode::BAD_REQUEST, "Tags must not be empty").into_response());
+    };
+    let event = Event::LibraryItemTagAddedEvent { tag };
+    save_and_broadcast_edit(id, vec![event], app_state, undo::undo_session(&cookies)).await?;
+    Ok(StatusCode::OK.into_response())
+}
+
+/// Remove a tag from a library item
+#[instrument(skip(app_state))]
+async fn remove_tag_handler(
+    State(app_state): State<AppState>,
+    Path((id, tag)): Path<(Uuid, String)>,
+    cookies: Cookies,
+) -> Result<impl IntoResponse, AppError> {
+    let tagged = app_state
+        .library
+        .read()
+        .await
+        .items
+        .get(&id)
+        .is_some_and(|item| item.tags.contains(&tag));
+    if !tagged {
+        return Ok(StatusCode::NOT_FOUND);
+    }
+
+    let event = Event::LibraryItemTagRemovedEvent { tag };
+    save_and_broadcast_edit(id, vec![event], app_state, undo::undo_session(&cookies)).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+fn clean_tag(tag: &str) -> Option<String> {
+    let tag = tag.trim();
+    (!tag.is_empty()).then(|| tag.to_string())
+}
+
+#[derive(Debug, Serialize)]
+struct TagResponse {
+    name: String,
+    count: usize,
+}
+
+/// Every tag in use, with how many items have it
+#[instrument(skip(app_state))]
+async fn tags_handler(State(app_state): State<AppState>) -> Json<Vec<TagResponse>> {
+    let tags = app_state
+        .library
+        .read()
+        .await
+        .tag_counts()
+        .into_iter()
+        .map(|(name, count)| TagResponse { name, count })
+        .collect();
+    Json(tags)
+}
+
+#[derive(Debug, Deserialize)]
+struct RenameTagRequest {
+    from: String,
+    to: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct MergeTagsRequest {
+    from: Vec<String>,
+    into: String,
+}
+
+#[derive(Debug, Serialize)]
+struct RetagResponse {
+    changed_items: usize,
+}
+
+/// Rename a tag on every item that has it. Renaming to a tag that's already in use merges them.
+#[instrument(skip(app_state))]
+async fn rename_tag_handler(
+    State(app_state): State<AppState>,
+    JsonExtractor(request): JsonExtractor<RenameTagRequest>,
+) -> Result<Response, AppError> {
+    retag(app_state, vec![request.from], &request.to).await
+}
+
+/// Replace several tags with one, on every item that has any of them
+#[instrument(skip(app_state))]
+async fn merge_tags_handler(
+    State(app_state): State<AppState>,
+    JsonExtractor(request): JsonExtractor<MergeTagsRequest>,
+) -> Result<Response, AppError> {
+    retag(app_state, request.from, &request.into).await
+}
+
+/// Retagging can touch a lot of items, so all of its events are saved in one transaction.
+/// It isn't undoable (undo works on one item at a time), but renaming back gets close.
+async fn retag(app_state: AppState, from: Vec<String>, to: &str) -> Result<Response, AppError> {
+    let Some(to) = clean_tag(to) else {
+        return Ok((StatusCode::BAD_REQUEST, "Tags must not be empty").into_response());
+    };
+
+    let retag = app_state.library.read().await.retag_events(&from, &to);
+    let changed_items = retag.len();
+    let events = retag
+        .into_iter()
+        .flat_map(|(id, events)| events.into_iter().map(move |event| (id, event)))
+        .map(|(id, event)| EventWithMetadata::new(id, event))
+        .collect::<Result<Vec<_>>>()?;
+    if !events.is_empty() {
+        save_and_broadcast_events(events, app_state).await?;
+    }
+    Ok(Json(RetagResponse { changed_items }).into_response())
+}
+
+/// Upload response with extracted metadata
+#[derive(Debug, Serialize)]
+struct UploadResponse {
+    id: Uuid,
+    name: String,
+    artist: Option<String>,
+    album: Option<String>,
+    file_path: String,
+}
+
+/// Handle file upload with ID3 metadata extraction
+#[debug_handler]
+async fn upload_handler(
+    State(app_state): State<AppState>,
+    mut multipart: Multipart,
+) -> Result<impl IntoResponse, AppError> {
+    // Process uploaded file
+    if let Some(field) = multipart.next_field().await? {
+        let filename = field.file_name().unwrap_or("unknown").to_string();
+        let data = field.bytes().await?;
+
+        info!(filename = %filename, size = data.len(), "Received file upload");
+
+        // Write to temp file for ID3 extraction
+        let temp_dir = tempfile::tempdir()?;
+        let temp_path = temp_dir.path().join(&filename);
+        tokio::fs::write(&temp_path, &data).await?;
 
         // Extract ID3 metadata
         let metadata = match extract_metadata(&temp_path) {
@@ -1038,7 +1866,9 @@ async fn upload_handler(
             )
         } else {
             // Fallback to LLM for files without ID3 tags
-            match llm::extract_song_metadata(&filename).await {
+            match llm::extract_song_metadata(app_state.config.openai.api_key.as_deref(), &filename)
+                .await
+            {
                 Ok(llm_meta) => (llm_meta.name, llm_meta.artist, llm_meta.album, None),
                 Err(e) => {
                     warn!(error = ?e, "LLM extraction failed, using filename");
@@ -1058,22 +1888,13 @@ async fn upload_handler(
             album: album.clone(),
             track_number,
             file_path: file_path.clone(),
+            duration: metadata.duration,
+            year: metadata.year,
+            genre: metadata.genre,
         };
         let event_with_metadata = EventWithMetadata::new(item_id, event)?;
 
-        // Save and broadcast
-        let conn = DB.get()?;
-        save_event_to_db(&conn, &event_with_metadata)?;
-
-        let mut library = app_state.library.write().await;
-        library.apply(&event_with_metadata);
-
-        if let Some(updated_item) = library.items.get(&item_id) {
-            let response = LibraryItemResponse::from_item(updated_item, &app_state.storage);
-            let _ = app_state
-                .update_tx
-                .send(FrontendUpdate::Update { item: Box::new(response) });
-        }
+        save_and_broadcast_event(event_with_metadata, app_state.clone()).await?;
 
         return Ok(Json(UploadResponse {
             id: item_id,
@@ -1098,6 +1919,7 @@ struct DownloadRequest {
 /// queues the download and pushes the finished track into ReiTunes itself.
 #[debug_handler]
 async fn download_handler(
+    State(app_state): State<AppState>,
     JsonExtractor(req): JsonExtractor<DownloadRequest>,
 ) -> Result<impl IntoResponse, (StatusCode, String)> {
     if req.url.trim().is_empty() {
@@ -1112,7 +1934,7 @@ async fn download_handler(
 
     let client = reqwest::Client::new();
     let response = client
-        .post(downloader_url())
+        .post(&app_state.config.downloader.url)
         .json(&req)
         .send()
         .await
@@ -1145,9 +1967,21 @@ async fn download_handler(
 /// List all playlists
 async fn list_playlists_handler(
     State(app_state): State<AppState>,
+    Query(query): Query<AsOfQuery>,
 ) -> Result<impl IntoResponse, AppError> {
-    let playlists = app_state.playlists.read().await;
-    let active: Vec<_> = playlists.active_playlists().into_iter().cloned().collect();
+    let active: Vec<_> = match query.as_of {
+        Some(as_of) => {
+            let past = app_state
+                .time_travel
+                .state_as_of(app_state.events.clone(), as_of)
+                .await?;
+            past.playlists.active_playlists().into_iter().cloned().collect()
+        }
+        None => {
+            let playlists = app_state.playlists.read().await;
+            playlists.active_playlists().into_iter().cloned().collect()
+        }
+    };
     Ok(Json(active))
 }
 
@@ -1167,11 +2001,11 @@ async fn create_playlist_handler(
     };
     let event_with_metadata = PlaylistEventWithMetadata::new(playlist_id, event)?;
 
-    let conn = DB.get()?;
-    save_playlist_event_to_db(&conn, &event_with_metadata)?;
+    // Lock before saving so that concurrent edits are applied in the order they're saved
+    let mut playlists = app_state.playlists.write().await;
+    app_state.events.append(&event_with_metadata.to_raw()?).await?;
 
     // Apply to in-memory store
-    let mut playlists = app_state.playlists.write().await;
     let playlist = Playlist::new(playlist_id, request.name, event_with_metadata.created_time_utc);
     playlists.playlists.insert(playlist_id, playlist.clone());
 
@@ -1194,11 +2028,11 @@ async fn rename_playlist_handler(
     };
     let event_with_metadata = PlaylistEventWithMetadata::new(id, event.clone())?;
 
-    let conn = DB.get()?;
-    save_playlist_event_to_db(&conn, &event_with_metadata)?;
+    // Lock before saving so that concurrent edits are applied in the order they're saved
+    let mut playlists = app_state.playlists.write().await;
+    app_state.events.append(&event_with_metadata.to_raw()?).await?;
 
     // Apply to in-memory store
-    let mut playlists = app_state.playlists.write().await;
     if let Some(playlist) = playlists.playlists.get_mut(&id) {
         playlist.apply(&event);
     }
@@ -1214,11 +2048,11 @@ async fn delete_playlist_handler(
     let event = PlaylistEvent::PlaylistDeletedEvent;
     let event_with_metadata = PlaylistEventWithMetadata::new(id, event.clone())?;
 
-    let conn = DB.get()?;
-    save_playlist_event_to_db(&conn, &event_with_metadata)?;
+    // Lock before saving so that concurrent edits are applied in the order they're saved
+    let mut playlists = app_state.playlists.write().await;
+    app_state.events.append(&event_with_metadata.to_raw()?).await?;
 
     // Apply to in-memory store
-    let mut playlists = app_state.playlists.write().await;
     if let Some(playlist) = playlists.playlists.get_mut(&id) {
         playlist.apply(&event);
     }
@@ -1238,17 +2072,17 @@ async fn add_playlist_item_handler(
     Path(id): Path<Uuid>,
     JsonExtractor(request): JsonExtractor<AddPlaylistItemRequest>,
 ) -> Result<impl IntoResponse, AppError> {
+    // Lock before saving so that concurrent edits are applied in the order they're saved
+    let mut playlists = app_state.playlists.write().await;
+
     // Get current position if not specified
-    let position = {
-        let playlists = app_state.playlists.read().await;
-        request.position.unwrap_or_else(|| {
-            playlists
-                .playlists
-                .get(&id)
-                .map(|p| p.items.len() as u32)
-                .unwrap_or(0)
-        })
-    };
+    let position = request.position.unwrap_or_else(|| {
+        playlists
+            .playlists
+            .get(&id)
+            .map(|p| p.items.len() as u32)
+            .unwrap_or(0)
+    });
 
     let event = PlaylistEvent::PlaylistItemAddedEvent {
         library_item_id: request.library_item_id,
@@ -1256,11 +2090,9 @@ async fn add_playlist_item_handler(
     };
     let event_with_metadata = PlaylistEventWithMetadata::new(id, event.clone())?;
 
-    let conn = DB.get()?;
-    save_playlist_event_to_db(&conn, &event_with_metadata)?;
+    app_state.events.append(&event_with_metadata.to_raw()?).await?;
 
     // Apply to in-memory store
-    let mut playlists = app_state.playlists.write().await;
     if let Some(playlist) = playlists.playlists.get_mut(&id) {
         playlist.apply(&event);
     }
@@ -1278,11 +2110,11 @@ async fn remove_playlist_item_handler(
     };
     let event_with_metadata = PlaylistEventWithMetadata::new(playlist_id, event.clone())?;
 
-    let conn = DB.get()?;
-    save_playlist_event_to_db(&conn, &event_with_metadata)?;
+    // Lock before saving so that concurrent edits are applied in the order they're saved
+    let mut playlists = app_state.playlists.write().await;
+    app_state.events.append(&event_with_metadata.to_raw()?).await?;
 
     // Apply to in-memory store
-    let mut playlists = app_state.playlists.write().await;
     if let Some(playlist) = playlists.playlists.get_mut(&playlist_id) {
         playlist.apply(&event);
     }
@@ -1299,45 +2131,157 @@ struct UpdateRequest {
 
 async fn update_handler(
     State(app_state): State<AppState>,
+    cookies: Cookies,
     JsonExtractor(request): JsonExtractor<UpdateRequest>,
 ) -> Result<impl IntoResponse, AppError> {
     let event = create_update_event(&request.field, &request.value)?;
-    let event_with_metadata = EventWithMetadata::new(request.id, event)?;
 
-    save_and_broadcast_event(event_with_metadata, app_state).await?;
+    save_and_broadcast_edit(request.id, vec![event], app_state, undo::undo_session(&cookies))
+        .await?;
 
     Ok(StatusCode::OK)
 }
 
 async fn save_and_broadcast_event(event: EventWithMetadata, app_state: AppState) -> Result<()> {
-    // Save the event to the database
-    let conn = DB.get()?;
-    save_event_to_db(&conn, &event)?;
-
-    // Apply the event to the library
+    save_and_broadcast_events(vec![event], app_state).await
+}
+
+/// Save several events in one transaction (so either all of them are saved or none are),
+/// apply them together and then tell clients about each item they changed, once
+async fn save_and_broadcast_events(
+    events: Vec<EventWithMetadata>,
+    app_state: AppState,
+) -> Result<()> {
+    let raw_events = events
+        .iter()
+        .map(EventWithMetadata::to_raw)
+        .collect::<Result<Vec<_>>>()?;
+    // Lock before saving so that concurrent edits are applied in the order they're saved, and
+    // what's in memory matches a replay of the log
     let mut library = app_state.library.write().await;
-    library.apply(&event);
+    app_state.events.append_batch(&raw_events).await?;
 
-    match &event.event {
-        Event::LibraryItemDeletedEvent => {
-            info!(id = ?event.id, "Broadcasting item deletion");
-            let _ = app_state.update_tx.send(FrontendUpdate::Delete {
-                id: event.aggregate_id,
-            });
+    for event in &events {
+        library.apply(event);
+    }
+
+    broadcast_library_items(
+        &app_state,
+        &library,
+        events.iter().map(|event| event.aggregate_id),
+    );
+    Ok(())
+}
+
+/// Save and apply an edit made by a user, remembering how to undo it for their session
+async fn save_and_broadcast_edit(
+    item_id: Uuid,
+    events: Vec<Event>,
+    app_state: AppState,
+    session: Uuid,
+) -> Result<()> {
+    let envelopes = events
+        .iter()
+        .map(|event| EventWithMetadata::new(item_id, event.clone()))
+        .collect::<Result<Vec<_>>>()?;
+
+    // Each event's compensation depends on the state left by the events before it, so play
+    // them out on a copy of the item
+    let mut scratch = Library::new();
+    {
+        let library = app_state.library.read().await;
+        if let Some(item) = library.items.get(&item_id) {
+            scratch.items.insert(item_id, item.clone());
         }
-        _ => {
-            if let Some(updated_item) = library.items.get(&event.aggregate_id) {
-                info!(id = ?event.id, "Broadcasting updated item, event type: {:?}", event.event);
-                let response = LibraryItemResponse::from_item(updated_item, &app_state.storage);
-                let _ = app_state
-                    .update_tx
-                    .send(FrontendUpdate::Update { item: Box::new(response) });
-            }
+        if let Some(item) = library.deleted_items.get(&item_id) {
+            scratch.deleted_items.insert(item_id, item.clone());
         }
     }
+    let mut inverse = Vec::new();
+    for event in &envelopes {
+        // undoing several events means undoing them in reverse order
+        inverse.splice(0..0, scratch.compensating_events(event));
+        scratch.apply(event);
+    }
+
+    save_and_broadcast_events(envelopes, app_state.clone()).await?;
+
+    if !inverse.is_empty() {
+        app_state.undo.record(
+            session,
+            undo::UndoableEdit {
+                item_id,
+                forward: events,
+                inverse,
+            },
+        );
+    }
     Ok(())
 }
 
+/// Undo this session's most recent edit by saving events that reverse it
+async fn undo_handler(
+    State(app_state): State<AppState>,
+    cookies: Cookies,
+) -> Result<impl IntoResponse, AppError> {
+    let session = undo::undo_session(&cookies);
+    let Some(edit) = app_state.undo.take_undo(session) else {
+        return Ok((StatusCode::CONFLICT, "Nothing to undo"));
+    };
+
+    let events = edit
+        .inverse
+        .iter()
+        .map(|event| EventWithMetadata::new(edit.item_id, event.clone()))
+        .collect::<Result<Vec<_>>>()?;
+    save_and_broadcast_events(events, app_state.clone()).await?;
+    app_state.undo.undone(session, edit);
+
+    Ok((StatusCode::OK, "Undone"))
+}
+
+/// Redo this session's most recently undone edit
+async fn redo_handler(
+    State(app_state): State<AppState>,
+    cookies: Cookies,
+) -> Result<impl IntoResponse, AppError> {
+    let session = undo::undo_session(&cookies);
+    let Some(edit) = app_state.undo.take_redo(session) else {
+        return Ok((StatusCode::CONFLICT, "Nothing to redo"));
+    };
+
+    let events = edit
+        .forward
+        .iter()
+        .map(|event| EventWithMetadata::new(edit.item_id, event.clone()))
+        .collect::<Result<Vec<_>>>()?;
+    save_and_broadcast_events(events, app_state.clone()).await?;
+    app_state.undo.redone(session, edit);
+
+    Ok((StatusCode::OK, "Redone"))
+}
+
+/// Tell connected clients about items that (already applied) events changed. Each item is
+/// only sent once, however many events there were for it.
+fn broadcast_library_items(
+    app_state: &AppState,
+    library: &Library,
+    item_ids: impl IntoIterator<Item = Uuid>,
+) {
+    for id in item_ids.into_iter().collect::<IndexSet<_>>() {
+        if let Some(updated_item) = library.items.get(&id) {
+            info!(%id, "Broadcasting updated item");
+            let response = LibraryItemResponse::from_item(updated_item, &app_state.storage);
+            let _ = app_state
+                .update_tx
+                .send(FrontendUpdate::Update { item: Box::new(response) });
+        } else if library.deleted_items.contains_key(&id) {
+            info!(%id, "Broadcasting item deletion");
+            let _ = app_state.update_tx.send(FrontendUpdate::Delete { id });
+        }
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 struct AddItemRequest {
     file_path: String,
@@ -1351,7 +2295,12 @@ async fn add_item_handler(
     info!(file_path = &request.file_path, "Adding new item");
     let item_id = Uuid::new_v4();
 
-    let metadata = match llm::extract_song_metadata(&request.file_path).await {
+    let metadata = match llm::extract_song_metadata(
+        app_state.config.openai.api_key.as_deref(),
+        &request.file_path,
+    )
+    .await
+    {
         Ok(metadata) => metadata,
         Err(e) => {
             warn!(
@@ -1372,46 +2321,48 @@ async fn add_item_handler(
         album: metadata.album,
         track_number: None, // LLM extraction doesn't provide track number
         file_path: request.file_path,
+        duration: None,
+        year: None,
+        genre: None,
     };
     let event_with_metadata = EventWithMetadata::new(item_id, event)?;
 
-    // Save the event to the database
-    let conn = DB.get()?;
-    save_event_to_db(&conn, &event_with_metadata)?;
-
-    // Apply the event to the library
-    let mut library = app_state.library.write().await;
-    library.apply(&event_with_metadata);
-
-    if let Some(updated_item) = library.items.get(&item_id) {
-        // Broadcast the new item to all connected clients
-        let response = LibraryItemResponse::from_item(updated_item, &app_state.storage);
-        let _ = app_state
-            .update_tx
-            .send(FrontendUpdate::Update { item: Box::new(response) });
-    }
+    save_and_broadcast_event(event_with_metadata, app_state).await?;
 
     Ok(StatusCode::CREATED)
 }
 
 #[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
 struct PlayRequest {
     id: uuid::Uuid,
+    /// Assumed to be the browser, which is the only client that predates this
+    #[serde(default)]
+    source: Option<PlaySource>,
+    #[serde(default)]
+    seconds_listened: Option<u32>,
+    #[serde(default)]
+    completed: Option<bool>,
 }
 
 async fn play_handler(
     State(app_state): State<AppState>,
     JsonExtractor(request): JsonExtractor<PlayRequest>,
 ) -> Result<impl IntoResponse, AppError> {
-    let event = Event::LibraryItemPlayedEvent;
+    let event = Event::LibraryItemPlayedEvent(PlayDetails {
+        source: Some(request.source.unwrap_or(PlaySource::Browser)),
+        seconds_listened: request.seconds_listened,
+        completed: request.completed,
+    });
     let event_with_metadata = EventWithMetadata::new(request.id, event)?;
 
+    // Lock before saving so that concurrent edits are applied in the order they're saved
+    let mut library = app_state.library.write().await;
+
     // Save the event to the database
-    let conn = DB.get()?;
-    save_event_to_db(&conn, &event_with_metadata)?;
+    app_state.events.append(&event_with_metadata.to_raw()?).await?;
 
     // Apply the event to the library
-    let mut library = app_state.library.write().await;
     library.apply(&event_with_metadata);
 
     if let Some(updated_item) = library.items.get(&request.id) {
@@ -1434,12 +2385,13 @@ struct DeleteRequest {
 
 async fn delete_handler(
     State(app_state): State<AppState>,
+    cookies: Cookies,
     JsonExtractor(request): JsonExtractor<DeleteRequest>,
 ) -> Result<impl IntoResponse, AppError> {
     let event = Event::LibraryItemDeletedEvent;
-    let event_with_metadata = EventWithMetadata::new(request.id, event)?;
 
-    save_and_broadcast_event(event_with_metadata, app_state).await?;
+    save_and_broadcast_edit(request.id, vec![event], app_state, undo::undo_session(&cookies))
+        .await?;
 
     Ok(StatusCode::OK)
 }
@@ -1447,6 +2399,9 @@ async fn delete_handler(
 #[derive(Debug, Deserialize)]
 struct AddBookmarkRequest {
     position: f64,
+    /// Makes this a range bookmark, marking the section from `position` to here
+    #[serde(default)]
+    end_position: Option<f64>,
     #[serde(default)]
     label: Option<String>,
 }
@@ -1455,18 +2410,86 @@ struct AddBookmarkRequest {
 async fn add_bookmark_handler(
     State(app_state): State<AppState>,
     axum::extract::Path(id): axum::extract::Path<Uuid>,
+    cookies: Cookies,
     JsonExtractor(request): JsonExtractor<AddBookmarkRequest>,
 ) -> Result<impl IntoResponse, AppError> {
+    let Some((position, end_position)) = bookmark_range(request.position, request.end_position)
+    else {
+        return Ok((StatusCode::BAD_REQUEST, INVALID_BOOKMARK_RANGE).into_response());
+    };
     let event = Event::LibraryItemBookmarkAddedEvent {
         bookmark_id: Uuid::new_v4(),
-        position: Duration::from_secs_f64(request.position),
+        position,
+        end_position,
         label: clean_bookmark_label(request.label),
     };
-    let event_with_metadata = EventWithMetadata::new(id, event)?;
 
-    save_and_broadcast_event(event_with_metadata, app_state).await?;
+    save_and_broadcast_edit(id, vec![event], app_state, undo::undo_session(&cookies)).await?;
 
-    Ok(StatusCode::CREATED)
+    Ok(StatusCode::CREATED.into_response())
+}
+
+#[derive(Debug, Deserialize)]
+struct MoveBookmarkRequest {
+    position: f64,
+    /// Leaving this out turns a range bookmark back into a plain one
+    #[serde(default)]
+    end_position: Option<f64>,
+}
+
+/// Move a bookmark (or change the section it marks), keeping its emoji and label
+#[instrument(skip(app_state))]
+async fn move_bookmark_handler(
+    State(app_state): State<AppState>,
+    Path((item_id, bookmark_id)): Path<(Uuid, Uuid)>,
+    cookies: Cookies,
+    JsonExtractor(request): JsonExtractor<MoveBookmarkRequest>,
+) -> Result<Response, AppError> {
+    let Some((position, end_position)) = bookmark_range(request.position, request.end_position)
+    else {
+        return Ok((StatusCode::BAD_REQUEST, INVALID_BOOKMARK_RANGE).into_response());
+    };
+    let exists = app_state
+        .library
+        .read()
+        .await
+        .items
+        .get(&item_id)
+        .is_some_and(|item| item.bookmarks.contains_key(&bookmark_id));
+    if !exists {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    }
+
+    let event = Event::LibraryItemBookmarkMovedEvent {
+        bookmark_id,
+        position,
+        end_position,
+    };
+    save_and_broadcast_edit(
+        item_id,
+        vec![event],
+        app_state,
+        undo::undo_session(&cookies),
+    )
+    .await?;
+    Ok(StatusCode::OK.into_response())
+}
+
+const INVALID_BOOKMARK_RANGE: &str =
+    "Bookmark positions must not be negative, and ranges must end after they start";
+
+/// Convert a bookmark's position (and end, for ranges) from seconds
+fn bookmark_range(
+    position: f64,
+    end_position: Option<f64>,
+) -> Option<(Duration, Option<Duration>)> {
+    let position = Duration::try_from_secs_f64(position).ok()?;
+    let end_position = match end_position.map(Duration::try_from_secs_f64) {
+        Some(Ok(end)) if end > position => Some(end),
+        Some(_) => return None,
+        None => None,
+    };
+    Some((position, end_position))
 }
 
 #[derive(Debug, Deserialize)]
@@ -1479,6 +2502,7 @@ struct UpdateBookmarkRequest {
 async fn update_bookmark_handler(
     State(app_state): State<AppState>,
     Path((item_id, bookmark_id)): Path<(Uuid, Uuid)>,
+    cookies: Cookies,
     JsonExtractor(request): JsonExtractor<UpdateBookmarkRequest>,
 ) -> Result<impl IntoResponse, AppError> {
     let existing_emoji = {
@@ -1493,24 +2517,19 @@ async fn update_bookmark_handler(
         return Ok(StatusCode::NOT_FOUND);
     };
 
-    let label_event = Event::LibraryItemBookmarkLabelChangedEvent {
+    let mut events = vec![Event::LibraryItemBookmarkLabelChangedEvent {
         bookmark_id,
         label: clean_bookmark_label(request.label),
-    };
-    save_and_broadcast_event(
-        EventWithMetadata::new(item_id, label_event)?,
-        app_state.clone(),
-    )
-    .await?;
+    }];
 
     let emoji = request.emoji.trim();
     if !emoji.is_empty() && emoji != existing_emoji {
-        let emoji_event = Event::LibraryItemBookmarkSetEmojiEvent {
+        events.push(Event::LibraryItemBookmarkSetEmojiEvent {
             bookmark_id,
             emoji: emoji.to_string(),
-        };
-        save_and_broadcast_event(EventWithMetadata::new(item_id, emoji_event)?, app_state).await?;
+        });
     }
+    save_and_broadcast_edit(item_id, events, app_state, undo::undo_session(&cookies)).await?;
 
     Ok(StatusCode::OK)
 }
@@ -1519,6 +2538,7 @@ async fn update_bookmark_handler(
 async fn delete_bookmark_handler(
     State(app_state): State<AppState>,
     Path((item_id, bookmark_id)): Path<(Uuid, Uuid)>,
+    cookies: Cookies,
 ) -> Result<impl IntoResponse, AppError> {
     let bookmark_exists = {
         let library = app_state.library.read().await;
@@ -1532,7 +2552,8 @@ async fn delete_bookmark_handler(
     }
 
     let event = Event::LibraryItemBookmarkDeletedEvent { bookmark_id };
-    save_and_broadcast_event(EventWithMetadata::new(item_id, event)?, app_state).await?;
+    save_and_broadcast_edit(item_id, vec![event], app_state, undo::undo_session(&cookies))
+        .await?;
 
     Ok(StatusCode::NO_CONTENT)
 }
@@ -1565,6 +2586,26 @@ fn create_update_event(field: &str, value: &str) -> Result<Event> {
             };
             Ok(Event::LibraryItemTrackNumberChangedEvent { new_track_number })
         }
+        "duration" => {
+            let new_duration = if value.is_empty() {
+                None
+            } else {
+                let seconds = value.parse::<f64>().context("Invalid duration")?;
+                Some(Duration::try_from_secs_f64(seconds).context("Invalid duration")?)
+            };
+            Ok(Event::LibraryItemDurationChangedEvent { new_duration })
+        }
+        "year" => {
+            let new_year = if value.is_empty() {
+                None
+            } else {
+                Some(value.parse::<u32>().context("Invalid year")?)
+            };
+            Ok(Event::LibraryItemYearChangedEvent { new_year })
+        }
+        "genre" => Ok(Event::LibraryItemGenreChangedEvent {
+            new_genre: value.to_string(),
+        }),
         _ => bail!("Invalid field: {}", field),
     }
 }
@@ -1579,9 +2620,40 @@ async fn login_handler() -> impl IntoResponse {
     Html(rendered)
 }
 
+fn session_cookie_name(app_state: &AppState) -> String {
+    format!("{SESSION_COOKIE_PREFIX}{}", app_state.library_settings.name)
+}
+
+// The session cookie is just the hashed password (salted with the library name, so a session
+// for one library doesn't work for another that happens to share its password)
+fn session_token(app_state: &AppState) -> String {
+    let library = &app_state.library_settings;
+    hash_with_rotating_salt(&format!("{}:{}", library.name, library.password()))
+}
+
+fn has_session(app_state: &AppState, cookies: &Cookies) -> bool {
+    let has_cookie = |name: &str, token: String| {
+        cookies
+            .get(name)
+            .is_some_and(|cookie| cookie.value() == token)
+    };
+    let is_default_library = app_state.library_settings.name == app_state.config.server.library;
+    has_cookie(&session_cookie_name(app_state), session_token(app_state))
+        || (is_default_library
+            && has_cookie(
+                LEGACY_SESSION_COOKIE_NAME,
+                hash_with_rotating_salt(app_state.library_settings.password()),
+            ))
+}
+
 // Check that the user has a valid session cookie... which is just the hashed password
 // Pretty weak authentication but this is a music library for one, not a bank
-async fn auth(cookies: Cookies, req: Request<Body>, next: Next) -> Result<Response, StatusCode> {
+async fn auth(
+    State(app_state): State<AppState>,
+    cookies: Cookies,
+    req: Request<Body>,
+    next: Next,
+) -> Result<Response, StatusCode> {
     // Bypass auth if --no-auth flag was set
     if *NO_AUTH.get().unwrap_or(&false) {
         return Ok(next.run(req).await);
@@ -1591,16 +2663,19 @@ async fn auth(cookies: Cookies, req: Request<Body>, next: Next) -> Result<Respon
         return Ok(next.run(req).await);
     }
 
-    if let Some(cookie) = cookies.get(SESSION_COOKIE_NAME) {
-        if cookie.value() == *PASSWORD_HASH {
-            return Ok(next.run(req).await);
-        }
+    if has_session(&app_state, &cookies) {
+        return Ok(next.run(req).await);
     }
 
-    Ok(Redirect::to("/login").into_response())
+    let base = req
+        .extensions()
+        .get::<NestedPath>()
+        .map_or("", |path| path.as_str());
+    Ok(Redirect::to(&format!("{base}/login")).into_response())
 }
 
 async fn api_session_auth(
+    State(app_state): State<AppState>,
     cookies: Cookies,
     req: Request<Body>,
     next: Next,
@@ -1609,10 +2684,7 @@ async fn api_session_auth(
         return Ok(next.run(req).await);
     }
 
-    if cookies
-        .get(SESSION_COOKIE_NAME)
-        .is_some_and(|cookie| cookie.value() == *PASSWORD_HASH)
-    {
+    if has_session(&app_state, &cookies) {
         return Ok(next.run(req).await);
     }
 
@@ -1620,12 +2692,13 @@ async fn api_session_auth(
 }
 
 async fn api_key_auth(
+    State(app_state): State<AppState>,
     headers: HeaderMap,
     req: Request<Body>,
     next: Next,
 ) -> Result<Response, StatusCode> {
     if let Some(api_key) = headers.get("X-API-Key") {
-        if api_key == API_KEY {
+        if api_key == app_state.library_settings.api_key() {
             return Ok(next.run(req).await);
         }
     }
@@ -1634,21 +2707,29 @@ async fn api_key_auth(
 
 #[debug_handler]
 async fn login_post_handler(
+    State(app_state): State<AppState>,
+    nested_path: Option<Extension<NestedPath>>,
     cookies: Cookies,
     Form(params): Form<std::collections::HashMap<String, String>>,
 ) -> impl IntoResponse {
+    let base = nested_path
+        .as_ref()
+        .map_or("", |Extension(path)| path.as_str());
     if let Some(password) = params.get("password") {
-        if password == PASSWORD {
-            let mut cookie = Cookie::new(SESSION_COOKIE_NAME, PASSWORD_HASH.as_str());
+        if password == app_state.library_settings.password() {
+            let mut cookie = Cookie::new(
+                session_cookie_name(&app_state),
+                session_token(&app_state),
+            );
             cookie.set_http_only(true);
             cookie.set_path("/");
             let one_year = tower_cookies::cookie::time::Duration::seconds(60 * 60 * 24 * 365);
             cookie.set_max_age(Some(one_year));
             cookies.add(cookie);
-            return Redirect::to("/").into_response();
+            return Redirect::to(if base.is_empty() { "/" } else { base }).into_response();
         }
     }
-    Redirect::to("/login").into_response()
+    Redirect::to(&format!("{base}/login")).into_response()
 }
 
 struct AppError(anyhow::Error);
@@ -1678,10 +2759,923 @@ impl fmt::Debug for AppError {
     }
 }
 
+/// The event for adding a library item that has nothing but a name, for tests
+#[cfg(test)]
+fn created(name: &str) -> Event {
+    Event::LibraryItemCreatedEvent {
+        name: name.to_string(),
+        artist: None,
+        album: None,
+        track_number: None,
+        file_path: format!("{name}.mp3"),
+        duration: None,
+        year: None,
+        genre: None,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    async fn test_app_state() -> AppState {
+        let storage = S3Storage::new(
+            "https://s3.example.com",
+            "reitunes",
+            Some("music"),
+            "ca-east-tor",
+            "test-key",
+            "test-secret",
+        )
+        .await
+        .unwrap();
+        AppState {
+            library: Arc::new(RwLock::new(Library::new())),
+            playlists: Arc::new(RwLock::new(PlaylistStore::new())),
+            update_tx: broadcast::channel(16).0,
+            storage: Arc::new(storage),
+            sonos: None,
+            cloud_queues: Arc::new(cloud_queue::CloudQueueStore::with_base_url(
+                "https://reitunes.example.com/",
+            )),
+            events: Arc::new(InMemoryEventStore::new()),
+            time_travel: Arc::new(time_travel::TimeTravelCache::new()),
+            undo: Arc::new(undo::UndoStacks::new()),
+            config: Arc::new(config::Config::default()),
+            library_settings: Arc::new(config::Config::default().library(None).unwrap()),
+        }
+    }
+
+    async fn get(app: &Router, uri: &str, header: (&str, &str)) -> Response {
+        let request = Request::get(uri)
+            .header(header.0, header.1)
+            .body(Body::empty())
+            .unwrap();
+        tower::ServiceExt::oneshot(app.clone(), request)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn sessions_and_api_keys_only_open_their_own_library() {
+        let library = |name: &str| config::LibrarySettings {
+            name: name.to_string(),
+            db_path: format!("{name}.db"),
+            prefix: Some(name.to_string()),
+            password: Some(format!("{name} password")),
+            api_key: Some(format!("{name} key")),
+        };
+        let music = AppState {
+            library_settings: Arc::new(library("music")),
+            ..test_app_state().await
+        };
+        let podcasts = AppState {
+            library_settings: Arc::new(library("podcasts")),
+            ..test_app_state().await
+        };
+        let music_session = format!("{}={}", session_cookie_name(&music), session_token(&music));
+        let podcasts_session = format!(
+            "{}={}",
+            session_cookie_name(&podcasts),
+            session_token(&podcasts)
+        );
+        let app = app_router(vec![music, podcasts], ViteServe::new(Assets::boxed()));
+
+        for (uri, header, expected) in [
+            (
+                "/api/items",
+                ("cookie", music_session.as_str()),
+                StatusCode::OK,
+            ),
+            (
+                "/l/music/api/items",
+                ("cookie", music_session.as_str()),
+                StatusCode::OK,
+            ),
+            (
+                "/l/podcasts/api/items",
+                ("cookie", podcasts_session.as_str()),
+                StatusCode::OK,
+            ),
+            (
+                "/api/items",
+                ("cookie", podcasts_session.as_str()),
+                StatusCode::UNAUTHORIZED,
+            ),
+            (
+                "/l/podcasts/api/items",
+                ("cookie", music_session.as_str()),
+                StatusCode::UNAUTHORIZED,
+            ),
+            (
+                "/l/podcasts/api/events",
+                ("x-api-key", "podcasts key"),
+                StatusCode::OK,
+            ),
+            (
+                "/api/events",
+                ("x-api-key", "podcasts key"),
+                StatusCode::UNAUTHORIZED,
+            ),
+            (
+                "/l/music/api/events",
+                ("x-api-key", "podcasts key"),
+                StatusCode::UNAUTHORIZED,
+            ),
+            (
+                "/l/audiobooks/api/items",
+                ("cookie", music_session.as_str()),
+                StatusCode::NOT_FOUND,
+            ),
+        ] {
+            let status = get(&app, uri, header).await.status();
+            assert_eq!(status, expected, "{uri} with {header:?}");
+        }
+
+        // the UI sends people to their own library's login page
+        let response = get(&app, "/l/podcasts", ("cookie", music_session.as_str())).await;
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+        assert_eq!(response.headers()["location"], "/l/podcasts/login");
+        let response = get(&app, "/l/podcasts/", ("cookie", music_session.as_str())).await;
+        assert_eq!(response.headers()["location"], "/l/podcasts");
+    }
+
+    #[tokio::test]
+    async fn sessions_from_before_there_were_libraries_only_open_the_default_one() {
+        let password = |library: config::LibrarySettings| config::LibrarySettings {
+            password: Some("shared password".to_string()),
+            ..library
+        };
+        let default = AppState {
+            library_settings: Arc::new(password(config::Config::default().library(None).unwrap())),
+            ..test_app_state().await
+        };
+        let podcasts = AppState {
+            library_settings: Arc::new(password(config::LibrarySettings {
+                name: "podcasts".to_string(),
+                db_path: "podcasts.db".to_string(),
+                prefix: Some("podcasts".to_string()),
+                password: None,
+                api_key: None,
+            })),
+            ..test_app_state().await
+        };
+        let legacy_session = format!(
+            "{LEGACY_SESSION_COOKIE_NAME}={}",
+            hash_with_rotating_salt("shared password")
+        );
+        let app = app_router(vec![default, podcasts], ViteServe::new(Assets::boxed()));
+
+        let response = get(&app, "/api/items", ("cookie", &legacy_session)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = get(&app, "/l/podcasts/api/items", ("cookie", &legacy_session)).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn all_events_are_library_items_unless_every_type_is_asked_for() {
+        let app_state = test_app_state().await;
+        let item_id = Uuid::new_v4();
+        let played = EventWithMetadata::new(
+            item_id,
+            Event::LibraryItemPlayedEvent(PlayDetails::default()),
+        )
+        .unwrap()
+        .to_raw()
+        .unwrap();
+        let created = PlaylistEventWithMetadata::new(
+            Uuid::new_v4(),
+            PlaylistEvent::PlaylistCreatedEvent {
+                name: "Mix".to_string(),
+            },
+        )
+        .unwrap()
+        .to_raw()
+        .unwrap();
+        // from a newer version
+        let smart_playlist = RawEvent {
+            id: Uuid::new_v4(),
+            aggregate_type: "SmartPlaylist".to_string(),
+            ..created.clone()
+        };
+        app_state
+            .events
+            .append_batch(&[played, created, smart_playlist])
+            .await
+            .unwrap();
+
+        let event_count = |aggregate_type: Option<&str>| {
+            let app_state = app_state.clone();
+            let aggregate_type = aggregate_type.map(str::to_string);
+            async move {
+                let response =
+                    all_events_handler(State(app_state), Query(AllEventsQuery { aggregate_type }))
+                        .await
+                        .unwrap();
+                assert_eq!(response.status(), StatusCode::OK);
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                    .await
+                    .unwrap();
+                serde_json::from_slice::<Vec<serde_json::Value>>(&body)
+                    .unwrap()
+                    .len()
+            }
+        };
+        assert_eq!(event_count(None).await, 1);
+        assert_eq!(event_count(Some("Playlist")).await, 1);
+        assert_eq!(event_count(Some("all")).await, 2);
+
+        let response = all_events_handler(
+            State(app_state),
+            Query(AllEventsQuery {
+                aggregate_type: Some("Nonsense".to_string()),
+            }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn pushed_events_are_stored_once_and_applied() {
+        let app_state = test_app_state().await;
+        let item_id = Uuid::new_v4();
+        let events = vec![
+            EventWithMetadata::new(item_id, created("Pushed"))
+                .unwrap()
+                .to_raw()
+                .unwrap(),
+            EventWithMetadata::new(
+                item_id,
+                Event::LibraryItemPlayedEvent(PlayDetails::default()),
+            )
+            .unwrap()
+            .to_raw()
+            .unwrap(),
+        ];
+        // from a newer version; it's kept for other clients rather than holding up the push
+        let smart_playlist = RawEvent {
+            id: Uuid::new_v4(),
+            aggregate_id: Uuid::new_v4(),
+            aggregate_type: "SmartPlaylist".to_string(),
+            ..events[0].clone()
+        };
+        let events = [events, vec![smart_playlist]].concat();
+
+        for expected_saved_count in [3, 0] {
+            let response = push_events_handler(
+                State(app_state.clone()),
+                JsonExtractor(events.clone()),
+            )
+            .await
+            .unwrap()
+            .into_response();
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            let response: PushResponse = serde_json::from_slice(&body).unwrap();
+            assert_eq!(response.saved_count, expected_saved_count);
+        }
+
+        assert_eq!(app_state.library.read().await.items[&item_id].play_count, 1);
+        assert_eq!(app_state.events.load_since(0, 10).unwrap().events.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn pushed_events_older_than_applied_ones_are_replayed_in_hlc_order() {
+        let app_state = test_app_state().await;
+        let item_id = Uuid::new_v4();
+        let created = EventWithMetadata::new(item_id, created("Original")).unwrap();
+        // made on a client that was offline, so it's older than the server's rename
+        let mut offline_rename = EventWithMetadata::new(
+            item_id,
+            Event::LibraryItemNameChangedEvent {
+                new_name: "Offline".to_string(),
+            },
+        )
+        .unwrap();
+        offline_rename.hlc = Hlc {
+            millis: created.hlc.millis,
+            counter: created.hlc.counter + 1,
+        };
+        save_and_broadcast_event(created, app_state.clone())
+            .await
+            .unwrap();
+        let online_rename = EventWithMetadata::new(
+            item_id,
+            Event::LibraryItemNameChangedEvent {
+                new_name: "Online".to_string(),
+            },
+        )
+        .unwrap();
+        save_and_broadcast_event(online_rename, app_state.clone())
+            .await
+            .unwrap();
+
+        push_events_handler(
+            State(app_state.clone()),
+            JsonExtractor(vec![offline_rename.to_raw().unwrap()]),
+        )
+        .await
+        .unwrap();
+
+        let library = app_state.library.read().await;
+        assert_eq!(library.items[&item_id].name, "Online");
+        let from_store = Library::build_from_events(
+            app_state
+                .events
+                .load_by_aggregate_id(item_id)
+                .unwrap()
+                .into_iter()
+                .map(|raw| EventWithMetadata::from_raw(raw).unwrap())
+                .collect(),
+        );
+        assert_eq!(from_store.items[&item_id].name, "Online");
+    }
+
+    #[tokio::test]
+    async fn items_can_be_rated_then_filtered_and_sorted_by_rating() {
+        let app_state = test_app_state().await;
+        let mut ids = Vec::new();
+        for (name, rating) in [("Bad", 1), ("Best", 5), ("Good", 4), ("Unrated", 0)] {
+            let id = Uuid::new_v4();
+            let created = created(name);
+            save_and_broadcast_event(
+                EventWithMetadata::new(id, created).unwrap(),
+                app_state.clone(),
+            )
+            .await
+            .unwrap();
+            let request = RatingRequest { rating };
+            let response = rating_handler(
+                State(app_state.clone()),
+                Path(id),
+                Cookies::default(),
+                JsonExtractor(request),
+            )
+            .await
+            .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            ids.push(id);
+        }
+
+        let too_many_stars = rating_handler(
+            State(app_state.clone()),
+            Path(ids[0]),
+            Cookies::default(),
+            JsonExtractor(RatingRequest { rating: 6 }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(too_many_stars.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(app_state.library.read().await.items[&ids[0]].rating, 1);
+
+        let query = ItemsQuery {
+            as_of: None,
+            min_rating: Some(4),
+            sort: Some(ItemSort::Rating),
+        };
+        let response = items_handler(State(app_state), Query(query))
+            .await
+            .unwrap()
+            .into_response();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let items: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let names: Vec<_> = items
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|item| (item["name"].as_str().unwrap(), item["rating"].as_u64().unwrap()))
+            .collect();
+        assert_eq!(names, vec![("Best", 5), ("Good", 4)]);
+    }
+
+    #[tokio::test]
+    async fn resume_positions_are_only_saved_when_they_move_far_enough() {
+        let app_state = test_app_state().await;
+        let id = Uuid::new_v4();
+        let created = Event::LibraryItemCreatedEvent {
+            name: "Long Mix".to_string(),
+            artist: None,
+            album: None,
+            track_number: None,
+            file_path: "mix.mp3".to_string(),
+            duration: Some(Duration::from_secs(3600)),
+            year: None,
+            genre: None,
+        };
+        save_and_broadcast_event(
+            EventWithMetadata::new(id, created).unwrap(),
+            app_state.clone(),
+        )
+        .await
+        .unwrap();
+
+        for position in [120.0, 125.0, 130.0, 155.5] {
+            let response = resume_position_handler(
+                State(app_state.clone()),
+                Path(id),
+                JsonExtractor(ResumePositionRequest { position }),
+            )
+            .await
+            .unwrap();
+            assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        }
+        let invalid = resume_position_handler(
+            State(app_state.clone()),
+            Path(id),
+            JsonExtractor(ResumePositionRequest { position: -1.0 }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(invalid.status(), StatusCode::BAD_REQUEST);
+
+        let saved = app_state.events.load_by_aggregate_id(id).unwrap();
+        assert_eq!(saved.len(), 3);
+        let item = app_state.library.read().await.items[&id].clone();
+        assert_eq!(item.resume_position, Some(Duration::from_secs_f64(155.5)));
+        let response = LibraryItemResponse::from_item(&item, &app_state.storage);
+        assert_eq!(
+            serde_json::to_value(response).unwrap()["resume_position"],
+            155.5
+        );
+    }
+
+    #[tokio::test]
+    async fn cloud_queue_positions_are_saved_to_the_library_the_track_is_from() {
+        let library = |name: &str| config::LibrarySettings {
+            name: name.to_string(),
+            db_path: format!("{name}.db"),
+            prefix: Some(name.to_string()),
+            password: None,
+            api_key: None,
+        };
+        let music = AppState {
+            library_settings: Arc::new(library("music")),
+            ..test_app_state().await
+        };
+        let podcasts = AppState {
+            library_settings: Arc::new(library("podcasts")),
+            cloud_queues: music.cloud_queues.clone(),
+            ..test_app_state().await
+        };
+        let id = Uuid::new_v4();
+        let created = Event::LibraryItemCreatedEvent {
+            name: "Episode 1".to_string(),
+            artist: None,
+            album: None,
+            track_number: None,
+            file_path: "episode-1.mp3".to_string(),
+            duration: Some(Duration::from_secs(3600)),
+            year: None,
+            genre: None,
+        };
+        save_and_broadcast_event(
+            EventWithMetadata::new(id, created).unwrap(),
+            podcasts.clone(),
+        )
+        .await
+        .unwrap();
+        let request = PrepareCloudQueueRequest {
+            item_ids: vec![id],
+            start_item_id: None,
+        };
+        let prepared = prepare_cloud_queue(&podcasts, &request).await.unwrap();
+        let parameters = podcasts
+            .cloud_queues
+            .playback_parameters(prepared.queue_id)
+            .unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            axum::http::header::AUTHORIZATION,
+            parameters.http_authorization.parse().unwrap(),
+        );
+        let report: cloud_queue::TimePlayedReport = serde_json::from_value(serde_json::json!({
+            "items": [{"id": prepared.start_item_id, "positionMillis": 120000}]
+        }))
+        .unwrap();
+
+        let status = cloud_queue_time_played_handler(
+            State(Libraries(Arc::new(vec![music.clone(), podcasts.clone()]))),
+            Path(prepared.queue_id),
+            headers,
+            JsonExtractor(report),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let item = podcasts.library.read().await.items[&id].clone();
+        assert_eq!(item.resume_position, Some(Duration::from_secs(120)));
+        assert!(music.events.load_by_aggregate_id(id).unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn tags_can_be_counted_and_merged_across_items() {
+        let app_state = test_app_state().await;
+        let [mix, podcast] = [Uuid::new_v4(), Uuid::new_v4()];
+        for (id, tags) in [(mix, vec!["dj mix ", "workout"]), (podcast, vec!["cardio"])] {
+            let created = created(&id.to_string());
+            save_and_broadcast_event(
+                EventWithMetadata::new(id, created).unwrap(),
+                app_state.clone(),
+            )
+            .await
+            .unwrap();
+            for tag in tags {
+                let request = AddTagRequest {
+                    tag: tag.to_string(),
+                };
+                add_tag_handler(
+                    State(app_state.clone()),
+                    Path(id),
+                    Cookies::default(),
+                    JsonExtractor(request),
+                )
+                .await
+                .unwrap();
+            }
+        }
+
+        let request = MergeTagsRequest {
+            from: vec!["workout".to_string(), "cardio".to_string()],
+            into: "exercise".to_string(),
+        };
+        let response = merge_tags_handler(State(app_state.clone()), JsonExtractor(request))
+            .await
+            .unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let merged: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(merged["changed_items"], 2);
+
+        let Json(tags) = tags_handler(State(app_state.clone())).await;
+        let tags: Vec<_> = tags
+            .iter()
+            .map(|tag| (tag.name.as_str(), tag.count))
+            .collect();
+        assert_eq!(tags, vec![("dj mix", 1), ("exercise", 2)]);
+
+        let removed = remove_tag_handler(
+            State(app_state.clone()),
+            Path((podcast, "dj mix".to_string())),
+            Cookies::default(),
+        )
+        .await
+        .unwrap()
+        .into_response();
+        assert_eq!(removed.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn deletes_can_be_undone_and_redone() {
+        let app_state = test_app_state().await;
+        let cookies = Cookies::default();
+        let item_id = Uuid::new_v4();
+        save_and_broadcast_event(
+            EventWithMetadata::new(item_id, created("Keep me")).unwrap(),
+            app_state.clone(),
+        )
+        .await
+        .unwrap();
+        let session = undo::undo_session(&cookies);
+        save_and_broadcast_edit(
+            item_id,
+            vec![Event::LibraryItemDeletedEvent],
+            app_state.clone(),
+            session,
+        )
+        .await
+        .unwrap();
+        assert!(!app_state.library.read().await.items.contains_key(&item_id));
+
+        let mut updates = app_state.update_tx.subscribe();
+        undo_handler(State(app_state.clone()), cookies.clone())
+            .await
+            .unwrap();
+        assert_eq!(app_state.library.read().await.items[&item_id].name, "Keep me");
+        assert!(matches!(
+            updates.try_recv().unwrap(),
+            FrontendUpdate::Update { item } if item.id == item_id
+        ));
+
+        redo_handler(State(app_state.clone()), cookies.clone())
+            .await
+            .unwrap();
+        assert!(!app_state.library.read().await.items.contains_key(&item_id));
+
+        // create + delete + restore + delete, all in the event log
+        assert_eq!(app_state.events.load_since(0, 10).unwrap().events.len(), 4);
+    }
+
+    #[tokio::test]
+    async fn item_history_lists_changes_oldest_first() {
+        let app_state = test_app_state().await;
+        let item_id = Uuid::new_v4();
+        for event in [
+            created("Play Your Part"),
+            Event::LibraryItemArtistChangedEvent {
+                new_artist: "Girl Talk".to_string(),
+            },
+        ] {
+            let event = EventWithMetadata::new(item_id, event).unwrap();
+            save_and_broadcast_event(event, app_state.clone()).await.unwrap();
+        }
+
+        let response = item_history_handler(State(app_state.clone()), Path(item_id))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let history: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(history[0]["changes"][0], "created: 'Play Your Part'");
+        assert_eq!(history[1]["changes"][0], "artist: '' → 'Girl Talk'");
+        assert_eq!(history[1]["event"]["$type"], "LibraryItemArtistChangedEvent");
+        assert_eq!(history[1]["before"]["artist"], "");
+        assert_eq!(history[1]["after"]["artist"], "Girl Talk");
+
+        let missing = item_history_handler(State(app_state), Path(Uuid::new_v4()))
+            .await
+            .unwrap();
+        assert_eq!(missing.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn plays_are_listed_newest_first_with_their_details() {
+        let app_state = test_app_state().await;
+        let item_id = Uuid::new_v4();
+        let event = EventWithMetadata::new(
+            item_id,
+            Event::LibraryItemCreatedEvent {
+                name: "Once Again".to_string(),
+                artist: Some("Girl Talk".to_string()),
+                album: None,
+                track_number: None,
+                file_path: "once.mp3".to_string(),
+                duration: None,
+                year: None,
+                genre: None,
+            },
+        )
+        .unwrap();
+        save_and_broadcast_event(event, app_state.clone()).await.unwrap();
+        for request in [
+            PlayRequest {
+                id: item_id,
+                source: None,
+                seconds_listened: None,
+                completed: None,
+            },
+            PlayRequest {
+                id: item_id,
+                source: Some(PlaySource::Sonos),
+                seconds_listened: Some(95),
+                completed: Some(true),
+            },
+        ] {
+            play_handler(State(app_state.clone()), JsonExtractor(request))
+                .await
+                .unwrap();
+        }
+
+        let item = app_state.library.read().await.items[&item_id].clone();
+        assert_eq!(item.play_count, 2);
+        assert!(item.last_played_utc.is_some());
+
+        let response = item_plays_handler(State(app_state.clone()), Path(item_id))
+            .await
+            .unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let plays: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(plays[0]["source"], "Sonos");
+        assert_eq!(plays[0]["seconds_listened"], 95);
+        assert_eq!(plays[0]["completed"], true);
+        assert_eq!(plays[1]["source"], "Browser");
+        assert_eq!(plays[1]["completed"], serde_json::Value::Null);
+
+        let query = PlayHistoryQuery {
+            before: None,
+            limit: Some(1),
+        };
+        let history = play_history_handler(State(app_state.clone()), Query(query))
+            .await
+            .unwrap()
+            .into_response();
+        let body = axum::body::to_bytes(history.into_body(), usize::MAX).await.unwrap();
+        let history: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(history.as_array().unwrap().len(), 1);
+        assert_eq!(history[0]["id"], plays[0]["id"]);
+        assert_eq!(history[0]["name"], "Once Again");
+        assert_eq!(history[0]["artist"], "Girl Talk");
+
+        let missing = item_plays_handler(State(app_state), Path(Uuid::new_v4()))
+            .await
+            .unwrap();
+        assert_eq!(missing.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn range_bookmarks_can_be_added_and_moved_keeping_their_label() {
+        let app_state = test_app_state().await;
+        let item_id = Uuid::new_v4();
+        let created = created("Mix");
+        save_and_broadcast_event(
+            EventWithMetadata::new(item_id, created).unwrap(),
+            app_state.clone(),
+        )
+        .await
+        .unwrap();
+
+        let backwards = add_bookmark_handler(
+            State(app_state.clone()),
+            Path(item_id),
+            Cookies::default(),
+            JsonExtractor(AddBookmarkRequest {
+                position: 90.0,
+                end_position: Some(30.0),
+                label: None,
+            }),
+        )
+        .await
+        .unwrap()
+        .into_response();
+        assert_eq!(backwards.status(), StatusCode::BAD_REQUEST);
+
+        let added = add_bookmark_handler(
+            State(app_state.clone()),
+            Path(item_id),
+            Cookies::default(),
+            JsonExtractor(AddBookmarkRequest {
+                position: 30.0,
+                end_position: Some(90.0),
+                label: Some("Second track".to_string()),
+            }),
+        )
+        .await
+        .unwrap()
+        .into_response();
+        assert_eq!(added.status(), StatusCode::CREATED);
+        let (bookmark_id, bookmark) = {
+            let library = app_state.library.read().await;
+            let (id, bookmark) = library.items[&item_id].bookmarks.first().unwrap();
+            (*id, bookmark.clone())
+        };
+        assert_eq!(bookmark.end_position, Some(Duration::from_secs(90)));
+
+        let moved = move_bookmark_handler(
+            State(app_state.clone()),
+            Path((item_id, bookmark_id)),
+            Cookies::default(),
+            JsonExtractor(MoveBookmarkRequest {
+                position: 35.0,
+                end_position: Some(95.5),
+            }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(moved.status(), StatusCode::OK);
+        let after = app_state.library.read().await.items[&item_id].bookmarks[&bookmark_id].clone();
+        assert_eq!(after.position, Duration::from_secs(35));
+        assert_eq!(after.end_position, Some(Duration::from_secs_f64(95.5)));
+        assert_eq!(after.label, bookmark.label);
+        assert_eq!(after.emoji, bookmark.emoji);
+
+        let missing = move_bookmark_handler(
+            State(app_state),
+            Path((item_id, Uuid::new_v4())),
+            Cookies::default(),
+            JsonExtractor(MoveBookmarkRequest {
+                position: 1.0,
+                end_position: None,
+            }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(missing.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn batches_are_broadcast_once_per_item() {
+        let app_state = test_app_state().await;
+        let item_id = Uuid::new_v4();
+        let bookmark_id = Uuid::new_v4();
+        let mut updates = app_state.update_tx.subscribe();
+        let events = [
+            created("Batch"),
+            Event::LibraryItemBookmarkAddedEvent {
+                bookmark_id,
+                position: Duration::from_secs(5),
+                end_position: None,
+                label: None,
+            },
+            Event::LibraryItemBookmarkSetEmojiEvent {
+                bookmark_id,
+                emoji: "🔥".to_string(),
+            },
+        ]
+        .into_iter()
+        .map(|event| EventWithMetadata::new(item_id, event))
+        .collect::<Result<Vec<_>>>()
+        .unwrap();
+
+        save_and_broadcast_events(events, app_state.clone())
+            .await
+            .unwrap();
+
+        match updates.try_recv().unwrap() {
+            FrontendUpdate::Update { item } => {
+                assert_eq!(item.bookmarks[&bookmark_id].emoji, "🔥");
+            }
+            _ => panic!("expected an update"),
+        }
+        assert!(updates.try_recv().is_err());
+        assert_eq!(app_state.events.latest_cursor().unwrap(), 3);
+    }
+
+    #[tokio::test]
+    async fn fsck_repair_removes_deleted_items_from_playlists() {
+        let app_state = test_app_state().await;
+        let item_id = Uuid::new_v4();
+        let playlist_id = Uuid::new_v4();
+        let events = [
+            EventWithMetadata::new(item_id, created("Gone"))
+                .unwrap()
+                .to_raw(),
+            PlaylistEventWithMetadata::new(
+                playlist_id,
+                PlaylistEvent::PlaylistCreatedEvent {
+                    name: "Mix".to_string(),
+                },
+            )
+            .unwrap()
+            .to_raw(),
+            PlaylistEventWithMetadata::new(
+                playlist_id,
+                PlaylistEvent::PlaylistItemAddedEvent {
+                    library_item_id: item_id,
+                    position: 0,
+                },
+            )
+            .unwrap()
+            .to_raw(),
+            EventWithMetadata::new(item_id, Event::LibraryItemDeletedEvent)
+                .unwrap()
+                .to_raw(),
+        ]
+        .into_iter()
+        .collect::<Result<Vec<_>>>()
+        .unwrap();
+        save_and_apply_raw_events(&app_state, &events).await.unwrap();
+        assert!(app_state.playlists.read().await.playlists[&playlist_id]
+            .items
+            .contains_key(&item_id));
+
+        fsck_repair_handler(State(app_state.clone())).await.unwrap();
+
+        assert!(app_state.playlists.read().await.playlists[&playlist_id]
+            .items
+            .is_empty());
+        let report = check_events(&app_state.events.load_all().unwrap(), jiff::Timestamp::now())
+            .unwrap();
+        assert!(report.problems.is_empty(), "{:?}", report.problems);
+    }
+
+    #[tokio::test]
+    async fn fsck_endpoints_report_unreadable_rows_instead_of_failing() {
+        let dir = tempfile::tempdir().unwrap();
+        let pool = open_connection_pool(dir.path().join("reitunes.db").to_str().unwrap()).unwrap();
+        pool.get()
+            .unwrap()
+            .execute(
+                "INSERT INTO events (Id, AggregateId, AggregateType, CreatedTimeUtc, MachineName, Serialized)
+                 VALUES (?1, ?2, 'LibraryItem', '2024-01-01T00:00:00', 'machine', '{not json')",
+                [Uuid::new_v4().to_string(), Uuid::new_v4().to_string()],
+            )
+            .unwrap();
+        let app_state = AppState {
+            events: Arc::new(SqliteEventStore::new(pool).unwrap()),
+            ..test_app_state().await
+        };
+
+        for response in [
+            fsck_handler(State(app_state.clone()))
+                .await
+                .unwrap()
+                .into_response(),
+            fsck_repair_handler(State(app_state.clone()))
+                .await
+                .unwrap()
+                .into_response(),
+        ] {
+            assert_eq!(response.status(), StatusCode::OK);
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            let report: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(report["events_checked"], 1);
+            assert_eq!(report["problems"][0]["kind"], "Unreadable");
+        }
+    }
+
     #[test]
     fn deserializes_sonos_play_request_from_frontend_json() {
         let item_id = Uuid::new_v4();
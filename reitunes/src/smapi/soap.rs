@@ -5,7 +5,7 @@ use axum::{
     response::{IntoResponse, Response},
 };
 use regex::Regex;
-use reitunes_workspace::{Bookmark, Library, LibraryItem};
+use reitunes_workspace::{Bookmark, Event, EventWithMetadata, Library, LibraryItem, MAX_RATING};
 use std::hash::{DefaultHasher, Hash, Hasher};
 use tracing::{debug, error, info};
 use uuid::Uuid;
@@ -33,6 +33,7 @@ pub async fn smapi_soap_handler(
         "getMediaMetadata" => get_media_metadata(&state, &body).await?,
         "getExtendedMetadata" => get_extended_metadata(&state, &body).await?,
         "getLastUpdate" => get_last_update(&state).await,
+        "rateItem" => rate_item(&state, &body).await?,
         _ => return Err(SoapError::UnsupportedOperation(action.to_string())),
     };
 
@@ -119,12 +120,15 @@ async fn get_media_uri(state: &crate::AppState, body: &str) -> Result<String, So
     let library = state.library.read().await;
     let (track, bookmark) = resolve_media_item(&library, &id)?;
     let url = state.storage.url(&track.file_path);
+    // bookmarks start where they point, and plain tracks pick up where they were left off
     let position_information = bookmark
-        .map(|bookmark| {
+        .map(|bookmark| bookmark.position)
+        .or(track.resume_position)
+        .map(|position| {
             format!(
                 "<positionInformation><id>{}</id><index>0</index><offsetMillis>{}</offsetMillis></positionInformation>",
                 escape_xml(&id),
-                bookmark.position.as_millis()
+                position.as_millis()
             )
         })
         .unwrap_or_default();
@@ -186,6 +190,29 @@ async fn get_last_update(state: &crate::AppState) -> String {
     ))
 }
 
+async fn rate_item(state: &crate::AppState, body: &str) -> Result<String, SoapError> {
+    let id = required_request_value(body, "id")?;
+    let rating = required_request_value(body, "rating")?;
+    let rating = rating
+        .parse::<u8>()
+        .ok()
+        .filter(|rating| *rating <= MAX_RATING)
+        .ok_or_else(|| SoapError::InvalidRequest(format!("invalid rating: {rating}")))?;
+    let track_id = track_uuid(&id)?;
+    if !state.library.read().await.items.contains_key(&track_id) {
+        return Err(SoapError::NotFound(id));
+    }
+
+    let event = EventWithMetadata::new(
+        track_id,
+        Event::LibraryItemRatingChangedEvent { new_rating: rating },
+    )?;
+    crate::save_and_broadcast_event(event, state.clone()).await?;
+    Ok(soap_envelope(&format!(
+        "<rateItemResponse xmlns=\"{SONOS_NAMESPACE}\"><rateItemResult><shouldSkip>false</shouldSkip></rateItemResult></rateItemResponse>"
+    )))
+}
+
 fn metadata_response(
     action: &str,
     index: usize,
@@ -223,6 +250,7 @@ fn browse_items(library: &Library, id: &str) -> Result<Vec<BrowseItem>, SoapErro
             collection_item("tracks", "trackList", "All songs"),
             collection_item("artists", "container", "Artists"),
             collection_item("albums", "container", "Albums"),
+            collection_item("tags", "container", "Tags"),
             collection_item("favorites", "trackList", "Favourites"),
             collection_item("bookmarks", "trackList", "Bookmarks"),
         ]),
@@ -235,6 +263,7 @@ fn browse_items(library: &Library, id: &str) -> Result<Vec<BrowseItem>, SoapErro
             .into_iter()
             .map(|(artist, album)| album_item(&artist, &album))
             .collect()),
+        "tags" => Ok(library.tag_counts().into_keys().map(tag_item).collect()),
         "favorites" => Ok(sorted_tracks(library)
             .into_iter()
             .filter(|track| track.is_favorite)
@@ -258,6 +287,18 @@ fn browse_items(library: &Library, id: &str) -> Result<Vec<BrowseItem>, SoapErro
                 .map(BrowseItem::from)
                 .collect())
         }
+        _ if id.starts_with("tag:") => {
+            let tag = &id["tag:".len()..];
+            let tracks: Vec<_> = sorted_tracks(library)
+                .into_iter()
+                .filter(|track| track.tags.contains(tag))
+                .map(BrowseItem::from)
+                .collect();
+            if tracks.is_empty() {
+                return Err(SoapError::NotFound(id.to_string()));
+            }
+            Ok(tracks)
+        }
         _ if id.starts_with("album:") => {
             let (artist, album) = albums(library)
                 .into_iter()
@@ -278,6 +319,7 @@ fn collection_details(library: &Library, id: &str) -> Option<(&'static str, Stri
         "tracks" => Some(("trackList", "All songs".to_string())),
         "artists" => Some(("container", "Artists".to_string())),
         "albums" => Some(("container", "Albums".to_string())),
+        "tags" => Some(("container", "Tags".to_string())),
         "favorites" => Some(("trackList", "Favourites".to_string())),
         "bookmarks" => Some(("trackList", "Bookmarks".to_string())),
         "search" => Some(("container", "Search".to_string())),
@@ -289,6 +331,10 @@ fn collection_details(library: &Library, id: &str) -> Option<(&'static str, Stri
             .into_iter()
             .find(|artist| stable_id("artist", &[artist]) == id)
             .map(|artist| ("artist", artist)),
+        _ if id.starts_with("tag:") => id
+            .strip_prefix("tag:")
+            .filter(|tag| library.tag_counts().contains_key(*tag))
+            .map(|tag| ("trackList", tag.to_string())),
         _ if id.starts_with("album:") => albums(library)
             .into_iter()
             .find(|(artist, album)| stable_id("album", &[artist, album]) == id)
@@ -309,6 +355,11 @@ fn artist_item(artist: String) -> BrowseItem {
     collection_item(&stable_id("artist", &[&artist]), "artist", &artist)
 }
 
+/// Unlike artists and albums, tags are short enough to go in their IDs as they are
+fn tag_item(tag: String) -> BrowseItem {
+    collection_item(&format!("tag:{tag}"), "trackList", &tag)
+}
+
 fn album_item(artist: &str, album: &str) -> BrowseItem {
     collection_item(
         &stable_id("album", &[artist, album]),
@@ -426,20 +477,19 @@ fn track_xml(track: &LibraryItem) -> String {
 }
 
 fn bookmark_xml(id: &str, track: &LibraryItem, bookmark: &Bookmark) -> String {
-    let title = match bookmark.label.as_deref() {
-        Some(label) => format!(
-            "{} {} — {} — {}",
-            bookmark.emoji,
-            label,
-            track.name,
-            format_position(bookmark.position)
-        ),
-        None => format!(
-            "{} {} — {}",
-            bookmark.emoji,
-            track.name,
-            format_position(bookmark.position)
+    // Sonos can start a bookmark at its position but won't stop at the end of a range, so
+    // ranges at least show where they end
+    let position = match bookmark.end_position {
+        Some(end) => format!(
+            "{}–{}",
+            format_position(bookmark.position),
+            format_position(end)
         ),
+        None => format_position(bookmark.position),
+    };
+    let title = match bookmark.label.as_deref() {
+        Some(label) => format!("{} {} — {} — {position}", bookmark.emoji, label, track.name),
+        None => format!("{} {} — {position}", bookmark.emoji, track.name),
     };
     track_xml_with_identity(id, &title, track, true)
 }
@@ -448,6 +498,15 @@ fn track_xml_with_identity(id: &str, title: &str, track: &LibraryItem, can_resum
     let mime_type = mime_guess::from_path(&track.file_path)
         .first_or_octet_stream()
         .to_string();
+    let genre = if track.genre.is_empty() {
+        String::new()
+    } else {
+        format!("<genre>{}</genre>", escape_xml(&track.genre))
+    };
+    let duration = track
+        .duration
+        .map(|duration| format!("<duration>{}</duration>", duration.as_secs()))
+        .unwrap_or_default();
     let track_number = track
         .track_number
         .map(|number| format!("<trackNumber>{number}</trackNumber>"))
@@ -457,12 +516,13 @@ fn track_xml_with_identity(id: &str, title: &str, track: &LibraryItem, can_resum
         .unwrap_or_default();
 
     format!(
-        "<id>{}</id><itemType>track</itemType><title>{}</title><mimeType>{}</mimeType><trackMetadata><artist>{}</artist><album>{}</album>{track_number}<canPlay>true</canPlay><canSkip>true</canSkip>{can_resume}</trackMetadata>",
+        "<id>{}</id><itemType>track</itemType><title>{}</title><mimeType>{}</mimeType><trackMetadata><artist>{}</artist><album>{}</album>{genre}{duration}{track_number}<canPlay>true</canPlay><canSkip>true</canSkip>{can_resume}</trackMetadata><dynamic><property><name>rating</name><value>{}</value></property></dynamic>",
         escape_xml(id),
         escape_xml(title),
         escape_xml(&mime_type),
         escape_xml(&track.artist),
         escape_xml(&track.album),
+        track.rating,
     )
 }
 
@@ -503,10 +563,15 @@ fn catalog_version(library: &Library) -> u64 {
         item.album.hash(&mut hasher);
         item.file_path.hash(&mut hasher);
         item.track_number.hash(&mut hasher);
+        item.genre.hash(&mut hasher);
+        item.duration.hash(&mut hasher);
+        item.rating.hash(&mut hasher);
+        item.tags.hash(&mut hasher);
         item.is_favorite.hash(&mut hasher);
         for (bookmark_id, bookmark) in &item.bookmarks {
             bookmark_id.hash(&mut hasher);
             bookmark.position.hash(&mut hasher);
+            bookmark.end_position.hash(&mut hasher);
             bookmark.emoji.hash(&mut hasher);
         }
     }
@@ -541,7 +606,7 @@ fn request_value(body: &str, name: &str) -> Option<String> {
         .expect("request element regex should be valid")
         .captures(body)
         .and_then(|captures| captures.get(1))
-        .map(|value| value.as_str().trim().to_string())
+        .map(|value| unescape_xml(value.as_str().trim()))
 }
 
 fn track_uuid(id: &str) -> Result<Uuid, SoapError> {
@@ -599,6 +664,16 @@ fn escape_xml(value: &str) -> String {
         .replace('\'', "&apos;")
 }
 
+/// Undo `escape_xml`, e.g. for tag IDs, which can contain anything
+fn unescape_xml(value: &str) -> String {
+    value
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum SoapError {
     #[error("missing SOAPAction header")]
@@ -611,6 +686,8 @@ pub enum SoapError {
     NotFound(String),
     #[error("HTTP response error: {0}")]
     Http(#[from] axum::http::Error),
+    #[error("{0:#}")]
+    Internal(#[from] anyhow::Error),
 }
 
 impl IntoResponse for SoapError {
@@ -620,7 +697,7 @@ impl IntoResponse for SoapError {
             Self::MissingSoapAction | Self::UnsupportedOperation(_) | Self::InvalidRequest(_) => {
                 "Client"
             }
-            Self::NotFound(_) | Self::Http(_) => "Server",
+            Self::NotFound(_) | Self::Http(_) | Self::Internal(_) => "Server",
         };
         let body = soap_envelope(&format!(
             "<soap:Fault><faultcode>{fault_code}</faultcode><faultstring>{}</faultstring></soap:Fault>",
@@ -637,7 +714,7 @@ impl IntoResponse for SoapError {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use reitunes_workspace::{Event, EventWithMetadata, PlaylistStore};
+    use reitunes_workspace::{InMemoryEventStore, PlaylistStore};
     use std::sync::Arc;
     use tokio::sync::{broadcast, RwLock};
 
@@ -688,6 +765,10 @@ mod tests {
             escape_xml("AC/DC & <friends> \"live\""),
             "AC/DC &amp; &lt;friends&gt; &quot;live&quot;"
         );
+        assert_eq!(
+            unescape_xml(&escape_xml("AC/DC & <friends> \"live\" &lt;")),
+            "AC/DC & <friends> \"live\" &lt;"
+        );
     }
 
     #[tokio::test]
@@ -703,15 +784,26 @@ mod tests {
                     album: Some("Album".to_string()),
                     track_number: Some(1),
                     file_path: "one and only.mp3".to_string(),
+                    duration: Some(std::time::Duration::from_millis(215_400)),
+                    year: Some(2009),
+                    genre: Some("Mashup".to_string()),
                 },
             )
             .unwrap(),
             EventWithMetadata::new(track_id, Event::LibraryItemFavoritedEvent).unwrap(),
+            EventWithMetadata::new(
+                track_id,
+                Event::LibraryItemTagAddedEvent {
+                    tag: "party & chill".to_string(),
+                },
+            )
+            .unwrap(),
             EventWithMetadata::new(
                 track_id,
                 Event::LibraryItemBookmarkAddedEvent {
                     bookmark_id,
                     position: std::time::Duration::from_secs(754),
+                    end_position: Some(std::time::Duration::from_secs(800)),
                     label: Some("The good bit".to_string()),
                 },
             )
@@ -721,6 +813,7 @@ mod tests {
             "https://s3.example.com",
             "reitunes",
             Some("music"),
+            "ca-east-tor",
             "test-key",
             "test-secret",
         )
@@ -735,6 +828,11 @@ mod tests {
             cloud_queues: Arc::new(crate::cloud_queue::CloudQueueStore::with_base_url(
                 "https://reitunes.example.com/",
             )),
+            events: Arc::new(InMemoryEventStore::new()),
+            time_travel: Arc::new(crate::time_travel::TimeTravelCache::new()),
+            undo: Arc::new(crate::undo::UndoStacks::new()),
+            config: Arc::new(crate::config::Config::default()),
+            library_settings: Arc::new(crate::config::Config::default().library(None).unwrap()),
         };
 
         let metadata = get_metadata(
@@ -746,6 +844,7 @@ mod tests {
         assert!(metadata.contains("<count>1</count><total>1</total>"));
         assert!(metadata.contains("<title>One &amp; Only</title>"));
         assert!(metadata.contains("<artist>A &lt;B</artist>"));
+        assert!(metadata.contains("<genre>Mashup</genre><duration>215</duration>"));
         assert!(metadata.contains(&format!("<id>track:{track_id}</id>")));
 
         let root = get_metadata(
@@ -758,6 +857,22 @@ mod tests {
         assert!(root.contains("<id>artists</id>"));
         assert!(root.contains("<id>albums</id>"));
         assert!(root.contains("<id>favorites</id>"));
+        assert!(root.contains("<id>tags</id>"));
+
+        let tags = get_metadata(
+            &state,
+            "<getMetadata><id>tags</id><index>0</index><count>10</count></getMetadata>",
+        )
+        .await
+        .unwrap();
+        assert!(tags.contains("<id>tag:party &amp; chill</id>"));
+        let tagged = get_metadata(
+            &state,
+            "<getMetadata><id>tag:party &amp; chill</id><index>0</index><count>10</count></getMetadata>",
+        )
+        .await
+        .unwrap();
+        assert!(tagged.contains(&format!("<id>track:{track_id}</id>")));
         assert!(root.contains("<id>bookmarks</id>"));
 
         let favorites = get_metadata(
@@ -778,7 +893,7 @@ mod tests {
         .unwrap();
         assert!(bookmarks.contains("<count>1</count><total>1</total>"));
         assert!(bookmarks.contains(&format!("<id>{bookmark_item_id}</id>")));
-        assert!(bookmarks.contains("The good bit — One &amp; Only — 12:34"));
+        assert!(bookmarks.contains("The good bit — One &amp; Only — 12:34–13:20"));
         assert!(bookmarks.contains("<canResume>true</canResume>"));
 
         let artist_id = stable_id("artist", &["A <B"]);
@@ -811,6 +926,26 @@ mod tests {
         assert!(media_uri.contains(
             "<getMediaURIResult>https://reitunes.s3.example.com/music/one%20and%20only.mp3</getMediaURIResult>"
         ));
+        assert!(!media_uri.contains("<positionInformation>"));
+
+        state.library.write().await.apply(
+            &EventWithMetadata::new(
+                track_id,
+                Event::LibraryItemResumePositionChangedEvent {
+                    new_position: Some(std::time::Duration::from_secs(95)),
+                },
+            )
+            .unwrap(),
+        );
+        let resumed_uri = get_media_uri(
+            &state,
+            &format!("<getMediaURI><id>track:{track_id}</id></getMediaURI>"),
+        )
+        .await
+        .unwrap();
+        assert!(resumed_uri.contains(&format!(
+            "<positionInformation><id>track:{track_id}</id><index>0</index><offsetMillis>95000</offsetMillis>"
+        )));
 
         let bookmark_uri = get_media_uri(
             &state,
@@ -846,5 +981,31 @@ mod tests {
         .unwrap();
         assert!(extended_metadata.contains("<getExtendedMetadataResult><mediaMetadata>"));
         assert!(extended_metadata.contains("<title>One &amp; Only</title>"));
+
+        let catalog = catalog_version(&*state.library.read().await);
+        let rated = rate_item(
+            &state,
+            &format!("<rateItem><id>track:{track_id}</id><rating>4</rating></rateItem>"),
+        )
+        .await
+        .unwrap();
+        assert!(rated.contains("<shouldSkip>false</shouldSkip>"));
+        assert_eq!(state.library.read().await.items[&track_id].rating, 4);
+        assert_ne!(catalog_version(&*state.library.read().await), catalog);
+        let rated_metadata = get_media_metadata(
+            &state,
+            &format!("<getMediaMetadata><id>track:{track_id}</id></getMediaMetadata>"),
+        )
+        .await
+        .unwrap();
+        assert!(rated_metadata.contains("<name>rating</name><value>4</value>"));
+        assert!(matches!(
+            rate_item(
+                &state,
+                &format!("<rateItem><id>track:{track_id}</id><rating>6</rating></rateItem>"),
+            )
+            .await,
+            Err(SoapError::InvalidRequest(_))
+        ));
     }
 }
@@ -18,6 +18,7 @@ use tokio::sync::Mutex;
 use tracing::warn;
 
 use crate::cloud_queue::PlaybackQueueParameters;
+use crate::config::{required, SonosSettings};
 
 const SONOS_SCOPE: &str = "playback-control-all";
 const SONOS_API_KEY_HEADER: &str = "X-Sonos-Api-Key";
@@ -30,7 +31,7 @@ const REFRESH_EARLY_BY_SECONDS: u64 = 60;
 const TOKEN_AAD: &[u8] = b"reitunes-sonos-oauth-v1";
 
 #[derive(Clone)]
-struct SonosConfig {
+pub(crate) struct SonosConfig {
     client_id: String,
     client_secret: String,
     redirect_uri: String,
@@ -38,22 +39,13 @@ struct SonosConfig {
 }
 
 impl SonosConfig {
-    fn from_env() -> Result<Option<Self>> {
-        let client_id = configured_value("SONOS_CLIENT_ID", option_env!("SONOS_CLIENT_ID"));
-        let client_secret =
-            configured_value("SONOS_CLIENT_SECRET", option_env!("SONOS_CLIENT_SECRET"));
-        let redirect_uri =
-            configured_value("SONOS_REDIRECT_URI", option_env!("SONOS_REDIRECT_URI"));
-        let encryption_secret = configured_value(
-            "SONOS_TOKEN_ENCRYPTION_SECRET",
-            option_env!("SONOS_TOKEN_ENCRYPTION_SECRET"),
-        );
-
+    /// `None` when Sonos isn't configured at all; an error when it's only partly configured
+    pub(crate) fn from_settings(settings: &SonosSettings) -> Result<Option<Self>> {
         if [
-            &client_id,
-            &client_secret,
-            &redirect_uri,
-            &encryption_secret,
+            &settings.client_id,
+            &settings.client_secret,
+            &settings.redirect_uri,
+            &settings.token_encryption_secret,
         ]
         .iter()
         .all(|value| value.is_none())
@@ -61,41 +53,42 @@ impl SonosConfig {
             return Ok(None);
         }
 
-        let client_id =
-            client_id.context("SONOS_CLIENT_ID is required when Sonos is configured")?;
-        let client_secret =
-            client_secret.context("SONOS_CLIENT_SECRET is required when Sonos is configured")?;
-        let redirect_uri =
-            redirect_uri.context("SONOS_REDIRECT_URI is required when Sonos is configured")?;
-        let encryption_secret = encryption_secret
-            .context("SONOS_TOKEN_ENCRYPTION_SECRET is required when Sonos is configured")?;
+        let client_id = required(&settings.client_id, "sonos.client_id", "SONOS_CLIENT_ID")?;
+        let client_secret = required(
+            &settings.client_secret,
+            "sonos.client_secret",
+            "SONOS_CLIENT_SECRET",
+        )?;
+        let redirect_uri = required(
+            &settings.redirect_uri,
+            "sonos.redirect_uri",
+            "SONOS_REDIRECT_URI",
+        )?;
+        let encryption_secret = required(
+            &settings.token_encryption_secret,
+            "sonos.token_encryption_secret",
+            "SONOS_TOKEN_ENCRYPTION_SECRET",
+        )?;
 
-        let redirect_url = Url::parse(&redirect_uri).context("SONOS_REDIRECT_URI is not a URL")?;
+        let redirect_url =
+            Url::parse(redirect_uri).context("sonos.redirect_uri is not a URL")?;
         if redirect_url.scheme() != "https" && redirect_url.host_str() != Some("localhost") {
-            bail!("SONOS_REDIRECT_URI must use HTTPS (except on localhost)");
+            bail!("sonos.redirect_uri must use HTTPS (except on localhost)");
         }
         if encryption_secret.len() < 32 {
-            bail!("SONOS_TOKEN_ENCRYPTION_SECRET must be at least 32 characters");
+            bail!("sonos.token_encryption_secret must be at least 32 characters");
         }
 
         let token_encryption_key = Sha256::digest(encryption_secret.as_bytes()).into();
         Ok(Some(Self {
-            client_id,
-            client_secret,
-            redirect_uri,
+            client_id: client_id.to_string(),
+            client_secret: client_secret.to_string(),
+            redirect_uri: redirect_uri.to_string(),
             token_encryption_key,
         }))
     }
 }
 
-fn configured_value(name: &str, compile_time_value: Option<&'static str>) -> Option<String> {
-    compile_time_value
-        .filter(|value| !value.trim().is_empty())
-        .map(ToOwned::to_owned)
-        .or_else(|| std::env::var(name).ok())
-        .filter(|value| !value.trim().is_empty())
-}
-
 #[derive(Clone)]
 struct SonosEndpoints {
     authorization: Url,
@@ -307,8 +300,11 @@ pub struct SonosControl {
 }
 
 impl SonosControl {
-    pub fn from_env(db: Pool<SqliteConnectionManager>) -> Result<Option<Arc<Self>>> {
-        let Some(config) = SonosConfig::from_env()? else {
+    pub fn from_config(
+        settings: &SonosSettings,
+        db: Pool<SqliteConnectionManager>,
+    ) -> Result<Option<Arc<Self>>> {
+        let Some(config) = SonosConfig::from_settings(settings)? else {
             return Ok(None);
         };
         Ok(Some(Arc::new(Self::new(
@@ -3,6 +3,11 @@ use aws_sdk_s3::primitives::ByteStream;
 use std::path::Path;
 use tracing::info;
 
+use crate::config::{required, StorageSettings};
+
+/// Where database backups go, under the prefix
+const BACKUP_DIR: &str = "backups/";
+
 /// S3-compatible object storage (OVHcloud, AWS, etc.)
 pub struct S3Storage {
     client: aws_sdk_s3::Client,
@@ -12,10 +17,33 @@ pub struct S3Storage {
 }
 
 impl S3Storage {
+    /// Each library keeps its media under its own `prefix`
+    pub async fn from_config(settings: &StorageSettings, prefix: Option<&str>) -> Result<Self> {
+        let endpoint = required(&settings.endpoint, "storage.endpoint", "S3_ENDPOINT")?;
+        let bucket = required(&settings.bucket, "storage.bucket", "S3_BUCKET")?;
+        let access_key = required(&settings.access_key, "storage.access_key", "S3_ACCESS_KEY")?;
+        let secret_key = required(&settings.secret_key, "storage.secret_key", "S3_SECRET_KEY")?;
+
+        info!(
+            "Using S3 storage: {} / {} (prefix: {:?})",
+            endpoint, bucket, prefix
+        );
+        Self::new(
+            endpoint,
+            bucket,
+            prefix,
+            &settings.region,
+            access_key,
+            secret_key,
+        )
+        .await
+    }
+
     pub async fn new(
         endpoint: &str,
         bucket: &str,
         prefix: Option<&str>,
+        region: &str,
         access_key: &str,
         secret_key: &str,
     ) -> Result<Self> {
@@ -29,7 +57,7 @@ impl S3Storage {
 
         let config = aws_sdk_s3::Config::builder()
             .endpoint_url(endpoint)
-            .region(aws_sdk_s3::config::Region::new("ca-east-tor"))
+            .region(aws_sdk_s3::config::Region::new(region.to_string()))
             .credentials_provider(credentials)
             .force_path_style(true) // Required for OVHcloud S3 API calls
             .behavior_version_latest()
@@ -77,11 +105,7 @@ impl S3Storage {
     pub async fn upload(&self, filename: &str, data: &[u8]) -> Result<String> {
         let unique_name = self.unique_filename(filename);
 
-        // Compute the S3 key (with prefix if configured)
-        let s3_key = match &self.prefix {
-            Some(prefix) => format!("{}/{}", prefix, unique_name),
-            None => unique_name.clone(),
-        };
+        let s3_key = self.key(&unique_name);
 
         // Guess content type from filename
         let content_type = mime_guess::from_path(&unique_name)
@@ -110,6 +134,91 @@ impl S3Storage {
         Ok(unique_name)
     }
 
+    /// Fetch a file previously returned by `upload`
+    pub async fn download(&self, file_path: &str) -> Result<Vec<u8>> {
+        let object = self
+            .client
+            .get_object()
+            .bucket(&self.bucket)
+            .key(self.key(file_path))
+            .send()
+            .await
+            .with_context(|| format!("Failed to download {file_path} from S3"))?;
+        let data = object
+            .body
+            .collect()
+            .await
+            .with_context(|| format!("Failed to read {file_path} from S3"))?;
+        Ok(data.into_bytes().to_vec())
+    }
+
+    /// Upload a database backup. Unlike media, backups are private.
+    pub async fn upload_backup(&self, file_name: &str, data: Vec<u8>) -> Result<()> {
+        let s3_key = self.key(&format!("{BACKUP_DIR}{file_name}"));
+        info!(key = %s3_key, size = data.len(), "Uploading backup to S3");
+
+        self.client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(&s3_key)
+            .body(ByteStream::from(data))
+            .content_type("application/vnd.sqlite3")
+            .acl(aws_sdk_s3::types::ObjectCannedAcl::Private)
+            .send()
+            .await
+            .context("Failed to upload backup to S3")?;
+        Ok(())
+    }
+
+    /// File names of the backups in S3, sorted by name
+    pub async fn list_backups(&self) -> Result<Vec<String>> {
+        let prefix = self.key(BACKUP_DIR);
+        let mut file_names = Vec::new();
+        let mut continuation_token = None;
+        loop {
+            let page = self
+                .client
+                .list_objects_v2()
+                .bucket(&self.bucket)
+                .prefix(&prefix)
+                .set_continuation_token(continuation_token)
+                .send()
+                .await
+                .context("Failed to list backups in S3")?;
+            file_names.extend(
+                page.contents()
+                    .iter()
+                    .filter_map(|object| object.key()?.strip_prefix(&prefix))
+                    .map(String::from),
+            );
+            continuation_token = page.next_continuation_token().map(String::from);
+            if continuation_token.is_none() {
+                break;
+            }
+        }
+        file_names.sort();
+        Ok(file_names)
+    }
+
+    pub async fn delete_backup(&self, file_name: &str) -> Result<()> {
+        self.client
+            .delete_object()
+            .bucket(&self.bucket)
+            .key(self.key(&format!("{BACKUP_DIR}{file_name}")))
+            .send()
+            .await
+            .with_context(|| format!("Failed to delete backup {file_name} from S3"))?;
+        Ok(())
+    }
+
+    /// The S3 key for a file (with prefix if configured)
+    fn key(&self, file_path: &str) -> String {
+        match &self.prefix {
+            Some(prefix) => format!("{}/{}", prefix, file_path),
+            None => file_path.to_string(),
+        }
+    }
+
     pub fn url(&self, file_path: &str) -> String {
         // base_url already includes the prefix, so just append the URL-encoded filename
         let encoded = urlencoding::encode(file_path);
@@ -0,0 +1,210 @@
+use std::sync::{Arc, Mutex};
+
+use anyhow::Result;
+use indexmap::IndexMap;
+use jiff::civil::DateTime;
+use reitunes_workspace::{
+    AggregateType, EventStore, EventWithMetadata, Library, PlaylistEventWithMetadata,
+    PlaylistStore,
+};
+
+/// How many points in time to keep rebuilt state for
+const CACHE_CAPACITY: usize = 16;
+
+/// How many new events to look through at a time when checking whether a cached state is
+/// still current
+const PAGE_SIZE: usize = 1000;
+
+/// The library and playlists as they were at some point in the past
+pub struct HistoricalState {
+    pub library: Library,
+    pub playlists: PlaylistStore,
+}
+
+struct CacheEntry {
+    /// `EventStore::latest_cursor` when the state was last known to be current. Events saved
+    /// since then only matter if they were created at or before the entry's time (e.g. an
+    /// offline edit that synced in later).
+    cursor: i64,
+    state: Arc<HistoricalState>,
+}
+
+/// Rebuilds past library/playlist state from the event log, caching the most recent few
+/// timestamps so that paging through a historical view doesn't replay everything each time
+#[derive(Default)]
+pub struct TimeTravelCache {
+    entries: Mutex<IndexMap<DateTime, CacheEntry>>,
+}
+
+impl TimeTravelCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub async fn state_as_of(
+        &self,
+        events: Arc<dyn EventStore>,
+        as_of: DateTime,
+    ) -> Result<Arc<HistoricalState>> {
+        let cached = self
+            .entries
+            .lock()
+            .unwrap()
+            .get(&as_of)
+            .map(|entry| (entry.cursor, entry.state.clone()));
+
+        // looking through new events and replaying the whole log both take a while, so keep
+        // them off the async workers. The lock isn't held meanwhile; the worst case is two
+        // requests doing the same work.
+        let (cursor, state, rebuilt) = tokio::task::spawn_blocking(move || -> Result<_> {
+            let cursor = events.latest_cursor()?;
+            if let Some((built_at, state)) = cached {
+                if built_at == cursor || !changes_history(events.as_ref(), built_at, as_of)? {
+                    return Ok((cursor, state, false));
+                }
+            }
+            Ok((cursor, build_state(events.as_ref(), as_of)?, true))
+        })
+        .await??;
+
+        if !rebuilt {
+            if let Some(entry) = self.entries.lock().unwrap().get_mut(&as_of) {
+                entry.cursor = entry.cursor.max(cursor);
+            }
+            return Ok(state);
+        }
+
+        let mut entries = self.entries.lock().unwrap();
+        entries.shift_remove(&as_of);
+        if entries.len() >= CACHE_CAPACITY {
+            entries.shift_remove_index(0);
+        }
+        entries.insert(
+            as_of,
+            CacheEntry {
+                cursor,
+                state: state.clone(),
+            },
+        );
+        Ok(state)
+    }
+}
+
+/// Whether any event saved after the `after` cursor was created at or before `as_of`, and so
+/// changes the state as of then
+fn changes_history(events: &dyn EventStore, mut after: i64, as_of: DateTime) -> Result<bool> {
+    loop {
+        let page = events.load_since(after, PAGE_SIZE)?;
+        if page
+            .events
+            .iter()
+            .any(|event| event.created_time_utc <= as_of)
+        {
+            return Ok(true);
+        }
+        if !page.has_more {
+            return Ok(false);
+        }
+        after = page.next_cursor;
+    }
+}
+
+fn build_state(events: &dyn EventStore, as_of: DateTime) -> Result<Arc<HistoricalState>> {
+    let library_events = events
+        .load_by_aggregate_type(AggregateType::LibraryItem)?
+        .into_iter()
+        .map(EventWithMetadata::from_raw)
+        .collect::<Result<_>>()?;
+    let playlist_events = events
+        .load_by_aggregate_type(AggregateType::Playlist)?
+        .into_iter()
+        .map(PlaylistEventWithMetadata::from_raw)
+        .collect::<Result<_>>()?;
+    Ok(Arc::new(HistoricalState {
+        library: Library::build_until(library_events, as_of),
+        playlists: PlaylistStore::build_until(playlist_events, as_of),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::created;
+    use reitunes_workspace::{Event, InMemoryEventStore};
+    use uuid::Uuid;
+
+    async fn append_at(
+        store: &InMemoryEventStore,
+        item_id: Uuid,
+        event: Event,
+        time: &str,
+    ) -> Result<()> {
+        let mut event = EventWithMetadata::new(item_id, event)?;
+        event.created_time_utc = time.parse()?;
+        store.append(&event.to_raw()?).await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn past_state_is_cached_until_events_from_before_then_arrive() -> Result<()> {
+        let store = Arc::new(InMemoryEventStore::new());
+        let cache = TimeTravelCache::new();
+        let item_id = Uuid::new_v4();
+        append_at(
+            store.as_ref(),
+            item_id,
+            created("Original"),
+            "2024-01-01T00:00:00",
+        )
+        .await?;
+        append_at(
+            store.as_ref(),
+            item_id,
+            Event::LibraryItemNameChangedEvent {
+                new_name: "Oops".to_string(),
+            },
+            "2024-06-01T00:00:00",
+        )
+        .await?;
+
+        let as_of = "2024-03-01T00:00:00".parse()?;
+        let before_edit = cache.state_as_of(store.clone(), as_of).await?;
+        assert_eq!(before_edit.library.items[&item_id].name, "Original");
+        assert!(Arc::ptr_eq(
+            &before_edit,
+            &cache.state_as_of(store.clone(), as_of).await?
+        ));
+
+        let before_create = cache
+            .state_as_of(store.clone(), "2023-01-01T00:00:00".parse()?)
+            .await?;
+        assert!(before_create.library.items.is_empty());
+
+        // events from after `as_of` don't change it
+        append_at(
+            store.as_ref(),
+            item_id,
+            Event::LibraryItemUnfavoritedEvent,
+            "2024-07-01T00:00:00",
+        )
+        .await?;
+        assert!(Arc::ptr_eq(
+            &before_edit,
+            &cache.state_as_of(store.clone(), as_of).await?
+        ));
+
+        // but an offline edit from before `as_of` that syncs in later does
+        append_at(
+            store.as_ref(),
+            item_id,
+            Event::LibraryItemFavoritedEvent,
+            "2024-02-01T00:00:00",
+        )
+        .await?;
+        let rebuilt = cache.state_as_of(store.clone(), as_of).await?;
+        assert!(!Arc::ptr_eq(&before_edit, &rebuilt));
+        assert!(rebuilt.library.items[&item_id].is_favorite);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,149 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use reitunes_workspace::Event;
+use tower_cookies::{Cookie, Cookies};
+use uuid::Uuid;
+
+/// Identifies a browser for undo purposes. The login cookie is the same for every browser, so
+/// it can't tell sessions apart.
+const UNDO_SESSION_COOKIE_NAME: &str = "reitunes_undo_session";
+
+/// How many edits each session can undo
+const MAX_UNDO_DEPTH: usize = 100;
+
+/// One user action (which may have taken several events), and the events that undo it
+#[derive(Debug, Clone)]
+pub struct UndoableEdit {
+    pub item_id: Uuid,
+    pub forward: Vec<Event>,
+    pub inverse: Vec<Event>,
+}
+
+#[derive(Default)]
+struct SessionHistory {
+    undo: Vec<UndoableEdit>,
+    redo: Vec<UndoableEdit>,
+}
+
+/// Per-session undo/redo stacks. Only kept in memory: undo is for "oops, I didn't mean to do
+/// that", not for going back in time after a restart (use `?as_of=` for that).
+#[derive(Default)]
+pub struct UndoStacks {
+    sessions: Mutex<HashMap<Uuid, SessionHistory>>,
+}
+
+impl UndoStacks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Remember a new edit. Like every other editor, this forgets anything that was undone.
+    pub fn record(&self, session: Uuid, edit: UndoableEdit) {
+        let mut sessions = self.sessions.lock().unwrap();
+        let history = sessions.entry(session).or_default();
+        history.redo.clear();
+        push_bounded(&mut history.undo, edit);
+    }
+
+    /// Take the most recent edit to undo. Pass it to `undone` once its inverse is saved.
+    pub fn take_undo(&self, session: Uuid) -> Option<UndoableEdit> {
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions.get_mut(&session)?.undo.pop()
+    }
+
+    /// Take the most recently undone edit. Pass it to `redone` once it's saved again.
+    pub fn take_redo(&self, session: Uuid) -> Option<UndoableEdit> {
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions.get_mut(&session)?.redo.pop()
+    }
+
+    pub fn undone(&self, session: Uuid, edit: UndoableEdit) {
+        let mut sessions = self.sessions.lock().unwrap();
+        push_bounded(&mut sessions.entry(session).or_default().redo, edit);
+    }
+
+    pub fn redone(&self, session: Uuid, edit: UndoableEdit) {
+        let mut sessions = self.sessions.lock().unwrap();
+        push_bounded(&mut sessions.entry(session).or_default().undo, edit);
+    }
+}
+
+fn push_bounded(stack: &mut Vec<UndoableEdit>, edit: UndoableEdit) {
+    if stack.len() >= MAX_UNDO_DEPTH {
+        stack.remove(0);
+    }
+    stack.push(edit);
+}
+
+/// The undo session for this browser, starting a new one if it doesn't have one yet
+pub fn undo_session(cookies: &Cookies) -> Uuid {
+    if let Some(session) = cookies
+        .get(UNDO_SESSION_COOKIE_NAME)
+        .and_then(|cookie| cookie.value().parse().ok())
+    {
+        return session;
+    }
+
+    let session = Uuid::new_v4();
+    let mut cookie = Cookie::new(UNDO_SESSION_COOKIE_NAME, session.to_string());
+    cookie.set_http_only(true);
+    cookie.set_path("/");
+    cookies.add(cookie);
+    session
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rename(item_id: Uuid, from: &str, to: &str) -> UndoableEdit {
+        UndoableEdit {
+            item_id,
+            forward: vec![Event::LibraryItemNameChangedEvent {
+                new_name: to.to_string(),
+            }],
+            inverse: vec![Event::LibraryItemNameChangedEvent {
+                new_name: from.to_string(),
+            }],
+        }
+    }
+
+    #[test]
+    fn sessions_have_separate_stacks_and_new_edits_clear_redo() {
+        let stacks = UndoStacks::new();
+        let (alice, bob) = (Uuid::new_v4(), Uuid::new_v4());
+        let item_id = Uuid::new_v4();
+        stacks.record(alice, rename(item_id, "A", "B"));
+        stacks.record(alice, rename(item_id, "B", "C"));
+
+        assert!(stacks.take_undo(bob).is_none());
+
+        let undone = stacks.take_undo(alice).unwrap();
+        assert_eq!(undone.forward, rename(item_id, "B", "C").forward);
+        stacks.undone(alice, undone);
+
+        let redone = stacks.take_redo(alice).unwrap();
+        stacks.redone(alice, redone);
+        assert!(stacks.take_redo(alice).is_none());
+
+        stacks.undone(alice, stacks.take_undo(alice).unwrap());
+        stacks.record(alice, rename(item_id, "B", "D"));
+        assert!(stacks.take_redo(alice).is_none());
+    }
+
+    #[test]
+    fn only_the_most_recent_edits_are_kept() {
+        let stacks = UndoStacks::new();
+        let session = Uuid::new_v4();
+        for _ in 0..MAX_UNDO_DEPTH + 10 {
+            stacks.record(session, rename(Uuid::new_v4(), "A", "B"));
+        }
+
+        let mut count = 0;
+        while stacks.take_undo(session).is_some() {
+            count += 1;
+        }
+        assert_eq!(count, MAX_UNDO_DEPTH);
+    }
+}
@@ -1,3 +1,6 @@
+-- The baseline schema (migration 1). Databases that predate migrations already
+-- have some of these tables, hence IF NOT EXISTS. Don't change this file; add a
+-- migration in src/migrations.rs instead.
 CREATE TABLE IF NOT EXISTS
 events(
     Id TEXT PRIMARY KEY NOT NULL,
@@ -8,6 +11,29 @@ events(
     Serialized TEXT NOT NULL
 );
 
+-- Clients remember how far through the server's event log they have synced so
+-- they only need to ask for newer events. Cursors are sequence numbers (Seq) in
+-- the server's events table, so they are only meaningful for the server they
+-- came from.
+CREATE TABLE IF NOT EXISTS
+sync_cursors(
+    Name TEXT PRIMARY KEY NOT NULL,
+    Cursor INTEGER NOT NULL
+);
+
+-- Materialized library/playlist state so startup only has to replay events saved
+-- since the snapshot was taken. Snapshots from other SchemaVersions are ignored.
+CREATE TABLE IF NOT EXISTS
+snapshots(
+    Id INTEGER PRIMARY KEY AUTOINCREMENT,
+    Kind TEXT NOT NULL,
+    SchemaVersion INTEGER NOT NULL,
+    LastEventRowId INTEGER NOT NULL,
+    LastEventTimeUtc TEXT NOT NULL,
+    CreatedTimeUtc TEXT NOT NULL,
+    Serialized TEXT NOT NULL
+);
+
 -- Sonos OAuth tokens are encrypted before they reach SQLite. This singleton row
 -- survives application restarts without putting a refresh token in source or in
 -- a browser cookie.
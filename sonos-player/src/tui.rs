@@ -18,10 +18,15 @@ use ratatui::{
     Frame, Terminal,
 };
 use reitunes_workspace::{
-    download_and_save_events, load_library_from_db, Bookmark, Library, LibraryItem,
+    download_and_save_events, load_library_from_db, push_pending_events, save_event_to_db,
+    Bookmark, Event as LibraryEvent, EventWithMetadata, Library, LibraryItem, PlayDetails,
+    PlaySource,
 };
 use rusqlite::Connection;
-use sonos::{av_transport::SeekRequest, SonosDevice, TrackMetaData, TransportState};
+use sonos::{
+    av_transport::{GetPositionInfoRequest, SeekRequest},
+    AVTransport, SonosDevice, TrackMetaData, TransportState,
+};
 use std::{io, sync::Arc, time::Duration};
 use tokio::{
     select,
@@ -60,6 +65,10 @@ struct App {
     search_active: bool,
     focus: Focus,
     bookmark_state: TableState,
+    /// The item last started from here, whose resume position is saved when it's paused
+    playing: Option<LibraryItem>,
+    /// Where to pause `playing`, when it was started from a range bookmark
+    section_end: Option<Duration>,
 }
 
 impl App {
@@ -74,6 +83,10 @@ impl App {
                     item.name.to_lowercase().contains(&query)
                         || item.artist.to_lowercase().contains(&query)
                         || item.album.to_lowercase().contains(&query)
+                        || item
+                            .tags
+                            .iter()
+                            .any(|tag| tag.to_lowercase().contains(&query))
                 })
                 .cloned()
                 .collect();
@@ -227,6 +240,8 @@ pub async fn run_tui(library: Library, conn: Connection, devices: Vec<&'static s
         search_active: false,
         focus: Focus::Library,
         bookmark_state: TableState::default(),
+        playing: None,
+        section_end: None,
     };
     app_instance.sync_bookmark_selection();
 
@@ -566,7 +581,13 @@ async fn run_app<B: Backend>(
                     } => {
                         if matches!(app.focus, Focus::Bookmarks) {
                             if let Some((item, bookmark)) = app.selected_bookmark() {
-                                let _ = play_bookmark(&app.device, item, bookmark).await;
+                                if play_bookmark(&app.device, item, bookmark).await.is_ok() {
+                                    record_play(&app.conn, item);
+                                    let section_end = bookmark.end_position;
+                                    let item = item.clone();
+                                    app.playing = Some(item);
+                                    app.section_end = section_end;
+                                }
                             }
                         } else if let Some(selected) = app.state.selected() {
                             let items = if app.is_search_mode() {
@@ -575,8 +596,29 @@ async fn run_app<B: Backend>(
                                 &app.items
                             };
                             if selected < items.len() {
-                                let item = &items[selected];
-                                let _ = play_song(&app.device, item).await;
+                                let item = items[selected].clone();
+                                if play_song(&app.device, &item).await.is_ok() {
+                                    record_play(&app.conn, &item);
+                                    // the list isn't reloaded after every save, the library is
+                                    let resume_position = app
+                                        .library
+                                        .items
+                                        .get(&item.id)
+                                        .and_then(|item| item.resume_position);
+                                    if let Some(position) = resume_position {
+                                        let _ = seek_with_retry(
+                                            &app.device,
+                                            SeekRequest {
+                                                instance_id: 0,
+                                                unit: sonos::SeekMode::RelTime,
+                                                target: format_duration(&position),
+                                            },
+                                        )
+                                        .await;
+                                    }
+                                    app.playing = Some(item);
+                                    app.section_end = None;
+                                }
                             }
                         }
                     }
@@ -626,12 +668,14 @@ async fn run_app<B: Backend>(
                         ..
                     } => {
                         play_random_bookmark(&app.device, &app.library).await?;
+                        app.section_end = None;
                     }
                     KeyEvent {
                         code: KeyCode::Char('p'),
                         modifiers: KeyModifiers::NONE,
                         ..
                     } => {
+                        push_pending_events(&mut app.conn).await?;
                         download_and_save_events(&mut app.conn).await?;
                         app.library = load_library_from_db(&app.conn)?;
                         let mut items: Vec<LibraryItem> =
@@ -645,13 +689,22 @@ async fn run_app<B: Backend>(
                     _ => {}
                 }
             }
-            Some(InputEvent::Tick) => {}
+            Some(InputEvent::Tick) => {
+                let mut app = app.lock().await;
+                stop_at_section_end(&mut app).await;
+            }
             Some(InputEvent::TrackMetadataChanged(metadata)) => {
                 let mut app = app.lock().await;
                 app.current_track = metadata;
             }
             Some(InputEvent::TransportStateChanged(state)) => {
                 let mut app = app.lock().await;
+                if matches!(
+                    state,
+                    TransportState::PausedPlayback | TransportState::Stopped
+                ) {
+                    save_resume_position(&mut app).await;
+                }
                 app.transport_state = state;
             }
             None => return Ok(()),
@@ -790,10 +843,17 @@ fn ui(f: &mut Frame, app: &mut App) {
     }
 
     let table_area_index = if app.is_search_mode() { 2 } else { 1 };
+    // ranges need room for their end as well
+    let has_ranges = app.selected_item().is_some_and(|item| {
+        item.bookmarks
+            .values()
+            .any(|bookmark| bookmark.end_position.is_some())
+    });
+    let bookmark_width = if has_ranges { 23 } else { 14 };
     let library_area = main_layout[table_area_index];
     let areas = Layout::default()
         .direction(Direction::Horizontal)
-        .constraints([Constraint::Min(10), Constraint::Length(14)])
+        .constraints([Constraint::Min(10), Constraint::Length(bookmark_width)])
         .split(library_area);
     let table_area = areas[0];
     let bookmark_area = areas[1];
@@ -904,7 +964,7 @@ fn ui(f: &mut Frame, app: &mut App) {
             } else {
                 Style::default().fg(Color::Gray)
             };
-            let time_cell = Cell::from(format_duration(&bookmark.position)).style(base_style);
+            let time_cell = Cell::from(format_bookmark_time(bookmark)).style(base_style);
             Row::new(vec![time_cell]).height(1)
         });
 
@@ -997,6 +1057,27 @@ fn ui(f: &mut Frame, app: &mut App) {
     f.render_widget(controls_paragraph, controls_area);
 }
 
+/// Parse a Sonos `RelTime` (`H:MM:SS`). Devices report `NOT_IMPLEMENTED` for streams.
+fn parse_duration(time: &str) -> Option<Duration> {
+    let mut seconds = 0;
+    for part in time.split(':') {
+        seconds = seconds * 60 + part.parse::<u64>().ok()?;
+    }
+    Some(Duration::from_secs(seconds))
+}
+
+/// e.g. `00:01:02`, or `00:01:02–00:03:30` for a range
+fn format_bookmark_time(bookmark: &Bookmark) -> String {
+    match &bookmark.end_position {
+        Some(end) => format!(
+            "{}–{}",
+            format_duration(&bookmark.position),
+            format_duration(end)
+        ),
+        None => format_duration(&bookmark.position),
+    }
+}
+
 fn format_duration(duration: &Duration) -> String {
     let total_seconds = duration.as_secs();
     let hours = total_seconds / 3600;
@@ -1011,10 +1092,100 @@ fn storage_base_url() -> String {
         .unwrap_or_else(|_| "https://reitunes.s3.ca-east-tor.io.cloud.ovh.net/prod".to_string())
 }
 
-async fn play_song(device: &SonosDevice, item: &LibraryItem) -> Result<()> {
+/// Record a play in the local database. It reaches the server with the next sync.
+fn record_play(conn: &Connection, item: &LibraryItem) {
+    let played = PlayDetails::from_source(PlaySource::Tui);
+    let result = EventWithMetadata::new(item.id, LibraryEvent::LibraryItemPlayedEvent(played))
+        .and_then(|event| save_event_to_db(conn, &event));
+    if let Err(e) = result {
+        warn!("Error recording play: {:?}", e);
+    }
+}
+
+/// Save how far the item last started from here has got, if the device is still playing it.
+/// Like plays, it reaches the server with the next sync.
+async fn save_resume_position(app: &mut App) {
+    let Some(playing) = &app.playing else {
+        return;
+    };
+    let info = match app
+        .device
+        .get_position_info(GetPositionInfoRequest { instance_id: 0 })
+        .await
+    {
+        Ok(info) => info,
+        Err(e) => {
+            warn!("Error getting playback position: {:?}", e);
+            return;
+        }
+    };
+    if info.track_uri.as_deref() != Some(song_url(playing).as_str()) {
+        return;
+    }
+    let Some(item) = app.library.items.get(&playing.id) else {
+        return;
+    };
+    let Some(event) = info
+        .rel_time
+        .as_deref()
+        .and_then(parse_duration)
+        .and_then(|position| item.resume_position_event(position))
+    else {
+        return;
+    };
+    let result = EventWithMetadata::new(item.id, event)
+        .and_then(|event| save_event_to_db(&app.conn, &event).map(|_| event));
+    match result {
+        Ok(event) => app.library.apply(&event),
+        Err(e) => warn!("Error saving resume position: {:?}", e),
+    }
+}
+
+/// Pause once a range bookmark started from here reaches the end of its section
+async fn stop_at_section_end(app: &mut App) {
+    let (Some(end), Some(playing)) = (app.section_end, &app.playing) else {
+        return;
+    };
+    if !matches!(app.transport_state, TransportState::Playing) {
+        return;
+    }
+    let info = match app
+        .device
+        .get_position_info(GetPositionInfoRequest { instance_id: 0 })
+        .await
+    {
+        Ok(info) => info,
+        Err(e) => {
+            warn!("Error getting playback position: {:?}", e);
+            return;
+        }
+    };
+    if info.track_uri.as_deref() != Some(song_url(playing).as_str()) {
+        // something else is playing now
+        app.section_end = None;
+        return;
+    }
+    let reached_end = info
+        .rel_time
+        .as_deref()
+        .and_then(parse_duration)
+        .is_some_and(|position| position >= end);
+    if reached_end {
+        app.section_end = None;
+        if let Err(e) = pause_with_retry(&app.device).await {
+            warn!("Error pausing at the end of a bookmark: {:?}", e);
+        }
+    }
+}
+
+fn song_url(item: &LibraryItem) -> String {
     let base_url = storage_base_url();
     let filename_url_encoded = urlencoding::encode(&item.file_path);
-    let url = format!("{}/{}", base_url, filename_url_encoded);
+    format!("{}/{}", base_url, filename_url_encoded)
+}
+
+async fn play_song(device: &SonosDevice, item: &LibraryItem) -> Result<()> {
+    let url = song_url(item);
 
     let metadata = TrackMetaData { title: item.name.clone(), ..Default::default() };
     set_av_transport_uri_with_retry(device, &url, Some(metadata)).await?;
@@ -0,0 +1,382 @@
+//! Portable library archives: a tar file with every event, a materialized copy of the library
+//! and playlists that's readable without reitunes, and (optionally) the media files themselves.
+//!
+//! Layout:
+//! - `events.jsonl`: every event, one `RawEvent` per line, in the order they're applied
+//! - `items.json`, `playlists.json`: the library items and playlists those events produce
+//! - `media/<file_path>`: the audio for each library item, if exported with media
+//! - `manifest.json`: counts and SHA-256 checksums of everything else. Written last.
+
+use std::collections::BTreeMap;
+use std::io::{self, Read, Write};
+
+use anyhow::{bail, ensure, Context, Result};
+use jiff::Timestamp;
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::info;
+
+use crate::database::{load_all_raw_events, save_raw_event_to_db, RawEvent};
+use crate::envelope::AnyEnvelope;
+use crate::library::{Library, LibraryItem};
+use crate::playlist::{Playlist, PlaylistStore};
+
+/// Bump this when the layout changes in a way older versions can't import
+pub const ARCHIVE_FORMAT_VERSION: u32 = 1;
+
+const MANIFEST_PATH: &str = "manifest.json";
+const EVENTS_PATH: &str = "events.jsonl";
+const ITEMS_PATH: &str = "items.json";
+const PLAYLISTS_PATH: &str = "playlists.json";
+const MEDIA_DIR: &str = "media/";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ArchiveManifest {
+    pub format_version: u32,
+    pub exported_at: Timestamp,
+    pub machine_name: String,
+    pub event_count: usize,
+    pub item_count: usize,
+    pub playlist_count: usize,
+    pub media_count: usize,
+    /// Media files that should have been exported but couldn't be found
+    pub missing_media: Vec<String>,
+    /// SHA-256 of every other file in the archive, keyed by path
+    pub checksums: BTreeMap<String, String>,
+}
+
+/// Writes an archive one piece at a time, so media can be fetched (and dropped) one file at a
+/// time instead of all being held in memory
+pub struct ArchiveWriter<W: Write> {
+    builder: tar::Builder<W>,
+    manifest: ArchiveManifest,
+}
+
+impl<W: Write> ArchiveWriter<W> {
+    pub fn new(out: W) -> Result<Self> {
+        Ok(ArchiveWriter {
+            builder: tar::Builder::new(out),
+            manifest: ArchiveManifest {
+                format_version: ARCHIVE_FORMAT_VERSION,
+                exported_at: Timestamp::now(),
+                machine_name: hostname::get()?.to_string_lossy().into(),
+                event_count: 0,
+                item_count: 0,
+                playlist_count: 0,
+                media_count: 0,
+                missing_media: Vec::new(),
+                checksums: BTreeMap::new(),
+            },
+        })
+    }
+
+    /// Write every event in the database, and the library and playlists they produce. Returns
+    /// the library items, for fetching their media.
+    pub fn write_library(&mut self, conn: &Connection) -> Result<Vec<LibraryItem>> {
+        let events = load_all_raw_events(conn)?;
+        let mut library = Library::new();
+        let mut playlists = PlaylistStore::new();
+        let mut events_jsonl = Vec::new();
+        for event in &events {
+            serde_json::to_writer(&mut events_jsonl, event)?;
+            events_jsonl.push(b'\n');
+            apply(&mut library, &mut playlists, event)?;
+        }
+
+        let items = sorted_items(&library);
+        let playlists: Vec<Playlist> = playlists.playlists.into_values().collect();
+        self.append(EVENTS_PATH, &events_jsonl)?;
+        self.append(ITEMS_PATH, &serde_json::to_vec_pretty(&items)?)?;
+        self.append(PLAYLISTS_PATH, &serde_json::to_vec_pretty(&playlists)?)?;
+        self.manifest.event_count = events.len();
+        self.manifest.item_count = items.len();
+        self.manifest.playlist_count = playlists.len();
+        Ok(items)
+    }
+
+    /// Add the media for a library item, stored under its `file_path`
+    pub fn add_media(&mut self, file_path: &str, data: &[u8]) -> Result<()> {
+        self.append(&format!("{MEDIA_DIR}{file_path}"), data)?;
+        self.manifest.media_count += 1;
+        Ok(())
+    }
+
+    /// Note a media file that couldn't be exported, so the manifest says what's missing
+    pub fn add_missing_media(&mut self, file_path: &str) {
+        self.manifest.missing_media.push(file_path.to_string());
+    }
+
+    /// Write the manifest and finish the archive
+    pub fn finish(mut self) -> Result<ArchiveManifest> {
+        let manifest = self.manifest.clone();
+        self.append(MANIFEST_PATH, &serde_json::to_vec_pretty(&manifest)?)?;
+        self.builder.into_inner()?.flush()?;
+        Ok(manifest)
+    }
+
+    fn append(&mut self, path: &str, data: &[u8]) -> Result<()> {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(self.manifest.exported_at.as_second().max(0) as u64);
+        self.builder
+            .append_data(&mut header, path, data)
+            .with_context(|| format!("Failed to write {path} to archive"))?;
+        if path != MANIFEST_PATH {
+            self.manifest
+                .checksums
+                .insert(path.to_string(), sha256_hex(data));
+        }
+        Ok(())
+    }
+}
+
+/// Restore an archive into an empty database. Every checksum is verified and the events are
+/// replayed and compared with the archive's items and playlists before anything is committed.
+pub fn import_archive(conn: &Connection, archive: impl Read) -> Result<ArchiveManifest> {
+    let event_count: i64 = conn.query_row("SELECT COUNT(*) FROM events", [], |row| row.get(0))?;
+    ensure!(
+        event_count == 0,
+        "Archives can only be imported into an empty database (this one has {event_count} events)"
+    );
+
+    let mut manifest: Option<ArchiveManifest> = None;
+    let mut contents: BTreeMap<String, Vec<u8>> = BTreeMap::new();
+    let mut checksums = BTreeMap::new();
+    let mut archive = tar::Archive::new(archive);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.to_string_lossy().into_owned();
+        if path == MANIFEST_PATH {
+            manifest = Some(serde_json::from_reader(&mut entry).context("Invalid manifest")?);
+        } else if path.starts_with(MEDIA_DIR) {
+            // media can be big, so hash it without keeping it around
+            let mut hasher = Sha256::new();
+            io::copy(&mut entry, &mut hasher)?;
+            checksums.insert(path, format!("{:x}", hasher.finalize()));
+        } else {
+            let mut data = Vec::new();
+            entry.read_to_end(&mut data)?;
+            checksums.insert(path.clone(), sha256_hex(&data));
+            contents.insert(path, data);
+        }
+    }
+
+    let Some(manifest) = manifest else {
+        bail!("Archive has no {MANIFEST_PATH}");
+    };
+    ensure!(
+        manifest.format_version <= ARCHIVE_FORMAT_VERSION,
+        "Archive format version {} is newer than this version of reitunes supports ({ARCHIVE_FORMAT_VERSION})",
+        manifest.format_version
+    );
+    for (path, expected) in &manifest.checksums {
+        match checksums.remove(path) {
+            Some(actual) if actual == *expected => {}
+            Some(_) => bail!("Checksum mismatch for {path}"),
+            None => bail!("{path} is listed in the manifest but missing from the archive"),
+        }
+    }
+    if let Some(path) = checksums.keys().next() {
+        bail!("{path} is in the archive but not listed in the manifest");
+    }
+
+    let file = |path: &str| {
+        contents
+            .get(path)
+            .with_context(|| format!("Archive has no {path}"))
+    };
+    let mut events = Vec::new();
+    for (i, line) in file(EVENTS_PATH)?.split(|&b| b == b'\n').enumerate() {
+        if !line.is_empty() {
+            let event: RawEvent = serde_json::from_slice(line)
+                .with_context(|| format!("Invalid event on line {}", i + 1))?;
+            events.push(event);
+        }
+    }
+    ensure!(
+        events.len() == manifest.event_count,
+        "Archive has {} events but the manifest says {}",
+        events.len(),
+        manifest.event_count
+    );
+
+    let mut library = Library::new();
+    let mut playlists = PlaylistStore::new();
+    let tx = conn.unchecked_transaction()?;
+    for event in &events {
+        save_raw_event_to_db(&tx, event)?;
+        apply(&mut library, &mut playlists, event)?;
+    }
+
+    let items: Vec<LibraryItem> = serde_json::from_slice(file(ITEMS_PATH)?)?;
+    let archived_playlists: Vec<Playlist> = serde_json::from_slice(file(PLAYLISTS_PATH)?)?;
+    ensure!(
+        items.len() == manifest.item_count && archived_playlists.len() == manifest.playlist_count,
+        "Archive item or playlist counts don't match the manifest"
+    );
+    ensure!(
+        sorted_items(&library) == items,
+        "Replaying the archived events doesn't produce the archived library items"
+    );
+    ensure!(
+        playlists.playlists.into_values().collect::<Vec<_>>() == archived_playlists,
+        "Replaying the archived events doesn't produce the archived playlists"
+    );
+    tx.commit()?;
+
+    info!(
+        events = manifest.event_count,
+        items = manifest.item_count,
+        playlists = manifest.playlist_count,
+        "Imported archive"
+    );
+    Ok(manifest)
+}
+
+fn apply(library: &mut Library, playlists: &mut PlaylistStore, event: &RawEvent) -> Result<()> {
+    // events of aggregate types from a newer version are archived, but there's nothing to
+    // apply them to
+    match AnyEnvelope::from_raw_if_known(event.clone())
+        .with_context(|| format!("Failed to read event {}", event.id))?
+    {
+        Some(AnyEnvelope::Library(event)) => library.apply(&event),
+        Some(AnyEnvelope::Playlist(event)) => playlists.apply(&event),
+        None => {}
+    }
+    Ok(())
+}
+
+/// Library items in a stable order, so the same library always produces the same `items.json`
+fn sorted_items(library: &Library) -> Vec<LibraryItem> {
+    let mut items: Vec<_> = library.items.values().cloned().collect();
+    items.sort_by_key(|item| (item.created_time_utc, item.id));
+    items
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::save_event_to_db;
+    use crate::envelope::Envelope;
+    use crate::library::{created, Event, PlayDetails};
+    use crate::migrations::migrate;
+    use crate::playlist::PlaylistEvent;
+    use uuid::Uuid;
+
+    fn library_db() -> Result<Connection> {
+        let conn = Connection::open_in_memory()?;
+        migrate(&conn)?;
+        let item_id = Uuid::new_v4();
+        let playlist_id = Uuid::new_v4();
+        save_event_to_db(&conn, &Envelope::new(item_id, created("Song"))?)?;
+        save_event_to_db(
+            &conn,
+            &Envelope::new(
+                item_id,
+                Event::LibraryItemPlayedEvent(PlayDetails::default()),
+            )?,
+        )?;
+        save_event_to_db(
+            &conn,
+            &Envelope::new(
+                playlist_id,
+                PlaylistEvent::PlaylistCreatedEvent {
+                    name: "Mix".to_string(),
+                },
+            )?,
+        )?;
+        save_event_to_db(
+            &conn,
+            &Envelope::new(
+                playlist_id,
+                PlaylistEvent::PlaylistItemAddedEvent {
+                    library_item_id: item_id,
+                    position: 0,
+                },
+            )?,
+        )?;
+        // from a newer version, so it's archived but not applied
+        let mut smart_playlist = Envelope::new(
+            Uuid::new_v4(),
+            PlaylistEvent::PlaylistCreatedEvent {
+                name: "Smart".to_string(),
+            },
+        )?
+        .to_raw()?;
+        smart_playlist.aggregate_type = "SmartPlaylist".to_string();
+        save_raw_event_to_db(&conn, &smart_playlist)?;
+        Ok(conn)
+    }
+
+    fn export(conn: &Connection) -> Result<Vec<u8>> {
+        let mut archive = Vec::new();
+        let mut writer = ArchiveWriter::new(&mut archive)?;
+        for item in writer.write_library(conn)? {
+            writer.add_media(&item.file_path, b"ID3 pretend audio")?;
+        }
+        writer.add_missing_media("gone.mp3");
+        writer.finish()?;
+        Ok(archive)
+    }
+
+    #[test]
+    fn archives_round_trip_into_an_empty_database() -> Result<()> {
+        let source = library_db()?;
+        let archive = export(&source)?;
+
+        let restored = Connection::open_in_memory()?;
+        migrate(&restored)?;
+        let manifest = import_archive(&restored, archive.as_slice())?;
+        assert_eq!(
+            (
+                manifest.event_count,
+                manifest.item_count,
+                manifest.playlist_count
+            ),
+            (5, 1, 1)
+        );
+        assert_eq!(manifest.media_count, 1);
+        assert_eq!(manifest.missing_media, vec!["gone.mp3".to_string()]);
+
+        let without_cursors = |events: Vec<RawEvent>| -> Vec<RawEvent> {
+            events
+                .into_iter()
+                .map(|event| RawEvent { cursor: 0, ..event })
+                .collect()
+        };
+        assert_eq!(
+            without_cursors(load_all_raw_events(&restored)?),
+            without_cursors(load_all_raw_events(&source)?)
+        );
+
+        // importing twice would duplicate the library, so it's refused
+        assert!(import_archive(&restored, archive.as_slice()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn tampered_archives_are_rejected() -> Result<()> {
+        let mut archive = export(&library_db()?)?;
+        let needle = b"\"Song\"";
+        let start = archive
+            .windows(needle.len())
+            .position(|window| window == needle)
+            .unwrap();
+        archive[start + 1] = b'X';
+
+        let conn = Connection::open_in_memory()?;
+        migrate(&conn)?;
+        let error = import_archive(&conn, archive.as_slice()).unwrap_err();
+        assert!(error.to_string().contains("Checksum mismatch"), "{error:#}");
+        let event_count: i64 =
+            conn.query_row("SELECT COUNT(*) FROM events", [], |row| row.get(0))?;
+        assert_eq!(event_count, 0);
+        Ok(())
+    }
+}
@@ -1,26 +1,120 @@
-use anyhow::Result;
+use anyhow::{bail, Context, Result};
+use jiff::civil::DateTime;
 use r2d2::Pool;
 use r2d2_sqlite::SqliteConnectionManager;
 use reqwest::header::{HeaderMap, HeaderValue};
-use rusqlite::{params, Connection};
+use rusqlite::{params, Connection, OpenFlags, OptionalExtension};
+use serde::{Deserialize, Serialize};
 use serde_rusqlite::*;
-use std::collections::HashSet;
 use tracing::{info, instrument};
+use uuid::Uuid;
+
+use crate::envelope::{AggregateEvent, AggregateType, Envelope};
+use crate::hlc::{Hlc, CLOCK};
+use crate::migrations::{migrate, SCHEMA_VERSION};
+
+const REMOTE_URL: &str = "https://reitunes.reillywood.com";
+
+/// How many events to request per page when pulling from the server
+const SYNC_PAGE_SIZE: usize = 1000;
+
+/// Name of the `sync_cursors` row that tracks how far we've pulled from the server
+const PULL_CURSOR: &str = "pull";
+
+/// Name of the `sync_cursors` row that tracks the last local event we've pushed to the server
+const PUSH_CURSOR: &str = "push";
+
+/// The server's API key, read at runtime. Clients built with the key baked in (sonos-player's
+/// `just build-with-secrets`) fall back to that.
+fn remote_api_key() -> Result<HeaderValue> {
+    let api_key = match std::env::var("REITUNES_API_KEY") {
+        Ok(api_key) => api_key,
+        Err(_) => match option_env!("REITUNES_API_KEY") {
+            Some(api_key) => api_key.to_string(),
+            None if cfg!(debug_assertions) => "development-only-api-key".to_string(),
+            None => bail!("REITUNES_API_KEY must be set to sync with the server"),
+        },
+    };
+    HeaderValue::from_str(&api_key).context("REITUNES_API_KEY is not a valid header value")
+}
+
+/// An event as stored in the `events` table, with its payload left as JSON.
+///
+/// Sync passes these around instead of `EventWithMetadata` so that it works for every
+/// aggregate type without having to decode (or even understand) the events themselves.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RawEvent {
+    /// The event's position in the log of the database it was loaded from (its `Seq`)
+    #[serde(default)]
+    pub cursor: i64,
+    pub id: Uuid,
+    pub aggregate_id: Uuid,
+    pub aggregate_type: String,
+    pub created_time_utc: DateTime,
+    /// Missing from events pushed by clients that predate HLCs
+    #[serde(default)]
+    pub hlc: Option<Hlc>,
+    pub machine_name: String,
+    pub event: serde_json::Value,
+}
+
+impl RawEvent {
+    /// The event's HLC, falling back to its creation time for events that don't have one
+    pub fn hlc(&self) -> Hlc {
+        self.hlc
+            .unwrap_or_else(|| Hlc::from_created_time(self.created_time_utc))
+    }
+}
 
-use crate::library::{EventRow, EventWithMetadata};
-use crate::playlist::PlaylistEventWithMetadata;
+/// A page of events returned by `GET /api/events`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EventPage {
+    pub events: Vec<RawEvent>,
+    /// Pass this as `after` to get the next page
+    pub next_cursor: i64,
+    pub has_more: bool,
+}
 
-#[cfg(debug_assertions)]
-const REMOTE_API_KEY: &str = match option_env!("REITUNES_API_KEY") {
-    Some(api_key) => api_key,
-    None => "development-only-api-key",
-};
+/// Response to `POST /api/events`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PushResponse {
+    /// How many of the pushed events were new to the server
+    pub saved_count: usize,
+}
 
-#[cfg(not(debug_assertions))]
-const REMOTE_API_KEY: &str = env!(
-    "REITUNES_API_KEY",
-    "REITUNES_API_KEY must be set when building a release binary"
-);
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct RawEventRow {
+    cursor: i64,
+    id: Uuid,
+    aggregate_id: Uuid,
+    aggregate_type: String,
+    created_time_utc: DateTime,
+    machine_name: String,
+    serialized: String,
+    hlc_millis: i64,
+    hlc_counter: u32,
+}
+
+impl RawEventRow {
+    fn into_raw_event(self) -> Result<RawEvent> {
+        let event = serde_json::from_str(&self.serialized)
+            .with_context(|| format!("Event {} is not valid JSON", self.id))?;
+        Ok(RawEvent {
+            cursor: self.cursor,
+            id: self.id,
+            aggregate_id: self.aggregate_id,
+            aggregate_type: self.aggregate_type,
+            created_time_utc: self.created_time_utc,
+            hlc: Some(Hlc {
+                millis: self.hlc_millis,
+                counter: self.hlc_counter,
+            }),
+            machine_name: self.machine_name,
+            event,
+        })
+    }
+}
 
 /// Open a direct SQLite connection (used by sonos-player)
 pub fn open_connection(db_path: &str) -> Result<Connection> {
@@ -35,8 +129,30 @@ pub fn open_connection(db_path: &str) -> Result<Connection> {
     // TODO: reenable this when we're further out of development
     // conn.execute_batch("PRAGMA journal_mode=WAL;")?;
 
-    // initialize tables if needed
-    conn.execute_batch(include_str!("../schema.sql"))?;
+    // create tables if needed and bring the schema up to date
+    migrate(&conn)?;
+    observe_latest_hlc(&conn)?;
+
+    Ok(conn)
+}
+
+/// Open a direct SQLite connection that can't write, for tools that only inspect the
+/// database. Unlike `open_connection` this doesn't migrate, so it refuses databases that
+/// aren't at the schema version this build expects.
+pub fn open_read_only_connection(db_path: &str) -> Result<Connection> {
+    let conn = Connection::open_with_flags(
+        db_path,
+        OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+    )
+    .with_context(|| format!("Failed to open {db_path} read-only"))?;
+
+    let version: i64 = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    if version != SCHEMA_VERSION {
+        bail!(
+            "Database schema version {version} isn't the version this build supports \
+             ({SCHEMA_VERSION}); open it with a matching version of reitunes first"
+        );
+    }
 
     Ok(conn)
 }
@@ -56,147 +172,580 @@ pub fn open_connection_pool(db_path: &str) -> Result<Pool<SqliteConnectionManage
     // TODO: reenable this when we're further out of development
     // conn.execute_batch("PRAGMA journal_mode=WAL;")?;
 
-    // initialize tables if needed
-    conn.execute_batch(include_str!("../schema.sql"))?;
+    // create tables if needed and bring the schema up to date
+    migrate(&conn)?;
+    observe_latest_hlc(&conn)?;
 
     Ok(pool)
 }
 
-/// Save an event to the database
-pub fn save_event_to_db(conn: &Connection, event: &EventWithMetadata) -> Result<()> {
+/// Make sure new events sort after every event already in the database, even if the
+/// system clock has gone backwards since they were created
+fn observe_latest_hlc(conn: &Connection) -> Result<()> {
+    let latest = conn
+        .query_row(
+            "SELECT HlcMillis, HlcCounter FROM events ORDER BY HlcMillis DESC, HlcCounter DESC LIMIT 1",
+            [],
+            |row| {
+                Ok(Hlc {
+                    millis: row.get(0)?,
+                    counter: row.get(1)?,
+                })
+            },
+        )
+        .optional()?;
+    if let Some(latest) = latest {
+        CLOCK.observe(latest);
+    }
+    Ok(())
+}
+
+/// Save an event (of any aggregate type) to the database
+pub fn save_event_to_db<E: AggregateEvent>(conn: &Connection, event: &Envelope<E>) -> Result<()> {
     let mut stmt = conn.prepare_cached(
-        "INSERT INTO events (Id, AggregateId, AggregateType, CreatedTimeUtc, MachineName, Serialized) 
-         VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        "INSERT INTO events (Id, AggregateId, AggregateType, CreatedTimeUtc, MachineName, Serialized, HlcMillis, HlcCounter)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
     )?;
 
     stmt.execute(params![
         event.id.to_string(),
         event.aggregate_id.to_string(),
-        event.aggregate_type,
+        event.aggregate_type.as_str(),
         event.created_time_utc.to_string(),
         event.machine_name,
         serde_json::to_string(&event.event)?,
+        event.hlc.millis,
+        event.hlc.counter,
     ])?;
 
     Ok(())
 }
 
-/// Save a playlist event to the database.
-pub fn save_playlist_event_to_db(
+/// Load all events of one aggregate type from the database (e.g.
+/// `load_all_events_from_db::<Event>` for the library)
+#[instrument(skip(conn))]
+pub fn load_all_events_from_db<E: AggregateEvent>(conn: &Connection) -> Result<Vec<Envelope<E>>> {
+    let events: Vec<_> = load_events_after_seq(conn, 0)?
+        .into_iter()
+        .map(|(_, event)| event)
+        .collect();
+
+    info!(
+        event_count = events.len(),
+        aggregate_type = %E::AGGREGATE_TYPE,
+        "Loaded all events from db"
+    );
+
+    Ok(events)
+}
+
+/// Load events of one aggregate type saved after `after_seq` in the order they should be
+/// applied (by HLC), along with their `Seq`s
+pub fn load_events_after_seq<E: AggregateEvent>(
     conn: &Connection,
-    event: &PlaylistEventWithMetadata,
-) -> Result<()> {
+    after_seq: i64,
+) -> Result<Vec<(i64, Envelope<E>)>> {
     let mut stmt = conn.prepare_cached(
-        "INSERT INTO events (Id, AggregateId, AggregateType, CreatedTimeUtc, MachineName, Serialized)
-         VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        "SELECT Seq AS Cursor, * FROM events e WHERE e.AggregateType == ?1 AND Seq > ?2
+         ORDER BY HlcMillis, HlcCounter, CreatedTimeUtc, MachineName, Id",
     )?;
 
-    stmt.execute(params![
+    // do the easy thing and load each row into a struct
+    // can get some performance wins by only getting the columns we care about, but
+    // this thing runs in sub-10ms with 3000 rows so it's not a big deal.
+    let rows =
+        from_rows::<RawEventRow>(stmt.query(params![E::AGGREGATE_TYPE.as_str(), after_seq])?);
+
+    let mut events = Vec::new();
+    for row in rows {
+        let raw = row?.into_raw_event()?;
+        events.push((raw.cursor, Envelope::from_raw(raw)?));
+    }
+
+    Ok(events)
+}
+
+/// Load every event of one aggregate type in the order they should be applied (by HLC),
+/// without decoding them
+pub fn load_raw_events_by_aggregate_type(
+    conn: &Connection,
+    aggregate_type: AggregateType,
+) -> Result<Vec<RawEvent>> {
+    let mut stmt = conn.prepare_cached(
+        "SELECT Seq AS Cursor, * FROM events WHERE AggregateType = ?1
+         ORDER BY HlcMillis, HlcCounter, CreatedTimeUtc, MachineName, Id",
+    )?;
+
+    let rows = from_rows::<RawEventRow>(stmt.query(params![aggregate_type.as_str()])?);
+    let mut events = Vec::new();
+    for row in rows {
+        events.push(row?.into_raw_event()?);
+    }
+    Ok(events)
+}
+
+/// Load every event for one aggregate (library item or playlist) in the order they should be
+/// applied (by HLC), without decoding them
+pub fn load_raw_events_by_aggregate_id(
+    conn: &Connection,
+    aggregate_id: Uuid,
+) -> Result<Vec<RawEvent>> {
+    let mut stmt = conn.prepare_cached(
+        "SELECT Seq AS Cursor, * FROM events WHERE AggregateId = ?1
+         ORDER BY HlcMillis, HlcCounter, CreatedTimeUtc, MachineName, Id",
+    )?;
+
+    let rows = from_rows::<RawEventRow>(stmt.query(params![aggregate_id.to_string()])?);
+    let mut events = Vec::new();
+    for row in rows {
+        events.push(row?.into_raw_event()?);
+    }
+    Ok(events)
+}
+
+/// Load up to `limit` library item plays created before `before` (if given), newest first.
+/// Plays are picked out by their `$type`, so nothing else in the log gets decoded.
+pub fn load_raw_plays(
+    conn: &Connection,
+    before: Option<DateTime>,
+    limit: usize,
+) -> Result<Vec<RawEvent>> {
+    let mut stmt = conn.prepare_cached(
+        "SELECT Seq AS Cursor, * FROM events
+         WHERE AggregateType = ?1
+           AND json_extract(Serialized, '$.\"$type\"') = 'LibraryItemPlayedEvent'
+           AND (?2 IS NULL OR CreatedTimeUtc < ?2)
+         ORDER BY CreatedTimeUtc DESC, Seq DESC
+         LIMIT ?3",
+    )?;
+
+    let rows = from_rows::<RawEventRow>(stmt.query(params![
+        AggregateType::LibraryItem.as_str(),
+        before.map(|before| before.to_string()),
+        limit as i64,
+    ])?);
+    let mut events = Vec::new();
+    for row in rows {
+        events.push(row?.into_raw_event()?);
+    }
+    Ok(events)
+}
+
+/// Load every event of every aggregate type in the order they should be applied (by HLC),
+/// without decoding them
+pub fn load_all_raw_events(conn: &Connection) -> Result<Vec<RawEvent>> {
+    let mut stmt = conn.prepare_cached(
+        "SELECT Seq AS Cursor, * FROM events
+         ORDER BY HlcMillis, HlcCounter, CreatedTimeUtc, MachineName, Id",
+    )?;
+
+    let rows = from_rows::<RawEventRow>(stmt.query([])?);
+    let mut events = Vec::new();
+    for row in rows {
+        events.push(row?.into_raw_event()?);
+    }
+    Ok(events)
+}
+
+/// Load one event by ID, without decoding it
+pub fn load_raw_event_by_id(conn: &Connection, id: Uuid) -> Result<Option<RawEvent>> {
+    let mut stmt = conn.prepare_cached("SELECT Seq AS Cursor, * FROM events WHERE Id = ?1")?;
+
+    let mut rows = from_rows::<RawEventRow>(stmt.query(params![id.to_string()])?);
+    match rows.next() {
+        Some(row) => Ok(Some(row?.into_raw_event()?)),
+        None => Ok(None),
+    }
+}
+
+/// An event that's in the `events` table but can't be read
+#[derive(Debug, Clone, Serialize)]
+pub struct UnreadableEvent {
+    pub cursor: i64,
+    /// Missing if even the ID is garbled
+    pub id: Option<String>,
+    pub error: String,
+}
+
+/// Like `load_all_raw_events`, but sets aside events that can't be read instead of failing.
+/// For `fsck`; everything else should fail loudly.
+pub fn load_all_raw_events_lenient(
+    conn: &Connection,
+) -> Result<(Vec<RawEvent>, Vec<UnreadableEvent>)> {
+    let mut stmt = conn.prepare_cached(
+        "SELECT Seq AS Cursor, * FROM events
+         ORDER BY HlcMillis, HlcCounter, CreatedTimeUtc, MachineName, Id",
+    )?;
+
+    let mut rows = stmt.query([])?;
+    let mut events = Vec::new();
+    let mut unreadable = Vec::new();
+    while let Some(row) = rows.next()? {
+        let event = from_row::<RawEventRow>(row)
+            .map_err(anyhow::Error::from)
+            .and_then(RawEventRow::into_raw_event);
+        match event {
+            Ok(event) => events.push(event),
+            Err(error) => unreadable.push(UnreadableEvent {
+                cursor: row.get("Cursor")?,
+                id: row.get("Id").ok(),
+                error: format!("{error:#}"),
+            }),
+        }
+    }
+    Ok((events, unreadable))
+}
+
+/// Save an event received from another database, skipping it if we already have it.
+/// Returns true if the event was new.
+pub fn save_raw_event_to_db(conn: &Connection, event: &RawEvent) -> Result<bool> {
+    let mut stmt = conn.prepare_cached(
+        "INSERT OR IGNORE INTO events (Id, AggregateId, AggregateType, CreatedTimeUtc, MachineName, Serialized, HlcMillis, HlcCounter)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+    )?;
+
+    let hlc = event.hlc();
+    let inserted = stmt.execute(params![
         event.id.to_string(),
         event.aggregate_id.to_string(),
         event.aggregate_type,
         event.created_time_utc.to_string(),
         event.machine_name,
         serde_json::to_string(&event.event)?,
+        hlc.millis,
+        hlc.counter,
     ])?;
+    CLOCK.observe(hlc);
 
-    Ok(())
+    Ok(inserted > 0)
 }
 
-/// Load all events from the database
+/// Load up to `limit` events (of every aggregate type) that were saved after `after`, in the
+/// order they were saved. Cursors are `Seq`s, so new events always sort after old ones even
+/// if their timestamps don't, and unlike implicit rowids they survive VACUUM.
 #[instrument(skip(conn))]
-pub fn load_all_events_from_db(conn: &Connection) -> Result<Vec<EventWithMetadata>> {
+pub fn load_event_page(conn: &Connection, after: i64, limit: usize) -> Result<EventPage> {
     let mut stmt = conn.prepare_cached(
-        "SELECT * FROM events e WHERE e.AggregateType == 'LibraryItem' ORDER BY CreatedTimeUtc",
+        "SELECT Seq AS Cursor, * FROM events WHERE Seq > ?1 ORDER BY Seq LIMIT ?2",
     )?;
 
-    // do the easy thing and load each row into a struct
-    // can get some performance wins by only getting the columns we care about, but
-    // this thing runs in sub-10ms with 3000 rows so it's not a big deal.
-    let rows = from_rows::<EventRow>(stmt.query([])?);
-
+    // grab one extra row so we know whether there's another page without a second query
+    let rows = from_rows::<RawEventRow>(stmt.query(params![after, limit as i64 + 1])?);
     let mut events = Vec::new();
     for row in rows {
-        let row = row?;
-        let event = EventWithMetadata::from_row(row)?;
-        events.push(event);
+        events.push(row?.into_raw_event()?);
     }
 
-    info!(event_count = events.len(), "Loaded all events from db");
+    let has_more = events.len() > limit;
+    events.truncate(limit);
+    let next_cursor = events.last().map_or(after, |event| event.cursor);
 
-    Ok(events)
+    Ok(EventPage {
+        events,
+        next_cursor,
+        has_more,
+    })
 }
 
-/// Load all playlist events from the database.
-#[instrument(skip(conn))]
-pub fn load_all_playlist_events_from_db(
+/// Load events created on `machine_name` that were saved after `after`. Events from other
+/// machines either came from the server or will be pushed by the machine that created them.
+fn load_local_events(
     conn: &Connection,
-) -> Result<Vec<PlaylistEventWithMetadata>> {
+    machine_name: &str,
+    after: i64,
+    limit: usize,
+) -> Result<Vec<RawEvent>> {
     let mut stmt = conn.prepare_cached(
-        "SELECT * FROM events e WHERE e.AggregateType == 'Playlist' ORDER BY CreatedTimeUtc, rowid",
+        "SELECT Seq AS Cursor, * FROM events WHERE Seq > ?1 AND MachineName = ?2
+         ORDER BY Seq LIMIT ?3",
     )?;
 
-    let rows = from_rows::<EventRow>(stmt.query([])?);
+    let rows = from_rows::<RawEventRow>(stmt.query(params![after, machine_name, limit as i64])?);
     let mut events = Vec::new();
     for row in rows {
-        events.push(PlaylistEventWithMetadata::from_row(row?)?);
+        events.push(row?.into_raw_event()?);
     }
-
-    info!(
-        event_count = events.len(),
-        "Loaded all playlist events from db"
-    );
     Ok(events)
 }
 
-/// Download events from remote server and save them to the database (sonos-player specific)
+/// Load a sync cursor, or 0 (the start of the log) if we've never synced
+pub fn load_sync_cursor(conn: &Connection, name: &str) -> Result<i64> {
+    let cursor = conn
+        .query_row(
+            "SELECT Cursor FROM sync_cursors WHERE Name = ?1",
+            params![name],
+            |row| row.get(0),
+        )
+        .optional()?;
+    Ok(cursor.unwrap_or(0))
+}
+
+/// Save a sync cursor
+pub fn save_sync_cursor(conn: &Connection, name: &str, cursor: i64) -> Result<()> {
+    conn.execute(
+        "INSERT OR REPLACE INTO sync_cursors (Name, Cursor) VALUES (?1, ?2)",
+        params![name, cursor],
+    )?;
+    Ok(())
+}
+
+/// Download events we haven't seen yet from the remote server and save them to the database
+/// (sonos-player specific). Only events after the last synced cursor are requested.
 pub async fn download_and_save_events(conn: &mut Connection) -> Result<()> {
-    info!("Downloading events");
+    let mut cursor = load_sync_cursor(conn, PULL_CURSOR)?;
+    info!(cursor, "Downloading events");
     let mut headers = HeaderMap::new();
-    headers.insert("X-API-Key", HeaderValue::from_static(REMOTE_API_KEY));
+    headers.insert("X-API-Key", remote_api_key()?);
 
     let client = reqwest::Client::new();
-    let events: Vec<EventWithMetadata> = client
-        .get("https://reitunes.reillywood.com/api/allevents")
-        .headers(headers)
-        .send()
-        .await?
-        .error_for_status()?
-        .json()
-        .await?;
-
-    info!(event_count = events.len(), "Downloaded events");
-
-    // Start a transaction
-    let mut tx = conn.transaction()?;
-    tx.set_drop_behavior(rusqlite::DropBehavior::Commit);
-
-    // Get existing event IDs to avoid duplicates
-    let mut stmt = tx.prepare_cached("SELECT Id FROM events")?;
-    let existing_ids: HashSet<String> = stmt
-        .query_map([], |row| row.get(0))?
-        .filter_map(Result::ok)
-        .collect();
-
-    let mut stmt = tx.prepare_cached(
-        "INSERT INTO events (Id, AggregateId, AggregateType, CreatedTimeUtc, MachineName, Serialized) 
-         VALUES (?1, ?2, ?3, ?4, ?5, ?6)"
-    )?;
+    let mut saved_count = 0;
+    loop {
+        let page: EventPage = client
+            .get(format!("{REMOTE_URL}/api/events"))
+            .query(&[("after", cursor), ("limit", SYNC_PAGE_SIZE as i64)])
+            .headers(headers.clone())
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        // Save the page and the cursor together so an interrupted sync picks up where it left off
+        let tx = conn.transaction()?;
+        for event in &page.events {
+            if save_raw_event_to_db(&tx, event)? {
+                saved_count += 1;
+            }
+        }
+        save_sync_cursor(&tx, PULL_CURSOR, page.next_cursor)?;
+        tx.commit()?;
 
-    // Only save events that don't already exist
-    for event in events {
-        if !existing_ids.contains(&event.id.to_string()) {
-            stmt.execute(params![
-                event.id.to_string(),
-                event.aggregate_id.to_string(),
-                event.aggregate_type,
-                event.created_time_utc.to_string(),
-                event.machine_name,
-                serde_json::to_string(&event.event)?,
-            ])?;
+        cursor = page.next_cursor;
+        if !page.has_more {
+            break;
         }
     }
 
-    info!("Saved events");
+    info!(saved_count, cursor, "Saved events");
     Ok(())
 }
+
+/// Upload events created on this machine that haven't been pushed to the remote server yet
+/// (sonos-player specific). Returns how many of them were new to the server.
+pub async fn push_pending_events(conn: &mut Connection) -> Result<usize> {
+    let machine_name: String = hostname::get()?.to_string_lossy().into();
+    let mut cursor = load_sync_cursor(conn, PUSH_CURSOR)?;
+    let mut headers = HeaderMap::new();
+    headers.insert("X-API-Key", remote_api_key()?);
+
+    let client = reqwest::Client::new();
+    let mut saved_count = 0;
+    loop {
+        let events = load_local_events(conn, &machine_name, cursor, SYNC_PAGE_SIZE)?;
+        let Some(last_cursor) = events.last().map(|event| event.cursor) else {
+            break;
+        };
+
+        info!(event_count = events.len(), "Pushing events");
+        let response: PushResponse = client
+            .post(format!("{REMOTE_URL}/api/events"))
+            .headers(headers.clone())
+            .json(&events)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        // The server ignores events it already has, so if we crash before saving the cursor
+        // the worst case is pushing the same page again
+        save_sync_cursor(conn, PUSH_CURSOR, last_cursor)?;
+        saved_count += response.saved_count;
+        cursor = last_cursor;
+        if events.len() < SYNC_PAGE_SIZE {
+            break;
+        }
+    }
+
+    info!(saved_count, cursor, "Pushed events");
+    Ok(saved_count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::library::{created, Event, EventWithMetadata, PlayDetails};
+    use crate::playlist::{PlaylistEvent, PlaylistEventWithMetadata};
+
+    fn raw_event_from_page(conn: &Connection, id: Uuid) -> RawEvent {
+        load_event_page(conn, 0, 100)
+            .unwrap()
+            .events
+            .into_iter()
+            .find(|event| event.id == id)
+            .unwrap()
+    }
+
+    #[test]
+    fn event_pages_cover_every_aggregate_type_in_save_order() -> Result<()> {
+        let conn = Connection::open_in_memory()?;
+        migrate(&conn)?;
+
+        let item_id = Uuid::new_v4();
+        let playlist_id = Uuid::new_v4();
+        let played = EventWithMetadata::new(
+            item_id,
+            Event::LibraryItemPlayedEvent(PlayDetails::default()),
+        )?;
+        let created = PlaylistEventWithMetadata::new(
+            playlist_id,
+            PlaylistEvent::PlaylistCreatedEvent {
+                name: "Mixes".to_string(),
+            },
+        )?;
+        let deleted = EventWithMetadata::new(item_id, Event::LibraryItemDeletedEvent)?;
+        save_event_to_db(&conn, &played)?;
+        save_event_to_db(&conn, &created)?;
+        save_event_to_db(&conn, &deleted)?;
+
+        let first_page = load_event_page(&conn, 0, 2)?;
+        assert_eq!(
+            first_page.events.iter().map(|e| e.id).collect::<Vec<_>>(),
+            vec![played.id, created.id]
+        );
+        assert_eq!(first_page.events[1].aggregate_type, "Playlist");
+        assert!(first_page.has_more);
+
+        let second_page = load_event_page(&conn, first_page.next_cursor, 2)?;
+        assert_eq!(
+            second_page.events.iter().map(|e| e.id).collect::<Vec<_>>(),
+            vec![deleted.id]
+        );
+        assert!(!second_page.has_more);
+
+        let empty_page = load_event_page(&conn, second_page.next_cursor, 2)?;
+        assert!(empty_page.events.is_empty());
+        assert_eq!(empty_page.next_cursor, second_page.next_cursor);
+
+        Ok(())
+    }
+
+    #[test]
+    fn raw_events_are_saved_once_and_load_like_local_events() -> Result<()> {
+        let server = Connection::open_in_memory()?;
+        migrate(&server)?;
+        let client = Connection::open_in_memory()?;
+        migrate(&client)?;
+
+        let item_id = Uuid::new_v4();
+        let created = EventWithMetadata::new(
+            item_id,
+            Event::LibraryItemCreatedEvent {
+                name: "Synced".to_string(),
+                artist: Some("Artist".to_string()),
+                album: None,
+                track_number: Some(3),
+                file_path: "synced.mp3".to_string(),
+                duration: None,
+                year: None,
+                genre: None,
+            },
+        )?;
+        save_event_to_db(&server, &created)?;
+
+        let raw = raw_event_from_page(&server, created.id);
+        assert!(save_raw_event_to_db(&client, &raw)?);
+        assert!(!save_raw_event_to_db(&client, &raw)?);
+
+        let loaded = load_all_events_from_db::<Event>(&client)?;
+        assert_eq!(loaded.len(), 1);
+        assert_eq!(loaded[0].id, created.id);
+        assert_eq!(loaded[0].event, created.event);
+        assert_eq!(loaded[0].created_time_utc, created.created_time_utc);
+
+        Ok(())
+    }
+
+    #[test]
+    fn events_are_applied_in_hlc_order_despite_clock_skew() -> Result<()> {
+        let conn = Connection::open_in_memory()?;
+        migrate(&conn)?;
+
+        // The server's clock is ahead, so an item it creates gets a later timestamp than a
+        // rename that another machine makes after syncing the create
+        let item_id = Uuid::new_v4();
+        let mut created = EventWithMetadata::new(item_id, created("Original"))?;
+        created.created_time_utc = "2030-01-01T00:00:00".parse()?;
+        created.hlc = Hlc::from_created_time(created.created_time_utc);
+        assert!(save_raw_event_to_db(&conn, &created.to_raw()?)?);
+
+        let renamed = EventWithMetadata::new(
+            item_id,
+            Event::LibraryItemNameChangedEvent {
+                new_name: "Renamed".to_string(),
+            },
+        )?;
+        save_event_to_db(&conn, &renamed)?;
+        assert!(renamed.created_time_utc < created.created_time_utc);
+        assert!(renamed.hlc > created.hlc);
+
+        let loaded = load_all_events_from_db::<Event>(&conn)?;
+        assert_eq!(
+            loaded.iter().map(|e| e.id).collect::<Vec<_>>(),
+            vec![created.id, renamed.id]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn events_without_an_hlc_fall_back_to_their_creation_time() -> Result<()> {
+        let conn = Connection::open_in_memory()?;
+        migrate(&conn)?;
+
+        let mut raw = EventWithMetadata::new(
+            Uuid::new_v4(),
+            Event::LibraryItemPlayedEvent(PlayDetails::default()),
+        )?
+        .to_raw()?;
+        raw.hlc = None;
+        save_raw_event_to_db(&conn, &raw)?;
+
+        let loaded = load_all_events_from_db::<Event>(&conn)?;
+        assert_eq!(loaded[0].hlc, Hlc::from_created_time(raw.created_time_utc));
+
+        Ok(())
+    }
+
+    #[test]
+    fn only_events_created_on_this_machine_are_pending_push() -> Result<()> {
+        let conn = Connection::open_in_memory()?;
+        migrate(&conn)?;
+
+        let item_id = Uuid::new_v4();
+        let local = EventWithMetadata::new(item_id, Event::LibraryItemFavoritedEvent)?;
+        let mut remote = EventWithMetadata::new(
+            item_id,
+            Event::LibraryItemPlayedEvent(PlayDetails::default()),
+        )?;
+        remote.machine_name = "some-other-machine".to_string();
+        let later_local = EventWithMetadata::new(item_id, Event::LibraryItemUnfavoritedEvent)?;
+        for event in [&local, &remote, &later_local] {
+            save_event_to_db(&conn, event)?;
+        }
+
+        let pending = load_local_events(&conn, &local.machine_name, 0, 100)?;
+        assert_eq!(
+            pending.iter().map(|e| e.id).collect::<Vec<_>>(),
+            vec![local.id, later_local.id]
+        );
+
+        let after_first = load_local_events(&conn, &local.machine_name, pending[0].cursor, 100)?;
+        assert_eq!(
+            after_first.iter().map(|e| e.id).collect::<Vec<_>>(),
+            vec![later_local.id]
+        );
+
+        Ok(())
+    }
+}
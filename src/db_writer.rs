@@ -0,0 +1,169 @@
+use std::ops::Deref;
+use std::thread;
+
+use anyhow::{anyhow, Context, Result};
+use rusqlite::Connection;
+use tokio::sync::{mpsc, oneshot};
+use tracing::warn;
+
+use crate::database::{save_raw_event_to_db, RawEvent};
+use crate::snapshot::snapshot_if_due;
+
+/// The most write requests that get committed in one transaction
+const MAX_REQUESTS_PER_TRANSACTION: usize = 256;
+
+struct WriteRequest {
+    events: Vec<RawEvent>,
+    ack: oneshot::Sender<Result<Vec<Option<i64>>>>,
+}
+
+/// Handle to the thread that does all event writes, on a connection nothing else uses.
+///
+/// SQLite only allows one writer at a time anyway, so instead of connections from the pool
+/// taking turns at the write lock (and each paying for their own fsync), requests queue up
+/// here. Everything that arrives while one transaction is being written is committed together
+/// in the next one, so a burst of writes costs a few transactions instead of one each.
+#[derive(Clone)]
+pub struct DbWriter {
+    requests: mpsc::UnboundedSender<WriteRequest>,
+}
+
+impl DbWriter {
+    /// Start the writer thread. It runs until every `DbWriter` handle has been dropped.
+    pub fn spawn<C>(conn: C) -> Result<Self>
+    where
+        C: Deref<Target = Connection> + Send + 'static,
+    {
+        let (requests, receiver) = mpsc::unbounded_channel();
+        thread::Builder::new()
+            .name("db-writer".to_string())
+            .spawn(move || run(&conn, receiver))?;
+        Ok(DbWriter { requests })
+    }
+
+    /// Append events atomically, returning the cursor of each one (`None` for events that
+    /// were already saved). Resolves once the transaction they were written in is committed.
+    pub async fn append_batch(&self, events: Vec<RawEvent>) -> Result<Vec<Option<i64>>> {
+        let (ack, acked) = oneshot::channel();
+        self.requests
+            .send(WriteRequest { events, ack })
+            .map_err(|_| anyhow!("The database writer has stopped"))?;
+        acked.await.context("The database writer has stopped")?
+    }
+}
+
+fn run(conn: &Connection, mut receiver: mpsc::UnboundedReceiver<WriteRequest>) {
+    while let Some(first) = receiver.blocking_recv() {
+        let mut batch = vec![first];
+        while batch.len() < MAX_REQUESTS_PER_TRANSACTION {
+            match receiver.try_recv() {
+                Ok(request) => batch.push(request),
+                Err(_) => break,
+            }
+        }
+        write_batch(conn, batch);
+        // after the writes have been acknowledged, so nobody waits for it
+        if let Err(e) = snapshot_if_due(conn) {
+            warn!(error = ?e, "Failed to write a snapshot");
+        }
+    }
+}
+
+fn write_batch(conn: &Connection, batch: Vec<WriteRequest>) {
+    match write_in_transaction(conn, batch.iter().map(|request| request.events.as_slice())) {
+        Ok(results) => {
+            for (request, cursors) in batch.into_iter().zip(results) {
+                // the caller may have given up waiting, which is fine
+                let _ = request.ack.send(Ok(cursors));
+            }
+        }
+        Err(e) if batch.len() == 1 => {
+            let _ = batch.into_iter().next().unwrap().ack.send(Err(e));
+        }
+        Err(e) => {
+            // one bad request shouldn't fail everything it was batched with, so find it by
+            // giving every request its own transaction
+            warn!(error = ?e, requests = batch.len(), "Batched write failed, retrying separately");
+            for request in batch {
+                let result = write_in_transaction(conn, [request.events.as_slice()].into_iter())
+                    .map(|mut results| results.remove(0));
+                let _ = request.ack.send(result);
+            }
+        }
+    }
+}
+
+fn write_in_transaction<'a>(
+    conn: &Connection,
+    requests: impl Iterator<Item = &'a [RawEvent]>,
+) -> Result<Vec<Vec<Option<i64>>>> {
+    let tx = conn.unchecked_transaction()?;
+    let mut results = Vec::new();
+    for events in requests {
+        let mut cursors = Vec::with_capacity(events.len());
+        for event in events {
+            let inserted = save_raw_event_to_db(&tx, event)?;
+            cursors.push(inserted.then(|| tx.last_insert_rowid()));
+        }
+        results.push(cursors);
+    }
+    tx.commit()?;
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::library::{Event, EventWithMetadata, PlayDetails};
+    use crate::migrations::migrate;
+    use uuid::Uuid;
+
+    #[tokio::test]
+    async fn concurrent_writes_are_acknowledged_and_bad_ones_fail_alone() -> Result<()> {
+        let conn = Connection::open_in_memory()?;
+        migrate(&conn)?;
+        conn.execute_batch(
+            "CREATE TRIGGER reject_bad_events BEFORE INSERT ON events WHEN NEW.MachineName = 'bad'
+             BEGIN SELECT RAISE(ABORT, 'rejected'); END;",
+        )?;
+        let writer = DbWriter::spawn(Box::new(conn))?;
+
+        let item_id = Uuid::new_v4();
+        let played = EventWithMetadata::new(
+            item_id,
+            Event::LibraryItemPlayedEvent(PlayDetails::default()),
+        )?
+        .to_raw()?;
+        let mut bad = played.clone();
+        bad.id = Uuid::new_v4();
+        bad.machine_name = "bad".to_string();
+        let bad_write = tokio::spawn({
+            let writer = writer.clone();
+            async move { writer.append_batch(vec![bad]).await }
+        });
+        let mut writes = Vec::new();
+        for _ in 0..50 {
+            let writer = writer.clone();
+            let event = EventWithMetadata::new(
+                item_id,
+                Event::LibraryItemPlayedEvent(PlayDetails::default()),
+            )?
+            .to_raw()?;
+            writes.push(tokio::spawn(async move {
+                writer.append_batch(vec![event]).await
+            }));
+        }
+        let mut cursors = Vec::new();
+        for write in writes {
+            cursors.push(write.await??[0].unwrap());
+        }
+        cursors.sort();
+        cursors.dedup();
+        assert_eq!(cursors.len(), 50);
+        assert!(bad_write.await?.is_err());
+
+        assert!(writer.append_batch(vec![played.clone()]).await?[0].is_some());
+        assert_eq!(writer.append_batch(vec![played]).await?, vec![None]);
+        Ok(())
+    }
+}
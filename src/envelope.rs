@@ -0,0 +1,214 @@
+use std::fmt;
+
+use anyhow::{bail, ensure, Context, Result};
+use jiff::{civil::DateTime, tz::TimeZone, Zoned};
+use serde::{de::DeserializeOwned, de::Error as _, Deserialize, Deserializer, Serialize};
+use uuid::Uuid;
+
+use crate::database::RawEvent;
+use crate::hlc::{Hlc, CLOCK};
+use crate::library::Event;
+use crate::playlist::PlaylistEvent;
+
+/// The kinds of thing that events can happen to. Stored in the `AggregateType` column.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum AggregateType {
+    LibraryItem,
+    Playlist,
+}
+
+impl AggregateType {
+    pub const ALL: [AggregateType; 2] = [AggregateType::LibraryItem, AggregateType::Playlist];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AggregateType::LibraryItem => "LibraryItem",
+            AggregateType::Playlist => "Playlist",
+        }
+    }
+}
+
+impl fmt::Display for AggregateType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl std::str::FromStr for AggregateType {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match AggregateType::ALL.into_iter().find(|t| t.as_str() == s) {
+            Some(aggregate_type) => Ok(aggregate_type),
+            None => bail!("Unknown aggregate type: {s}"),
+        }
+    }
+}
+
+/// An event enum for one aggregate type. Implementing this is all it takes for events to be
+/// saved, loaded and synced through `Envelope`.
+pub trait AggregateEvent: Serialize + DeserializeOwned {
+    const AGGREGATE_TYPE: AggregateType;
+}
+
+impl AggregateEvent for Event {
+    const AGGREGATE_TYPE: AggregateType = AggregateType::LibraryItem;
+}
+
+impl AggregateEvent for PlaylistEvent {
+    const AGGREGATE_TYPE: AggregateType = AggregateType::Playlist;
+}
+
+/// An event plus everything we know about when, where and to what it happened
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Envelope<E> {
+    pub id: Uuid,
+    pub aggregate_id: Uuid,
+    pub aggregate_type: AggregateType,
+    pub created_time_utc: DateTime,
+    /// Determines the order events are applied in; see `Hlc`
+    pub hlc: Hlc,
+    pub machine_name: String,
+    pub event: E,
+}
+
+impl<E: AggregateEvent> Envelope<E> {
+    /// Wrap a new event that's happening now, on this machine
+    pub fn new(aggregate_id: Uuid, event: E) -> Result<Self> {
+        Ok(Envelope {
+            id: Uuid::new_v4(),
+            aggregate_id,
+            aggregate_type: E::AGGREGATE_TYPE,
+            created_time_utc: Zoned::now().with_time_zone(TimeZone::UTC).datetime(),
+            hlc: CLOCK.tick(),
+            machine_name: hostname::get()?.to_string_lossy().into(),
+            event,
+        })
+    }
+
+    /// Convert to the aggregate-agnostic form used by sync and `EventStore`
+    pub fn to_raw(&self) -> Result<RawEvent> {
+        Ok(RawEvent {
+            cursor: 0,
+            id: self.id,
+            aggregate_id: self.aggregate_id,
+            aggregate_type: self.aggregate_type.to_string(),
+            created_time_utc: self.created_time_utc,
+            hlc: Some(self.hlc),
+            machine_name: self.machine_name.clone(),
+            event: serde_json::to_value(&self.event)?,
+        })
+    }
+
+    pub fn from_raw(raw: RawEvent) -> Result<Self> {
+        ensure!(
+            raw.aggregate_type == E::AGGREGATE_TYPE.as_str(),
+            "Event {} is a {} event, not a {} event",
+            raw.id,
+            raw.aggregate_type,
+            E::AGGREGATE_TYPE
+        );
+        let hlc = raw.hlc();
+        let event = serde_json::from_value(raw.event)
+            .with_context(|| format!("Failed to deserialize {} event", E::AGGREGATE_TYPE))?;
+
+        Ok(Envelope {
+            id: raw.id,
+            aggregate_id: raw.aggregate_id,
+            aggregate_type: E::AGGREGATE_TYPE,
+            created_time_utc: raw.created_time_utc,
+            hlc,
+            machine_name: raw.machine_name,
+            event,
+        })
+    }
+}
+
+/// An event of any aggregate type, for code that handles a mixed stream of events
+#[derive(Debug, Clone, Serialize)]
+#[serde(untagged)]
+pub enum AnyEnvelope {
+    Library(Envelope<Event>),
+    Playlist(Envelope<PlaylistEvent>),
+}
+
+impl AnyEnvelope {
+    /// Decode an event according to its aggregate type
+    pub fn from_raw(raw: RawEvent) -> Result<Self> {
+        Ok(match raw.aggregate_type.parse()? {
+            AggregateType::LibraryItem => AnyEnvelope::Library(Envelope::from_raw(raw)?),
+            AggregateType::Playlist => AnyEnvelope::Playlist(Envelope::from_raw(raw)?),
+        })
+    }
+
+    /// Decode an event according to its aggregate type, or `None` if this version doesn't know
+    /// its aggregate type (it was probably written by a newer version, so it's kept but not
+    /// applied)
+    pub fn from_raw_if_known(raw: RawEvent) -> Result<Option<Self>> {
+        if raw.aggregate_type.parse::<AggregateType>().is_err() {
+            return Ok(None);
+        }
+        Self::from_raw(raw).map(Some)
+    }
+}
+
+/// Deserialize an event with `known` (the derived deserializer), treating it as unknown only
+/// if its `$type` isn't one of `known_types`. An event of a known type that doesn't fit is
+/// garbled rather than new, so it's an error.
+pub(crate) fn deserialize_event<'de, D, E>(
+    deserializer: D,
+    known_types: &[&str],
+    known: impl FnOnce(&serde_json::Value) -> serde_json::Result<E>,
+    unknown: impl FnOnce(String, serde_json::Value) -> E,
+) -> Result<E, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let raw = serde_json::Value::deserialize(deserializer)?;
+    let Some(type_name) = raw
+        .get("$type")
+        .and_then(|t| t.as_str())
+        .map(str::to_string)
+    else {
+        return Err(D::Error::custom("event has no $type"));
+    };
+    match known(&raw) {
+        Ok(event) => Ok(event),
+        Err(e) if known_types.contains(&type_name.as_str()) => Err(D::Error::custom(e)),
+        Err(_) => Ok(unknown(type_name, raw)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::library::PlayDetails;
+
+    #[test]
+    fn envelopes_round_trip_through_raw_events_of_the_right_type() -> Result<()> {
+        let played = Envelope::new(
+            Uuid::new_v4(),
+            Event::LibraryItemPlayedEvent(PlayDetails::default()),
+        )?;
+        let raw = played.to_raw()?;
+        assert_eq!(raw.aggregate_type, "LibraryItem");
+
+        let decoded = Envelope::<Event>::from_raw(raw.clone())?;
+        assert_eq!(decoded.id, played.id);
+        assert_eq!(decoded.event, played.event);
+        assert!(matches!(
+            AnyEnvelope::from_raw(raw.clone())?,
+            AnyEnvelope::Library(_)
+        ));
+
+        assert!(Envelope::<PlaylistEvent>::from_raw(raw.clone()).is_err());
+        let unknown_type = RawEvent {
+            aggregate_type: "SmartPlaylist".to_string(),
+            ..raw
+        };
+        assert!(AnyEnvelope::from_raw(unknown_type.clone()).is_err());
+        assert!(AnyEnvelope::from_raw_if_known(unknown_type)?.is_none());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,349 @@
+use std::collections::HashSet;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Mutex;
+
+use anyhow::Result;
+use jiff::civil::DateTime;
+use r2d2::Pool;
+use r2d2_sqlite::SqliteConnectionManager;
+
+use uuid::Uuid;
+
+use crate::database::{
+    load_all_raw_events, load_all_raw_events_lenient, load_event_page,
+    load_raw_events_by_aggregate_id, load_raw_events_by_aggregate_type, load_raw_plays, EventPage,
+    RawEvent, UnreadableEvent,
+};
+use crate::db_writer::DbWriter;
+use crate::envelope::AggregateType;
+
+/// What `EventStore` appends return. Boxed so that the trait can still be used as
+/// `dyn EventStore`.
+pub type AppendFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T>> + Send + 'a>>;
+
+/// Somewhere to keep the event log.
+///
+/// Events are appended (never updated or deleted) and every event gets a cursor: a number
+/// that is larger than the cursor of every event appended before it. Cursors are what sync
+/// clients use to ask for "everything since last time".
+pub trait EventStore: Send + Sync {
+    /// Append an event, returning its cursor. Returns `None` if an event with the same ID is
+    /// already in the store, so that replaying a sync is harmless.
+    fn append(&self, event: &RawEvent) -> AppendFuture<'_, Option<i64>> {
+        let cursors = self.append_batch(std::slice::from_ref(event));
+        Box::pin(async move { Ok(cursors.await?.pop().flatten()) })
+    }
+
+    /// Append several events atomically: either all of them are saved or none are. Returns
+    /// the same thing as `append` would for each event.
+    fn append_batch(&self, events: &[RawEvent]) -> AppendFuture<'_, Vec<Option<i64>>>;
+
+    /// Load every event in chronological order (the order they should be applied in),
+    /// including events for aggregate types this version doesn't know about
+    fn load_all(&self) -> Result<Vec<RawEvent>>;
+
+    /// Like `load_all`, but sets aside events that can't be read instead of failing. For
+    /// fsck; everything else should fail loudly.
+    fn load_all_lenient(&self) -> Result<(Vec<RawEvent>, Vec<UnreadableEvent>)>;
+
+    /// Load every event for one aggregate type in chronological order
+    fn load_by_aggregate_type(&self, aggregate_type: AggregateType) -> Result<Vec<RawEvent>>;
+
+    /// Load every event for one library item or playlist in chronological order
+    fn load_by_aggregate_id(&self, aggregate_id: Uuid) -> Result<Vec<RawEvent>>;
+
+    /// Load up to `limit` library item plays created before `before` (if given), newest first
+    fn load_plays(&self, before: Option<DateTime>, limit: usize) -> Result<Vec<RawEvent>>;
+
+    /// Load up to `limit` events of every aggregate type appended after the `after` cursor, in
+    /// the order they were appended
+    fn load_since(&self, after: i64, limit: usize) -> Result<EventPage>;
+
+    /// The cursor of the most recently appended event, or 0 if the store is empty. Anything
+    /// derived from the store is stale once this changes.
+    fn latest_cursor(&self) -> Result<i64>;
+}
+
+/// The real event store, backed by the `events` table. Appends go through a `DbWriter`;
+/// loads use the pool.
+#[derive(Clone)]
+pub struct SqliteEventStore {
+    pool: Pool<SqliteConnectionManager>,
+    writer: DbWriter,
+}
+
+impl SqliteEventStore {
+    /// Takes one connection from the pool for good, for the writer
+    pub fn new(pool: Pool<SqliteConnectionManager>) -> Result<Self> {
+        let writer = DbWriter::spawn(pool.get()?)?;
+        Ok(SqliteEventStore { pool, writer })
+    }
+}
+
+impl EventStore for SqliteEventStore {
+    fn append_batch(&self, events: &[RawEvent]) -> AppendFuture<'_, Vec<Option<i64>>> {
+        Box::pin(self.writer.append_batch(events.to_vec()))
+    }
+
+    fn load_all(&self) -> Result<Vec<RawEvent>> {
+        let conn = self.pool.get()?;
+        load_all_raw_events(&conn)
+    }
+
+    fn load_all_lenient(&self) -> Result<(Vec<RawEvent>, Vec<UnreadableEvent>)> {
+        let conn = self.pool.get()?;
+        load_all_raw_events_lenient(&conn)
+    }
+
+    fn load_by_aggregate_type(&self, aggregate_type: AggregateType) -> Result<Vec<RawEvent>> {
+        let conn = self.pool.get()?;
+        load_raw_events_by_aggregate_type(&conn, aggregate_type)
+    }
+
+    fn load_by_aggregate_id(&self, aggregate_id: Uuid) -> Result<Vec<RawEvent>> {
+        let conn = self.pool.get()?;
+        load_raw_events_by_aggregate_id(&conn, aggregate_id)
+    }
+
+    fn load_plays(&self, before: Option<DateTime>, limit: usize) -> Result<Vec<RawEvent>> {
+        let conn = self.pool.get()?;
+        load_raw_plays(&conn, before, limit)
+    }
+
+    fn load_since(&self, after: i64, limit: usize) -> Result<EventPage> {
+        let conn = self.pool.get()?;
+        load_event_page(&conn, after, limit)
+    }
+
+    fn latest_cursor(&self) -> Result<i64> {
+        let conn = self.pool.get()?;
+        let cursor = conn.query_row("SELECT COALESCE(MAX(Seq), 0) FROM events", [], |row| {
+            row.get(0)
+        })?;
+        Ok(cursor)
+    }
+}
+
+/// An event store that only lives as long as the process, for tests. Like the old .NET
+/// `InMemoryEventRepository`.
+#[derive(Default)]
+pub struct InMemoryEventStore {
+    inner: Mutex<InMemoryEvents>,
+}
+
+#[derive(Default)]
+struct InMemoryEvents {
+    /// Append order; an event's cursor is its index + 1
+    events: Vec<RawEvent>,
+    ids: HashSet<Uuid>,
+}
+
+impl InMemoryEvents {
+    fn append(&mut self, event: &RawEvent) -> Option<i64> {
+        if !self.ids.insert(event.id) {
+            return None;
+        }
+        let cursor = self.events.len() as i64 + 1;
+        self.events.push(RawEvent {
+            cursor,
+            ..event.clone()
+        });
+        Some(cursor)
+    }
+
+    /// The events matching `filter`, in the same order as the SQLite store loads them
+    fn load_sorted(&self, filter: impl Fn(&RawEvent) -> bool) -> Vec<RawEvent> {
+        let mut events: Vec<_> = self
+            .events
+            .iter()
+            .filter(|event| filter(event))
+            .cloned()
+            .collect();
+        events.sort_by(|a, b| {
+            (a.hlc(), a.created_time_utc, &a.machine_name, a.id).cmp(&(
+                b.hlc(),
+                b.created_time_utc,
+                &b.machine_name,
+                b.id,
+            ))
+        });
+        events
+    }
+}
+
+impl InMemoryEventStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl EventStore for InMemoryEventStore {
+    fn append_batch(&self, events: &[RawEvent]) -> AppendFuture<'_, Vec<Option<i64>>> {
+        // holding the lock for the whole batch is enough to make it atomic, since appending
+        // to memory can't fail halfway through
+        let mut inner = self.inner.lock().unwrap();
+        let cursors = events.iter().map(|event| inner.append(event)).collect();
+        Box::pin(std::future::ready(Ok(cursors)))
+    }
+
+    fn load_all(&self) -> Result<Vec<RawEvent>> {
+        Ok(self.inner.lock().unwrap().load_sorted(|_| true))
+    }
+
+    fn load_all_lenient(&self) -> Result<(Vec<RawEvent>, Vec<UnreadableEvent>)> {
+        // events only get in here by being appended, so they're all readable
+        Ok((self.load_all()?, Vec::new()))
+    }
+
+    fn load_by_aggregate_type(&self, aggregate_type: AggregateType) -> Result<Vec<RawEvent>> {
+        Ok(self
+            .inner
+            .lock()
+            .unwrap()
+            .load_sorted(|event| event.aggregate_type == aggregate_type.as_str()))
+    }
+
+    fn load_by_aggregate_id(&self, aggregate_id: Uuid) -> Result<Vec<RawEvent>> {
+        Ok(self
+            .inner
+            .lock()
+            .unwrap()
+            .load_sorted(|event| event.aggregate_id == aggregate_id))
+    }
+
+    fn load_plays(&self, before: Option<DateTime>, limit: usize) -> Result<Vec<RawEvent>> {
+        let inner = self.inner.lock().unwrap();
+        let mut plays: Vec<_> = inner
+            .events
+            .iter()
+            .filter(|event| {
+                event.aggregate_type == AggregateType::LibraryItem.as_str()
+                    && event.event.get("$type").and_then(|t| t.as_str())
+                        == Some("LibraryItemPlayedEvent")
+                    && before.is_none_or(|before| event.created_time_utc < before)
+            })
+            .cloned()
+            .collect();
+        plays.sort_by_key(|event| std::cmp::Reverse((event.created_time_utc, event.cursor)));
+        plays.truncate(limit);
+        Ok(plays)
+    }
+
+    fn load_since(&self, after: i64, limit: usize) -> Result<EventPage> {
+        let inner = self.inner.lock().unwrap();
+        let start = usize::try_from(after).unwrap_or(0).min(inner.events.len());
+        let remaining = &inner.events[start..];
+        let events: Vec<_> = remaining.iter().take(limit).cloned().collect();
+        let next_cursor = events.last().map_or(after, |event| event.cursor);
+        Ok(EventPage {
+            has_more: remaining.len() > events.len(),
+            events,
+            next_cursor,
+        })
+    }
+
+    fn latest_cursor(&self) -> Result<i64> {
+        Ok(self.inner.lock().unwrap().events.len() as i64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::library::{created, Event, EventWithMetadata, Library, PlayDetails};
+    use crate::migrations::migrate;
+    use crate::playlist::{PlaylistEvent, PlaylistEventWithMetadata};
+    use uuid::Uuid;
+
+    fn sqlite_store(dir: &tempfile::TempDir) -> Result<SqliteEventStore> {
+        // the writer has its own connection, so this can't be an in-memory database (those
+        // are one per connection)
+        let pool = Pool::new(SqliteConnectionManager::file(dir.path().join("events.db")))?;
+        migrate(&*pool.get()?)?;
+        SqliteEventStore::new(pool)
+    }
+
+    /// Run the same checks against every implementation so they can't drift apart
+    async fn exercise(store: &dyn EventStore) -> Result<()> {
+        let item_id = Uuid::new_v4();
+        let playlist_id = Uuid::new_v4();
+        let created = EventWithMetadata::new(item_id, created("Stored"))?.to_raw()?;
+        let playlist_created = PlaylistEventWithMetadata::new(
+            playlist_id,
+            PlaylistEvent::PlaylistCreatedEvent {
+                name: "Stored playlist".to_string(),
+            },
+        )?
+        .to_raw()?;
+        let played = EventWithMetadata::new(
+            item_id,
+            Event::LibraryItemPlayedEvent(PlayDetails::default()),
+        )?
+        .to_raw()?;
+
+        let first_cursor = store.append(&created).await?.unwrap();
+        assert_eq!(store.append(&created).await?, None);
+
+        let cursors = store
+            .append_batch(&[playlist_created.clone(), created.clone(), played])
+            .await?;
+        assert!(cursors[0].unwrap() > first_cursor);
+        assert_eq!(cursors[1], None);
+        assert!(cursors[2].unwrap() > cursors[0].unwrap());
+        assert_eq!(store.latest_cursor()?, cursors[2].unwrap());
+
+        let library_events = store.load_by_aggregate_type(AggregateType::LibraryItem)?;
+        assert_eq!(library_events.len(), 2);
+        let library = Library::build_from_events(
+            library_events
+                .into_iter()
+                .map(EventWithMetadata::from_raw)
+                .collect::<Result<_>>()?,
+        );
+        assert_eq!(library.items[&item_id].play_count, 1);
+        assert_eq!(store.load_by_aggregate_id(item_id)?.len(), 2);
+        assert_eq!(store.load_by_aggregate_id(playlist_id)?.len(), 1);
+        assert_eq!(store.load_all()?.len(), 3);
+
+        let page = store.load_since(first_cursor, 1)?;
+        assert_eq!(page.events[0].id, playlist_created.id);
+        assert!(page.has_more);
+        let page = store.load_since(page.next_cursor, 10)?;
+        assert_eq!(page.events.len(), 1);
+        assert!(!page.has_more);
+
+        let mut earlier_play = EventWithMetadata::new(
+            item_id,
+            Event::LibraryItemPlayedEvent(PlayDetails::default()),
+        )?;
+        earlier_play.created_time_utc = "2020-01-01T00:00:00".parse()?;
+        let earlier_play = earlier_play.to_raw()?;
+        store.append(&earlier_play).await?;
+        let play_ids =
+            |plays: Vec<RawEvent>| -> Vec<Uuid> { plays.into_iter().map(|play| play.id).collect() };
+        let played_id = page.events[0].id;
+        assert_eq!(
+            play_ids(store.load_plays(None, 10)?),
+            vec![played_id, earlier_play.id]
+        );
+        assert_eq!(play_ids(store.load_plays(None, 1)?), vec![played_id]);
+        assert_eq!(
+            play_ids(store.load_plays(Some(page.events[0].created_time_utc), 10)?),
+            vec![earlier_play.id]
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn in_memory_store_behaves_like_an_event_store() -> Result<()> {
+        exercise(&InMemoryEventStore::new()).await
+    }
+
+    #[tokio::test]
+    async fn sqlite_store_behaves_like_an_event_store() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        exercise(&sqlite_store(&dir)?).await
+    }
+}
@@ -0,0 +1,442 @@
+//! Consistency checks for the event log. Events are never edited or deleted, so anything odd
+//! in there (bugs, bad merges, clock trouble) stays forever; better to find it early.
+
+use anyhow::Result;
+use jiff::{tz::TimeZone, Timestamp};
+use rusqlite::Connection;
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::database::{load_all_raw_events_lenient, RawEvent, UnreadableEvent};
+use crate::envelope::{AggregateType, AnyEnvelope, Envelope};
+use crate::event_store::EventStore;
+use crate::library::{Event, Library};
+use crate::playlist::{PlaylistEvent, PlaylistStore};
+
+/// How far in the future an event can be before it's suspicious (clocks drift a bit)
+const CLOCK_SKEW_TOLERANCE_MILLIS: i64 = 5 * 60 * 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum ProblemKind {
+    /// The row couldn't be read at all (e.g. the payload isn't JSON)
+    Unreadable,
+    /// An event for an aggregate type this version doesn't know about
+    UnknownAggregateType,
+    /// An event type this version doesn't know about, probably written by a newer version
+    UnknownEventType,
+    /// An event for a library item or playlist that was never created
+    MissingCreate,
+    /// A second creation event for the same ID
+    DuplicateCreate,
+    /// An event for a library item or playlist after it was deleted
+    EventAfterDelete,
+    /// A bookmark event for a library item that doesn't exist
+    BookmarkOnMissingItem,
+    /// A playlist that still contains a library item that doesn't exist
+    PlaylistEntryForMissingItem,
+    /// An event created (according to its timestamp or HLC) in the future
+    FutureTimestamp,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Problem {
+    pub kind: ProblemKind,
+    /// The event with the problem, for problems with a single event
+    pub event_id: Option<Uuid>,
+    pub aggregate_id: Option<Uuid>,
+    pub description: String,
+    /// An event that fixes the problem, if there is one
+    pub repair: Option<RawEvent>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct FsckReport {
+    pub events_checked: usize,
+    pub problems: Vec<Problem>,
+}
+
+impl FsckReport {
+    /// The events that would fix every repairable problem
+    pub fn repairs(&self) -> Vec<RawEvent> {
+        self.problems
+            .iter()
+            .filter_map(|problem| problem.repair.clone())
+            .collect()
+    }
+}
+
+/// Check every event in the database, including ones that can't be read
+pub fn check_database(conn: &Connection) -> Result<FsckReport> {
+    let (events, unreadable) = load_all_raw_events_lenient(conn)?;
+    check_readable_and_unreadable(&events, unreadable)
+}
+
+/// Check every event in an event store, including ones that can't be read
+pub fn check_event_store(store: &dyn EventStore) -> Result<FsckReport> {
+    let (events, unreadable) = store.load_all_lenient()?;
+    check_readable_and_unreadable(&events, unreadable)
+}
+
+fn check_readable_and_unreadable(
+    events: &[RawEvent],
+    unreadable: Vec<UnreadableEvent>,
+) -> Result<FsckReport> {
+    let mut report = check_events(events, Timestamp::now())?;
+    report.events_checked += unreadable.len();
+    report.problems.splice(
+        0..0,
+        unreadable.into_iter().map(|event| {
+            let UnreadableEvent { cursor, id, error } = event;
+            Problem {
+                kind: ProblemKind::Unreadable,
+                event_id: id.as_deref().and_then(|id| id.parse().ok()),
+                aggregate_id: None,
+                description: format!("Event in row {cursor} can't be read: {error}"),
+                repair: None,
+            }
+        }),
+    );
+    Ok(report)
+}
+
+/// Check events (in the order they're applied) for problems, as of `now`
+pub fn check_events(events: &[RawEvent], now: Timestamp) -> Result<FsckReport> {
+    let mut checker = Checker::default();
+    for raw in events {
+        checker.check_timestamps(raw, now);
+        match AnyEnvelope::from_raw(raw.clone()) {
+            Ok(AnyEnvelope::Library(event)) => checker.check_library_event(&event),
+            Ok(AnyEnvelope::Playlist(event)) => checker.check_playlist_event(&event),
+            Err(error) => {
+                let kind = if raw.aggregate_type.parse::<AggregateType>().is_err() {
+                    ProblemKind::UnknownAggregateType
+                } else {
+                    ProblemKind::Unreadable
+                };
+                checker.report(kind, raw.id, raw.aggregate_id, format!("{error:#}"));
+            }
+        }
+    }
+    checker.check_playlist_entries()?;
+
+    Ok(FsckReport {
+        events_checked: events.len(),
+        problems: checker.problems,
+    })
+}
+
+/// Replays events like loading does, checking each one against the state so far
+#[derive(Default)]
+struct Checker {
+    library: Library,
+    playlists: PlaylistStore,
+    problems: Vec<Problem>,
+}
+
+impl Checker {
+    fn report(
+        &mut self,
+        kind: ProblemKind,
+        event_id: Uuid,
+        aggregate_id: Uuid,
+        description: String,
+    ) {
+        self.problems.push(Problem {
+            kind,
+            event_id: Some(event_id),
+            aggregate_id: Some(aggregate_id),
+            description,
+            repair: None,
+        });
+    }
+
+    fn check_timestamps(&mut self, raw: &RawEvent, now: Timestamp) {
+        let latest_ok = now.as_millisecond() + CLOCK_SKEW_TOLERANCE_MILLIS;
+        let created_millis = TimeZone::UTC
+            .to_timestamp(raw.created_time_utc)
+            .map_or(i64::MAX, |timestamp| timestamp.as_millisecond());
+        if created_millis > latest_ok || raw.hlc().millis > latest_ok {
+            let description = format!(
+                "Created at {} (HLC {}) on {}, which is in the future",
+                raw.created_time_utc,
+                raw.hlc().millis,
+                raw.machine_name
+            );
+            self.report(
+                ProblemKind::FutureTimestamp,
+                raw.id,
+                raw.aggregate_id,
+                description,
+            );
+        }
+    }
+
+    fn check_library_event(&mut self, event: &Envelope<Event>) {
+        let id = event.aggregate_id;
+        let exists = self.library.items.contains_key(&id);
+        let deleted = self.library.deleted_items.contains_key(&id);
+        let problem = match &event.event {
+            Event::LibraryItemCreatedEvent { .. } if exists || deleted => Some((
+                ProblemKind::DuplicateCreate,
+                "Library item was already created".to_string(),
+            )),
+            Event::LibraryItemCreatedEvent { .. } => None,
+            Event::LibraryItemRestoredEvent if !exists && !deleted => Some((
+                ProblemKind::MissingCreate,
+                "Restore of a library item that was never created".to_string(),
+            )),
+            Event::LibraryItemRestoredEvent => None,
+            Event::Unknown { type_name, .. } => Some((
+                ProblemKind::UnknownEventType,
+                format!("Unknown library item event type {type_name}"),
+            )),
+            _ if deleted => Some((
+                ProblemKind::EventAfterDelete,
+                format!("{} after the library item was deleted", type_name(event)),
+            )),
+            Event::LibraryItemBookmarkAddedEvent { .. }
+            | Event::LibraryItemBookmarkDeletedEvent { .. }
+            | Event::LibraryItemBookmarkMovedEvent { .. }
+            | Event::LibraryItemBookmarkSetEmojiEvent { .. }
+            | Event::LibraryItemBookmarkLabelChangedEvent { .. }
+                if !exists =>
+            {
+                Some((
+                    ProblemKind::BookmarkOnMissingItem,
+                    format!("{} for a library item that doesn't exist", type_name(event)),
+                ))
+            }
+            _ if !exists => Some((
+                ProblemKind::MissingCreate,
+                format!("{} for a library item that doesn't exist", type_name(event)),
+            )),
+            _ => None,
+        };
+        if let Some((kind, description)) = problem {
+            self.report(kind, event.id, id, description);
+        }
+
+        self.library.apply(event);
+    }
+
+    fn check_playlist_event(&mut self, event: &Envelope<PlaylistEvent>) {
+        let id = event.aggregate_id;
+        let playlist = self.playlists.playlists.get(&id);
+        let problem = match (&event.event, playlist) {
+            (PlaylistEvent::PlaylistCreatedEvent { .. }, Some(_)) => Some((
+                ProblemKind::DuplicateCreate,
+                "Playlist was already created".to_string(),
+            )),
+            (PlaylistEvent::PlaylistCreatedEvent { .. }, None) => None,
+            (PlaylistEvent::Unknown { type_name, .. }, _) => Some((
+                ProblemKind::UnknownEventType,
+                format!("Unknown playlist event type {type_name}"),
+            )),
+            (_, None) => Some((
+                ProblemKind::MissingCreate,
+                format!("{} for a playlist that doesn't exist", type_name(event)),
+            )),
+            (_, Some(playlist)) if playlist.is_deleted => Some((
+                ProblemKind::EventAfterDelete,
+                format!("{} after the playlist was deleted", type_name(event)),
+            )),
+            _ => None,
+        };
+        if let Some((kind, description)) = problem {
+            self.report(kind, event.id, id, description);
+        }
+
+        self.playlists.apply(event);
+    }
+
+    /// Playlists should only contain items that (still) exist. Removing the entry fixes it.
+    fn check_playlist_entries(&mut self) -> Result<()> {
+        for playlist in self.playlists.active_playlists() {
+            for item_id in playlist.items.keys() {
+                if self.library.items.contains_key(item_id) {
+                    continue;
+                }
+                let state = if self.library.deleted_items.contains_key(item_id) {
+                    "deleted"
+                } else {
+                    "missing"
+                };
+                let repair = Envelope::new(
+                    playlist.id,
+                    PlaylistEvent::PlaylistItemRemovedEvent {
+                        library_item_id: *item_id,
+                    },
+                )?;
+                self.problems.push(Problem {
+                    kind: ProblemKind::PlaylistEntryForMissingItem,
+                    event_id: None,
+                    aggregate_id: Some(playlist.id),
+                    description: format!(
+                        "Playlist '{}' contains {state} library item {item_id}",
+                        playlist.name
+                    ),
+                    repair: Some(repair.to_raw()?),
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The `$type` of an event, for descriptions
+fn type_name<E: Serialize>(event: &Envelope<E>) -> String {
+    serde_json::to_value(&event.event)
+        .ok()
+        .and_then(|value| value.get("$type")?.as_str().map(str::to_string))
+        .unwrap_or_else(|| "Event".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::{save_event_to_db, save_raw_event_to_db};
+    use crate::hlc::Hlc;
+    use crate::library::{created, PlayDetails};
+    use crate::migrations::migrate;
+
+    /// Other tests push the shared clock into the future, so go by `created_time_utc` instead
+    fn envelope<E: crate::envelope::AggregateEvent>(id: Uuid, event: E) -> Result<Envelope<E>> {
+        let mut envelope = Envelope::new(id, event)?;
+        envelope.hlc = Hlc::from_created_time(envelope.created_time_utc);
+        Ok(envelope)
+    }
+
+    fn raw<E: crate::envelope::AggregateEvent>(id: Uuid, event: E) -> Result<RawEvent> {
+        envelope(id, event)?.to_raw()
+    }
+
+    fn kinds(report: &FsckReport) -> Vec<ProblemKind> {
+        report.problems.iter().map(|problem| problem.kind).collect()
+    }
+
+    #[test]
+    fn healthy_logs_have_no_problems() -> Result<()> {
+        let item_id = Uuid::new_v4();
+        let playlist_id = Uuid::new_v4();
+        let events = vec![
+            raw(item_id, created("fine"))?,
+            raw(
+                item_id,
+                Event::LibraryItemPlayedEvent(PlayDetails::default()),
+            )?,
+            raw(
+                playlist_id,
+                PlaylistEvent::PlaylistCreatedEvent {
+                    name: "Fine".to_string(),
+                },
+            )?,
+            raw(
+                playlist_id,
+                PlaylistEvent::PlaylistItemAddedEvent {
+                    library_item_id: item_id,
+                    position: 0,
+                },
+            )?,
+            raw(item_id, Event::LibraryItemDeletedEvent)?,
+            raw(item_id, Event::LibraryItemRestoredEvent)?,
+        ];
+
+        let report = check_events(&events, Timestamp::now())?;
+        assert_eq!(report.events_checked, 6);
+        assert!(report.problems.is_empty(), "{:?}", report.problems);
+        Ok(())
+    }
+
+    #[test]
+    fn problems_are_found_and_playlist_entries_repaired() -> Result<()> {
+        let item_id = Uuid::new_v4();
+        let ghost_id = Uuid::new_v4();
+        let playlist_id = Uuid::new_v4();
+        let mut future = raw(Uuid::new_v4(), created("future"))?;
+        future.created_time_utc = "2999-01-01T00:00:00".parse()?;
+        let events = vec![
+            raw(item_id, created("original"))?,
+            raw(item_id, created("again"))?,
+            raw(
+                ghost_id,
+                Event::LibraryItemPlayedEvent(PlayDetails::default()),
+            )?,
+            raw(
+                ghost_id,
+                Event::LibraryItemBookmarkDeletedEvent {
+                    bookmark_id: Uuid::new_v4(),
+                },
+            )?,
+            raw(
+                playlist_id,
+                PlaylistEvent::PlaylistCreatedEvent {
+                    name: "Mix".to_string(),
+                },
+            )?,
+            raw(
+                playlist_id,
+                PlaylistEvent::PlaylistItemAddedEvent {
+                    library_item_id: item_id,
+                    position: 0,
+                },
+            )?,
+            raw(item_id, Event::LibraryItemDeletedEvent)?,
+            raw(
+                item_id,
+                Event::LibraryItemPlayedEvent(PlayDetails::default()),
+            )?,
+            future,
+        ];
+
+        let report = check_events(&events, Timestamp::now())?;
+        assert_eq!(
+            kinds(&report),
+            vec![
+                ProblemKind::DuplicateCreate,
+                ProblemKind::MissingCreate,
+                ProblemKind::BookmarkOnMissingItem,
+                ProblemKind::EventAfterDelete,
+                ProblemKind::FutureTimestamp,
+                ProblemKind::PlaylistEntryForMissingItem,
+            ]
+        );
+
+        // applying the repairs fixes the playlist
+        let repaired: Vec<_> = events.into_iter().chain(report.repairs()).collect();
+        let report = check_events(&repaired, Timestamp::now())?;
+        assert!(!kinds(&report).contains(&ProblemKind::PlaylistEntryForMissingItem));
+        Ok(())
+    }
+
+    #[test]
+    fn unreadable_rows_are_reported_instead_of_failing() -> Result<()> {
+        let conn = Connection::open_in_memory()?;
+        migrate(&conn)?;
+        save_event_to_db(&conn, &envelope(Uuid::new_v4(), created("fine"))?)?;
+        conn.execute(
+            "INSERT INTO events (Id, AggregateId, AggregateType, CreatedTimeUtc, MachineName, Serialized)
+             VALUES (?1, ?2, 'LibraryItem', '2024-01-01T00:00:00', 'machine', '{not json')",
+            [Uuid::new_v4().to_string(), Uuid::new_v4().to_string()],
+        )?;
+
+        // a known event type with the wrong fields is garbled, not from a newer version
+        let mut garbled = raw(Uuid::new_v4(), created("garbled"))?;
+        garbled.event = serde_json::json!({ "$type": "LibraryItemCreatedEvent", "Name": 5 });
+        save_raw_event_to_db(&conn, &garbled)?;
+        let mut newer = raw(Uuid::new_v4(), created("newer"))?;
+        newer.event = serde_json::json!({ "$type": "LibraryItemRemixedEvent" });
+        save_raw_event_to_db(&conn, &newer)?;
+
+        let report = check_database(&conn)?;
+        assert_eq!(report.events_checked, 4);
+        assert_eq!(
+            kinds(&report),
+            vec![
+                ProblemKind::Unreadable,
+                ProblemKind::Unreadable,
+                ProblemKind::UnknownEventType
+            ]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,123 @@
+use std::sync::{LazyLock, Mutex};
+
+use jiff::{civil::DateTime, tz::TimeZone, Timestamp};
+use serde::{Deserialize, Serialize};
+
+/// A hybrid logical clock timestamp: wall clock milliseconds plus a counter that breaks ties
+/// (and keeps time moving forward when the wall clock doesn't).
+///
+/// Events are applied in HLC order. Every machine observes the HLCs of events it receives, so
+/// an event created in response to another event always sorts after it, even if the second
+/// machine's clock is behind the first one's.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default, Serialize, Deserialize,
+)]
+#[serde(rename_all = "PascalCase")]
+pub struct Hlc {
+    pub millis: i64,
+    pub counter: u32,
+}
+
+impl Hlc {
+    /// The HLC to use for events created before we had HLCs: their wall clock time with no
+    /// counter. Migration 3 backfills old rows the same way (SQLite's date functions also
+    /// truncate to the millisecond).
+    pub fn from_created_time(created_time_utc: DateTime) -> Hlc {
+        let millis = TimeZone::UTC
+            .to_timestamp(created_time_utc)
+            .map_or(0, |timestamp| timestamp.as_millisecond());
+        Hlc { millis, counter: 0 }
+    }
+}
+
+/// The clock used for every event created by this process
+pub static CLOCK: LazyLock<HybridLogicalClock> = LazyLock::new(HybridLogicalClock::default);
+
+#[derive(Debug, Default)]
+pub struct HybridLogicalClock {
+    last: Mutex<Hlc>,
+}
+
+impl HybridLogicalClock {
+    /// Get a timestamp for a new event, later than every timestamp issued or observed so far
+    pub fn tick(&self) -> Hlc {
+        self.tick_at(Timestamp::now().as_millisecond())
+    }
+
+    fn tick_at(&self, physical_millis: i64) -> Hlc {
+        let mut last = self.last.lock().unwrap();
+        *last = if physical_millis > last.millis {
+            Hlc {
+                millis: physical_millis,
+                counter: 0,
+            }
+        } else {
+            Hlc {
+                millis: last.millis,
+                counter: last.counter + 1,
+            }
+        };
+        *last
+    }
+
+    /// Account for a timestamp from an event created elsewhere (or in a previous run)
+    pub fn observe(&self, hlc: Hlc) {
+        let mut last = self.last.lock().unwrap();
+        *last = (*last).max(hlc);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timestamps_always_increase_even_if_the_wall_clock_does_not() {
+        // like the .NET NeverIncreasingClock/AlwaysIncreasingClock test scenarios
+        let clock = HybridLogicalClock::default();
+        let first = clock.tick_at(1_000);
+        let stuck = clock.tick_at(1_000);
+        let went_backwards = clock.tick_at(500);
+        let moved_on = clock.tick_at(2_000);
+        assert!(first < stuck && stuck < went_backwards && went_backwards < moved_on);
+        assert_eq!(
+            moved_on,
+            Hlc {
+                millis: 2_000,
+                counter: 0
+            }
+        );
+    }
+
+    #[test]
+    fn observed_timestamps_from_a_faster_clock_are_overtaken() {
+        let clock = HybridLogicalClock::default();
+        let remote = Hlc {
+            millis: 10_000,
+            counter: 7,
+        };
+        clock.observe(remote);
+        assert!(clock.tick_at(1_000) > remote);
+    }
+
+    #[test]
+    fn fallback_matches_the_migration_backfill() -> anyhow::Result<()> {
+        let conn = rusqlite::Connection::open_in_memory()?;
+        for created_time_utc in [
+            "2024-03-04T05:06:07",
+            "2024-03-04T05:06:07.1234",
+            "2024-03-04T05:06:07.9999",
+        ] {
+            let backfilled: i64 = conn.query_row(
+                "SELECT CAST(ROUND((julianday(?1) - 2440587.5) * 86400000) AS INTEGER)",
+                [created_time_utc],
+                |row| row.get(0),
+            )?;
+            assert_eq!(
+                Hlc::from_created_time(created_time_utc.parse()?).millis,
+                backfilled
+            );
+        }
+        Ok(())
+    }
+}
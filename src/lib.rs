@@ -3,13 +3,29 @@
 //! This library contains common database operations, library management,
 //! and utility functions shared between the reitunes web server and sonos-player.
 
+pub mod archive;
 pub mod database;
+pub mod db_writer;
+pub mod envelope;
+pub mod event_store;
+pub mod fsck;
+pub mod hlc;
 pub mod library;
+pub mod migrations;
 pub mod playlist;
+pub mod snapshot;
 pub mod utils;
 
 // Re-export commonly used types and functions
+pub use archive::*;
 pub use database::*;
+pub use db_writer::*;
+pub use envelope::*;
+pub use event_store::*;
+pub use fsck::*;
+pub use hlc::*;
 pub use library::*;
+pub use migrations::*;
 pub use playlist::*;
+pub use snapshot::*;
 pub use utils::*;
@@ -1,19 +1,31 @@
-use anyhow::{Context, Result};
+use anyhow::Result;
 use indexmap::IndexMap;
-use jiff::{civil::DateTime, tz::TimeZone, Zoned};
+use jiff::civil::DateTime;
 use rusqlite::Connection;
-use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, time::Duration};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    time::Duration,
+};
 use tracing::{info, instrument, warn};
 use uuid::Uuid;
 
-use crate::database::load_all_events_from_db;
+use crate::database::load_events_after_seq;
+use crate::envelope::{deserialize_event, Envelope};
+use crate::snapshot::{replay_with_snapshot, LIBRARY_SNAPSHOT};
+use crate::utils::describe_change;
 
-/// Load library from database connection
+/// Load library from database connection, starting from the newest snapshot if there is one
 pub fn load_library_from_db(conn: &Connection) -> Result<Library> {
     let start = std::time::Instant::now();
-    let events = load_all_events_from_db(conn)?;
-    let library = Library::build_from_events(events);
+    let library = replay_with_snapshot(
+        conn,
+        LIBRARY_SNAPSHOT,
+        Library::new,
+        |after_seq| load_events_after_seq(conn, after_seq),
+        Library::apply,
+        |event| event.hlc,
+    )?;
     info!(elapsed = ?start.elapsed(), "Loaded library from db");
     Ok(library)
 }
@@ -56,6 +68,29 @@ pub mod duration_serde_dotnet {
 
         Ok(Duration::new(hours * 3600 + minutes * 60 + seconds, nanos))
     }
+
+    /// The same format for optional durations, which are `null` when missing
+    pub mod option {
+        use serde::{Deserialize, Deserializer, Serialize, Serializer};
+        use std::time::Duration;
+
+        #[derive(Serialize, Deserialize)]
+        struct Wrapper(#[serde(with = "super")] Duration);
+
+        pub fn serialize<S>(duration: &Option<Duration>, serializer: S) -> Result<S::Ok, S::Error>
+        where
+            S: Serializer,
+        {
+            duration.map(Wrapper).serialize(serializer)
+        }
+
+        pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<Duration>, D::Error>
+        where
+            D: Deserializer<'de>,
+        {
+            Ok(Option::<Wrapper>::deserialize(deserializer)?.map(|wrapper| wrapper.0))
+        }
+    }
 }
 
 /// When sending durations to the frontend, we want to serialize them as (floating point) seconds
@@ -78,73 +113,65 @@ pub mod duration_serde_seconds {
     {
         let seconds = f64::deserialize(deserializer)?;
         let whole_seconds = seconds.trunc() as u64;
-        let nanos = ((seconds.fract() * 1_000_000_000.0) as u32).min(999_999_999);
+        // round rather than truncate so that serializing and deserializing is lossless
+        let nanos = ((seconds.fract() * 1_000_000_000.0).round() as u32).min(999_999_999);
         Ok(Duration::new(whole_seconds, nanos))
     }
-}
 
-/// Database row structure for events
-#[derive(Debug, Serialize, Deserialize)]
-#[serde(rename_all = "PascalCase")]
-pub struct EventRow {
-    pub id: Uuid,
-    pub aggregate_id: Uuid,
-    pub aggregate_type: String,
-    pub created_time_utc: DateTime,
-    pub machine_name: String,
-    pub serialized: String,
-}
-
-/// Event with metadata wrapper
-#[derive(Debug, Serialize, Deserialize)]
-#[allow(dead_code)]
-pub struct EventWithMetadata {
-    pub id: Uuid,
-    pub aggregate_id: Uuid,
-    pub aggregate_type: String,
-    pub created_time_utc: DateTime,
-    pub machine_name: String,
-    pub event: Event,
-}
+    /// The same format for optional durations, which are `null` when missing
+    pub mod option {
+        use serde::{Deserialize, Deserializer, Serialize, Serializer};
+        use std::time::Duration;
 
-impl EventWithMetadata {
-    pub fn new(library_item_id: Uuid, event: Event) -> Result<EventWithMetadata> {
-        let created_time_utc = Zoned::now().with_time_zone(TimeZone::UTC).datetime();
-        let event_with_metadata = EventWithMetadata {
-            id: Uuid::new_v4(),
-            aggregate_id: library_item_id,
-            aggregate_type: "LibraryItem".to_string(),
-            created_time_utc,
-            machine_name: hostname::get()?.to_string_lossy().into(),
-            event,
-        };
-        Ok(event_with_metadata)
-    }
+        #[derive(Serialize, Deserialize)]
+        struct Wrapper(#[serde(with = "super")] Duration);
 
-    pub fn from_row(row: EventRow) -> Result<Self> {
-        let event = serde_json::from_str(&row.serialized).context("Failed to deserialize event")?;
+        pub fn serialize<S>(duration: &Option<Duration>, serializer: S) -> Result<S::Ok, S::Error>
+        where
+            S: Serializer,
+        {
+            duration.map(Wrapper).serialize(serializer)
+        }
 
-        Ok(EventWithMetadata {
-            id: row.id,
-            aggregate_id: row.aggregate_id,
-            aggregate_type: row.aggregate_type,
-            created_time_utc: row.created_time_utc,
-            machine_name: row.machine_name,
-            event,
-        })
+        pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<Duration>, D::Error>
+        where
+            D: Deserializer<'de>,
+        {
+            Ok(Option::<Wrapper>::deserialize(deserializer)?.map(|wrapper| wrapper.0))
+        }
     }
 }
 
+/// The most stars an item can be rated
+pub const MAX_RATING: u8 = 5;
+
+/// A resume position is only saved once playback has moved at least this far from the last
+/// saved one, so that players can report their position as often as they like without
+/// writing an event every few seconds
+pub const RESUME_POSITION_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Positions this close to the start or end of an item aren't worth resuming from, so they
+/// clear the resume position instead
+pub const RESUME_POSITION_MARGIN: Duration = Duration::from_secs(10);
+
+/// A library item event with metadata
+pub type EventWithMetadata = Envelope<Event>;
+
 /// In-memory library containing all library items
-#[derive(Clone, Default)]
+#[derive(Clone, Default, Serialize, Deserialize)]
 pub struct Library {
     pub items: HashMap<Uuid, LibraryItem>,
+    /// Items as they were when they were deleted, so that a `LibraryItemRestoredEvent` can
+    /// bring them back
+    #[serde(default)]
+    pub deleted_items: HashMap<Uuid, LibraryItem>,
 }
 
 impl Library {
     pub fn new() -> Self {
         Library {
             items: HashMap::new(),
+            deleted_items: HashMap::new(),
         }
     }
 
@@ -168,6 +195,42 @@ impl Library {
         Some(all_bookmarks[random_index])
     }
 
+    /// How many items have each tag
+    pub fn tag_counts(&self) -> BTreeMap<String, usize> {
+        let mut counts = BTreeMap::new();
+        for tag in self.items.values().flat_map(|item| &item.tags) {
+            *counts.entry(tag.clone()).or_default() += 1;
+        }
+        counts
+    }
+
+    /// The events that replace each of the `from` tags with `to` on every item that has one.
+    /// Items that already have `to` just lose the old tags, so this merges tags as well as
+    /// renaming them.
+    pub fn retag_events(&self, from: &[String], to: &str) -> Vec<(Uuid, Vec<Event>)> {
+        let mut items: Vec<_> = self.items.values().collect();
+        items.sort_by_key(|item| item.id);
+        items
+            .into_iter()
+            .filter_map(|item| {
+                let mut events: Vec<_> = from
+                    .iter()
+                    .filter(|tag| *tag != to && item.tags.contains(*tag))
+                    .map(|tag| Event::LibraryItemTagRemovedEvent { tag: tag.clone() })
+                    .collect();
+                if events.is_empty() {
+                    return None;
+                }
+                if !item.tags.contains(to) {
+                    events.push(Event::LibraryItemTagAddedEvent {
+                        tag: to.to_string(),
+                    });
+                }
+                Some((item.id, events))
+            })
+            .collect()
+    }
+
     /// Build library from a list of events
     #[instrument(skip(events))]
     pub fn build_from_events(events: Vec<EventWithMetadata>) -> Self {
@@ -178,10 +241,193 @@ impl Library {
         library
     }
 
+    /// Replace one item with the result of replaying just its events (in HLC order). For
+    /// events that arrive out of order, which can't simply be applied on top of the item.
+    pub fn rebuild_item(&mut self, item_id: Uuid, events: &[EventWithMetadata]) {
+        self.items.remove(&item_id);
+        self.deleted_items.remove(&item_id);
+        for event in events.iter().filter(|event| event.aggregate_id == item_id) {
+            self.apply(event);
+        }
+    }
+
+    /// Build the library as it was at `until`, by applying only the events created at or
+    /// before then
+    pub fn build_until(events: Vec<EventWithMetadata>, until: DateTime) -> Self {
+        let mut library = Library::new();
+        for event in events.iter().filter(|event| event.created_time_utc <= until) {
+            library.apply(event);
+        }
+        library
+    }
+
+    /// The events that would undo `event`, given the library as it is just before `event` is
+    /// applied. Empty when there's nothing to undo (e.g. plays, or edits to items that don't
+    /// exist).
+    pub fn compensating_events(&self, event: &EventWithMetadata) -> Vec<Event> {
+        let id = &event.aggregate_id;
+        if matches!(event.event, Event::LibraryItemRestoredEvent) {
+            return if self.deleted_items.contains_key(id) {
+                vec![Event::LibraryItemDeletedEvent]
+            } else {
+                vec![]
+            };
+        }
+        let Some(item) = self.items.get(id) else {
+            return vec![];
+        };
+
+        match &event.event {
+            Event::LibraryItemDeletedEvent => vec![Event::LibraryItemRestoredEvent],
+            Event::LibraryItemNameChangedEvent { .. } => vec![Event::LibraryItemNameChangedEvent {
+                new_name: item.name.clone(),
+            }],
+            Event::LibraryItemFilePathChangedEvent { .. } => {
+                vec![Event::LibraryItemFilePathChangedEvent {
+                    new_file_path: item.file_path.clone(),
+                }]
+            }
+            Event::LibraryItemArtistChangedEvent { .. } => {
+                vec![Event::LibraryItemArtistChangedEvent {
+                    new_artist: item.artist.clone(),
+                }]
+            }
+            Event::LibraryItemAlbumChangedEvent { .. } => {
+                vec![Event::LibraryItemAlbumChangedEvent {
+                    new_album: item.album.clone(),
+                }]
+            }
+            Event::LibraryItemTrackNumberChangedEvent { .. } => {
+                vec![Event::LibraryItemTrackNumberChangedEvent {
+                    new_track_number: item.track_number,
+                }]
+            }
+            Event::LibraryItemDurationChangedEvent { .. } => {
+                vec![Event::LibraryItemDurationChangedEvent {
+                    new_duration: item.duration,
+                }]
+            }
+            Event::LibraryItemYearChangedEvent { .. } => {
+                vec![Event::LibraryItemYearChangedEvent {
+                    new_year: item.year,
+                }]
+            }
+            Event::LibraryItemGenreChangedEvent { .. } => {
+                vec![Event::LibraryItemGenreChangedEvent {
+                    new_genre: item.genre.clone(),
+                }]
+            }
+            Event::LibraryItemRatingChangedEvent { .. } => {
+                vec![Event::LibraryItemRatingChangedEvent {
+                    new_rating: item.rating,
+                }]
+            }
+            Event::LibraryItemFavoritedEvent | Event::LibraryItemUnfavoritedEvent => {
+                vec![if item.is_favorite {
+                    Event::LibraryItemFavoritedEvent
+                } else {
+                    Event::LibraryItemUnfavoritedEvent
+                }]
+            }
+            Event::LibraryItemBookmarkAddedEvent { bookmark_id, .. } => {
+                vec![Event::LibraryItemBookmarkDeletedEvent {
+                    bookmark_id: *bookmark_id,
+                }]
+            }
+            Event::LibraryItemBookmarkDeletedEvent { bookmark_id } => {
+                let Some(bookmark) = item.bookmarks.get(bookmark_id) else {
+                    return vec![];
+                };
+                // re-adding picks the same default emoji, but the user may have changed it
+                vec![
+                    Event::LibraryItemBookmarkAddedEvent {
+                        bookmark_id: *bookmark_id,
+                        position: bookmark.position,
+                        end_position: bookmark.end_position,
+                        label: bookmark.label.clone(),
+                    },
+                    Event::LibraryItemBookmarkSetEmojiEvent {
+                        bookmark_id: *bookmark_id,
+                        emoji: bookmark.emoji.clone(),
+                    },
+                ]
+            }
+            Event::LibraryItemBookmarkMovedEvent { bookmark_id, .. } => item
+                .bookmarks
+                .get(bookmark_id)
+                .map(|bookmark| Event::LibraryItemBookmarkMovedEvent {
+                    bookmark_id: *bookmark_id,
+                    position: bookmark.position,
+                    end_position: bookmark.end_position,
+                })
+                .into_iter()
+                .collect(),
+            Event::LibraryItemBookmarkSetEmojiEvent { bookmark_id, .. } => item
+                .bookmarks
+                .get(bookmark_id)
+                .map(|bookmark| Event::LibraryItemBookmarkSetEmojiEvent {
+                    bookmark_id: *bookmark_id,
+                    emoji: bookmark.emoji.clone(),
+                })
+                .into_iter()
+                .collect(),
+            Event::LibraryItemBookmarkLabelChangedEvent { bookmark_id, .. } => item
+                .bookmarks
+                .get(bookmark_id)
+                .map(|bookmark| Event::LibraryItemBookmarkLabelChangedEvent {
+                    bookmark_id: *bookmark_id,
+                    label: bookmark.label.clone(),
+                })
+                .into_iter()
+                .collect(),
+            Event::LibraryItemTagAddedEvent { tag } if !item.tags.contains(tag) => {
+                vec![Event::LibraryItemTagRemovedEvent { tag: tag.clone() }]
+            }
+            Event::LibraryItemTagRemovedEvent { tag } if item.tags.contains(tag) => {
+                vec![Event::LibraryItemTagAddedEvent { tag: tag.clone() }]
+            }
+            Event::LibraryItemCreatedEvent { .. }
+            | Event::LibraryItemRestoredEvent
+            | Event::LibraryItemPlayedEvent(_)
+            | Event::LibraryItemResumePositionChangedEvent { .. }
+            | Event::LibraryItemTagAddedEvent { .. }
+            | Event::LibraryItemTagRemovedEvent { .. }
+            | Event::Unknown { .. } => vec![],
+        }
+    }
+
+    /// Replay one item's events (in order), recording the item's state around each of them
+    pub fn item_history(events: Vec<EventWithMetadata>, item_id: Uuid) -> Vec<ItemHistoryEntry> {
+        let mut library = Library::new();
+        events
+            .into_iter()
+            .filter(|event| event.aggregate_id == item_id)
+            .map(|event| {
+                let before = library.items.get(&item_id).cloned();
+                library.apply(&event);
+                let after = library.items.get(&item_id).cloned();
+                ItemHistoryEntry {
+                    event,
+                    before,
+                    after,
+                }
+            })
+            .collect()
+    }
+
     /// Apply an event to update the library state
     pub fn apply(&mut self, event: &EventWithMetadata) {
         match &event.event {
-            Event::LibraryItemCreatedEvent { name, file_path, artist, album, track_number } => {
+            Event::LibraryItemCreatedEvent {
+                name,
+                file_path,
+                artist,
+                album,
+                track_number,
+                duration,
+                year,
+                genre,
+            } => {
                 let item = LibraryItem {
                     id: event.aggregate_id,
                     name: name.clone(),
@@ -190,20 +436,42 @@ impl Library {
                     artist: artist.clone().unwrap_or_default(),
                     album: album.clone().unwrap_or_default(),
                     track_number: *track_number,
+                    duration: *duration,
+                    year: *year,
+                    genre: genre.clone().unwrap_or_default(),
                     play_count: 0,
+                    last_played_utc: None,
+                    resume_position: None,
+                    rating: 0,
+                    tags: BTreeSet::new(),
                     bookmarks: IndexMap::new(),
                     is_favorite: false,
                 };
                 self.items.insert(item.id, item);
             }
-            Event::LibraryItemPlayedEvent => {
-                // Update play count or last played time if needed
+            Event::LibraryItemPlayedEvent(details) => {
                 if let Some(item) = self.items.get_mut(&event.aggregate_id) {
                     item.play_count += 1;
+                    item.last_played_utc = item.last_played_utc.max(Some(event.created_time_utc));
+                    if details.completed == Some(true) {
+                        item.resume_position = None;
+                    }
                 }
             }
             Event::LibraryItemDeletedEvent => {
-                self.items.remove(&event.aggregate_id);
+                if let Some(item) = self.items.remove(&event.aggregate_id) {
+                    self.deleted_items.insert(item.id, item);
+                }
+            }
+            Event::LibraryItemRestoredEvent => {
+                if let Some(item) = self.deleted_items.remove(&event.aggregate_id) {
+                    self.items.insert(item.id, item);
+                } else {
+                    warn!(
+                        "Attempted to restore an item that isn't deleted: {}",
+                        event.aggregate_id
+                    );
+                }
             }
             Event::LibraryItemNameChangedEvent { new_name } => {
                 if let Some(item) = self.items.get_mut(&event.aggregate_id) {
@@ -230,9 +498,35 @@ impl Library {
                     item.track_number = *new_track_number;
                 }
             }
+            Event::LibraryItemDurationChangedEvent { new_duration } => {
+                if let Some(item) = self.items.get_mut(&event.aggregate_id) {
+                    item.duration = *new_duration;
+                }
+            }
+            Event::LibraryItemYearChangedEvent { new_year } => {
+                if let Some(item) = self.items.get_mut(&event.aggregate_id) {
+                    item.year = *new_year;
+                }
+            }
+            Event::LibraryItemGenreChangedEvent { new_genre } => {
+                if let Some(item) = self.items.get_mut(&event.aggregate_id) {
+                    item.genre = new_genre.clone();
+                }
+            }
+            Event::LibraryItemRatingChangedEvent { new_rating } => {
+                if let Some(item) = self.items.get_mut(&event.aggregate_id) {
+                    item.rating = (*new_rating).min(MAX_RATING);
+                }
+            }
+            Event::LibraryItemResumePositionChangedEvent { new_position } => {
+                if let Some(item) = self.items.get_mut(&event.aggregate_id) {
+                    item.resume_position = *new_position;
+                }
+            }
             Event::LibraryItemBookmarkAddedEvent {
                 bookmark_id,
                 position,
+                end_position,
                 label,
             } => {
                 if let Some(item) = self.items.get_mut(&event.aggregate_id) {
@@ -248,6 +542,7 @@ impl Library {
                         *bookmark_id,
                         Bookmark {
                             position: *position,
+                            end_position: range_end(*position, *end_position),
                             emoji: music_emoji[emoji_index].to_string(),
                             label: label.clone(),
                             created_time_utc: event.created_time_utc,
@@ -267,6 +562,20 @@ impl Library {
                     item.bookmarks.shift_remove(bookmark_id);
                 }
             }
+            Event::LibraryItemBookmarkMovedEvent {
+                bookmark_id,
+                position,
+                end_position,
+            } => {
+                if let Some(item) = self.items.get_mut(&event.aggregate_id) {
+                    if let Some(bookmark) = item.bookmarks.get_mut(bookmark_id) {
+                        bookmark.position = *position;
+                        bookmark.end_position = range_end(*position, *end_position);
+                        item.bookmarks
+                            .sort_by(|_, v1, _, v2| Ord::cmp(&v1.position, &v2.position));
+                    }
+                }
+            }
             Event::LibraryItemBookmarkSetEmojiEvent { bookmark_id, emoji } => {
                 if let Some(item) = self.items.get_mut(&event.aggregate_id) {
                     if let Some(bookmark) = item.bookmarks.get_mut(bookmark_id) {
@@ -286,28 +595,78 @@ impl Library {
                     item.is_favorite = true;
                 }
             }
+            Event::LibraryItemTagAddedEvent { tag } => {
+                if let Some(item) = self.items.get_mut(&event.aggregate_id) {
+                    item.tags.insert(tag.clone());
+                }
+            }
+            Event::LibraryItemTagRemovedEvent { tag } => {
+                if let Some(item) = self.items.get_mut(&event.aggregate_id) {
+                    item.tags.remove(tag);
+                }
+            }
             Event::LibraryItemUnfavoritedEvent => {
                 if let Some(item) = self.items.get_mut(&event.aggregate_id) {
                     item.is_favorite = false;
                 }
             }
+            Event::Unknown { type_name, .. } => {
+                warn!(id = %event.id, type_name, "Skipping unknown event");
+            }
+        }
+    }
+}
+
+/// One event in a library item's history, with the item as it was just before and after it.
+/// The item is `None` before it was created and after it was deleted.
+#[derive(Debug)]
+pub struct ItemHistoryEntry {
+    pub event: EventWithMetadata,
+    pub before: Option<LibraryItem>,
+    pub after: Option<LibraryItem>,
+}
+
+impl ItemHistoryEntry {
+    /// What the event changed, in words (e.g. `artist: '' → 'Girl Talk'`)
+    pub fn changes(&self) -> Vec<String> {
+        match (&self.before, &self.after) {
+            (None, None) => vec![],
+            (None, Some(_)) if matches!(self.event.event, Event::LibraryItemRestoredEvent) => {
+                vec!["restored".to_string()]
+            }
+            (None, Some(after)) => vec![format!("created: '{}'", after.name)],
+            (Some(_), None) => vec!["deleted".to_string()],
+            (Some(before), Some(after)) => before.changes_to(after),
         }
     }
 }
 
 /// Library event types
+///
+/// `Serialize`/`Deserialize` are implemented below so that unknown events pass through
+/// unchanged; the derived versions are what they use for everything else.
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
-#[serde(tag = "$type", rename_all_fields = "PascalCase")]
+#[serde(remote = "Self", tag = "$type", rename_all_fields = "PascalCase")]
 pub enum Event {
-    LibraryItemPlayedEvent,
+    LibraryItemPlayedEvent(PlayDetails),
     LibraryItemCreatedEvent {
         name: String,
         artist: Option<String>,
         album: Option<String>,
         track_number: Option<u32>,
         file_path: String,
+        /// Duration, year and genre are read from the file's tags, so events from before
+        /// they were recorded (and uploads without tags) don't have them
+        #[serde(default, with = "duration_serde_dotnet::option")]
+        duration: Option<Duration>,
+        #[serde(default)]
+        year: Option<u32>,
+        #[serde(default)]
+        genre: Option<String>,
     },
     LibraryItemDeletedEvent,
+    /// Undoes a `LibraryItemDeletedEvent`, bringing the item back as it was
+    LibraryItemRestoredEvent,
     LibraryItemNameChangedEvent {
         new_name: String,
     },
@@ -323,16 +682,37 @@ pub enum Event {
     LibraryItemTrackNumberChangedEvent {
         new_track_number: Option<u32>,
     },
+    LibraryItemDurationChangedEvent {
+        #[serde(with = "duration_serde_dotnet::option")]
+        new_duration: Option<Duration>,
+    },
+    LibraryItemYearChangedEvent {
+        new_year: Option<u32>,
+    },
+    LibraryItemGenreChangedEvent {
+        new_genre: String,
+    },
     LibraryItemBookmarkAddedEvent {
         bookmark_id: Uuid,
         #[serde(with = "duration_serde_dotnet")]
         position: Duration,
+        /// Where the section a range bookmark marks ends; plain bookmarks don't have one
+        #[serde(default, with = "duration_serde_dotnet::option")]
+        end_position: Option<Duration>,
         #[serde(default)]
         label: Option<String>,
     },
     LibraryItemBookmarkDeletedEvent {
         bookmark_id: Uuid,
     },
+    /// Moves a bookmark, keeping its emoji and label
+    LibraryItemBookmarkMovedEvent {
+        bookmark_id: Uuid,
+        #[serde(with = "duration_serde_dotnet")]
+        position: Duration,
+        #[serde(with = "duration_serde_dotnet::option")]
+        end_position: Option<Duration>,
+    },
     LibraryItemBookmarkSetEmojiEvent {
         bookmark_id: Uuid,
         emoji: String,
@@ -343,10 +723,115 @@ pub enum Event {
     },
     LibraryItemFavoritedEvent,
     LibraryItemUnfavoritedEvent,
+    /// 0 (unrated) to `MAX_RATING` stars
+    LibraryItemRatingChangedEvent {
+        new_rating: u8,
+    },
+    /// Where to pick the item up again after it was stopped partway through; `None` starts
+    /// it from the beginning. Not undoable, like plays.
+    LibraryItemResumePositionChangedEvent {
+        #[serde(with = "duration_serde_dotnet::option")]
+        new_position: Option<Duration>,
+    },
+    LibraryItemTagAddedEvent {
+        tag: String,
+    },
+    LibraryItemTagRemovedEvent {
+        tag: String,
+    },
+    /// An event this version doesn't understand (probably written by a newer version), kept
+    /// as raw JSON so that it's saved and served back out unchanged
+    #[serde(skip)]
+    Unknown {
+        type_name: String,
+        raw: serde_json::Value,
+    },
+}
+
+/// Every `$type` in `Event` (other than `Unknown`). Events of these types that don't
+/// deserialize are errors rather than unknown events.
+const EVENT_TYPES: &[&str] = &[
+    "LibraryItemPlayedEvent",
+    "LibraryItemCreatedEvent",
+    "LibraryItemDeletedEvent",
+    "LibraryItemRestoredEvent",
+    "LibraryItemNameChangedEvent",
+    "LibraryItemFilePathChangedEvent",
+    "LibraryItemArtistChangedEvent",
+    "LibraryItemAlbumChangedEvent",
+    "LibraryItemTrackNumberChangedEvent",
+    "LibraryItemDurationChangedEvent",
+    "LibraryItemYearChangedEvent",
+    "LibraryItemGenreChangedEvent",
+    "LibraryItemBookmarkAddedEvent",
+    "LibraryItemBookmarkDeletedEvent",
+    "LibraryItemBookmarkMovedEvent",
+    "LibraryItemBookmarkSetEmojiEvent",
+    "LibraryItemBookmarkLabelChangedEvent",
+    "LibraryItemFavoritedEvent",
+    "LibraryItemUnfavoritedEvent",
+    "LibraryItemRatingChangedEvent",
+    "LibraryItemResumePositionChangedEvent",
+    "LibraryItemTagAddedEvent",
+    "LibraryItemTagRemovedEvent",
+];
+
+impl Serialize for Event {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Event::Unknown { raw, .. } => raw.serialize(serializer),
+            event => Event::serialize(event, serializer),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Event {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserialize_event(
+            deserializer,
+            EVENT_TYPES,
+            |raw| Event::deserialize(raw),
+            |type_name, raw| Event::Unknown { type_name, raw },
+        )
+    }
+}
+
+/// What we know about a play. Older clients (and every event from before these were
+/// recorded) leave all of it out, so a plain `LibraryItemPlayedEvent` is still a valid play.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct PlayDetails {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<PlaySource>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seconds_listened: Option<u32>,
+    /// Whether the item was played to the end
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub completed: Option<bool>,
+}
+
+impl PlayDetails {
+    pub fn from_source(source: PlaySource) -> Self {
+        PlayDetails {
+            source: Some(source),
+            ..Default::default()
+        }
+    }
+}
+
+/// Where an item was played from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PlaySource {
+    Browser,
+    Sonos,
+    Tui,
+    /// A source added by a newer version
+    #[serde(other)]
+    Other,
 }
 
 /// Library item representation
-#[derive(Debug, Clone, PartialEq, Serialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct LibraryItem {
     pub id: Uuid,
     pub name: String,
@@ -355,33 +840,213 @@ pub struct LibraryItem {
     pub artist: String,
     pub album: String,
     pub track_number: Option<u32>,
+    #[serde(default, with = "duration_serde_seconds::option")]
+    pub duration: Option<Duration>,
+    #[serde(default)]
+    pub year: Option<u32>,
+    #[serde(default)]
+    pub genre: String,
     pub play_count: u32,
+    #[serde(default)]
+    pub last_played_utc: Option<DateTime>,
+    #[serde(default, with = "duration_serde_seconds::option")]
+    pub resume_position: Option<Duration>,
+    /// 0 (unrated) to `MAX_RATING` stars
+    #[serde(default)]
+    pub rating: u8,
+    #[serde(default)]
+    pub tags: BTreeSet<String>,
     pub bookmarks: IndexMap<Uuid, Bookmark>,
     pub is_favorite: bool,
 }
 
+impl LibraryItem {
+    /// The event that saves `position` as where to resume this item, or `None` if it's too
+    /// close to the saved one to be worth an event (see `RESUME_POSITION_INTERVAL`)
+    pub fn resume_position_event(&self, position: Duration) -> Option<Event> {
+        let near_end = self
+            .duration
+            .is_some_and(|duration| position.saturating_add(RESUME_POSITION_MARGIN) >= duration);
+        let new_position = (position >= RESUME_POSITION_MARGIN && !near_end).then_some(position);
+        let changed = match (self.resume_position, new_position) {
+            (Some(old), Some(new)) => old.abs_diff(new) >= RESUME_POSITION_INTERVAL,
+            (old, new) => old != new,
+        };
+        changed.then_some(Event::LibraryItemResumePositionChangedEvent { new_position })
+    }
+
+    /// Describe every difference between this and a later version of the same item
+    fn changes_to(&self, after: &LibraryItem) -> Vec<String> {
+        let track_number = |item: &LibraryItem| item.track_number.map(|n| n.to_string());
+        let duration = |item: &LibraryItem| item.duration.map(format_position);
+        let year = |item: &LibraryItem| item.year.map(|year| year.to_string());
+        let last_played = |item: &LibraryItem| item.last_played_utc.map(|t| t.to_string());
+        let resume_position = |item: &LibraryItem| item.resume_position.map(format_position);
+        let mut changes: Vec<_> = [
+            describe_change("name", &self.name, &after.name),
+            describe_change("file_path", &self.file_path, &after.file_path),
+            describe_change("artist", &self.artist, &after.artist),
+            describe_change("album", &self.album, &after.album),
+            describe_change(
+                "track_number",
+                &track_number(self).unwrap_or_default(),
+                &track_number(after).unwrap_or_default(),
+            ),
+            describe_change(
+                "duration",
+                &duration(self).unwrap_or_default(),
+                &duration(after).unwrap_or_default(),
+            ),
+            describe_change(
+                "year",
+                &year(self).unwrap_or_default(),
+                &year(after).unwrap_or_default(),
+            ),
+            describe_change("genre", &self.genre, &after.genre),
+            describe_change(
+                "play_count",
+                &self.play_count.to_string(),
+                &after.play_count.to_string(),
+            ),
+            describe_change(
+                "last_played_utc",
+                &last_played(self).unwrap_or_default(),
+                &last_played(after).unwrap_or_default(),
+            ),
+            describe_change(
+                "resume_position",
+                &resume_position(self).unwrap_or_default(),
+                &resume_position(after).unwrap_or_default(),
+            ),
+            describe_change(
+                "rating",
+                &self.rating.to_string(),
+                &after.rating.to_string(),
+            ),
+            describe_change(
+                "is_favorite",
+                &self.is_favorite.to_string(),
+                &after.is_favorite.to_string(),
+            ),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+
+        changes.extend(
+            self.tags
+                .difference(&after.tags)
+                .map(|tag| format!("tag removed: '{tag}'")),
+        );
+        changes.extend(
+            after
+                .tags
+                .difference(&self.tags)
+                .map(|tag| format!("tag added: '{tag}'")),
+        );
+
+        for (id, bookmark) in &self.bookmarks {
+            let Some(new) = after.bookmarks.get(id) else {
+                changes.push(format!("bookmark removed: {}", bookmark.describe()));
+                continue;
+            };
+            let field = |name: &str| format!("bookmark {} {name}", bookmark.describe());
+            let label = |bookmark: &Bookmark| bookmark.label.clone().unwrap_or_default();
+            changes.extend(
+                [
+                    describe_change(
+                        &field("position"),
+                        &bookmark.describe_position(),
+                        &new.describe_position(),
+                    ),
+                    describe_change(&field("label"), &label(bookmark), &label(new)),
+                    describe_change(&field("emoji"), &bookmark.emoji, &new.emoji),
+                ]
+                .into_iter()
+                .flatten(),
+            );
+        }
+        for (id, bookmark) in &after.bookmarks {
+            if !self.bookmarks.contains_key(id) {
+                changes.push(format!("bookmark added: {}", bookmark.describe()));
+            }
+        }
+
+        changes
+    }
+}
 
 /// Bookmark within a library item
-#[derive(Debug, Clone, PartialEq, Serialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Bookmark {
     #[serde(with = "duration_serde_seconds")]
     pub position: std::time::Duration,
+    /// Set for bookmarks that mark a section (e.g. one track in a mix) rather than a point
+    #[serde(default, with = "duration_serde_seconds::option")]
+    pub end_position: Option<std::time::Duration>,
     pub emoji: String,
     pub label: Option<String>,
     pub created_time_utc: DateTime,
 }
 
+impl Bookmark {
+    /// e.g. `🎵 1:02 'Chorus'`, or `🎵 1:02–3:30 'Chorus'` for a range
+    fn describe(&self) -> String {
+        match &self.label {
+            Some(label) => format!("{} {} '{label}'", self.emoji, self.describe_position()),
+            None => format!("{} {}", self.emoji, self.describe_position()),
+        }
+    }
+
+    fn describe_position(&self) -> String {
+        match self.end_position {
+            Some(end) => format!(
+                "{}–{}",
+                format_position(self.position),
+                format_position(end)
+            ),
+            None => format_position(self.position),
+        }
+    }
+}
+
+/// Ranges that end before they start are treated as plain bookmarks
+fn range_end(position: Duration, end_position: Option<Duration>) -> Option<Duration> {
+    end_position.filter(|end| *end > position)
+}
+
+fn format_position(position: Duration) -> String {
+    let seconds = position.as_secs();
+    format!("{}:{:02}", seconds / 60, seconds % 60)
+}
+
+/// A `LibraryItemCreatedEvent` with only a name (and `<name>.mp3`) filled in, for tests
+#[cfg(test)]
+pub(crate) fn created(name: &str) -> Event {
+    Event::LibraryItemCreatedEvent {
+        name: name.to_string(),
+        artist: None,
+        album: None,
+        track_number: None,
+        file_path: format!("{name}.mp3"),
+        duration: None,
+        year: None,
+        genre: None,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::database::save_event_to_db;
+    use crate::database::{load_all_events_from_db, save_event_to_db, save_raw_event_to_db};
+    use crate::migrations::migrate;
     use rusqlite::Connection;
 
     #[test]
     fn test_load_library_from_db() {
         // Use in-memory database for testing
         let conn = Connection::open(":memory:").unwrap();
-        conn.execute_batch(include_str!("../schema.sql")).unwrap();
+        migrate(&conn).unwrap();
 
         let library = load_library_from_db(&conn).unwrap();
 
@@ -396,20 +1061,11 @@ mod tests {
     fn test_save_and_load_events() -> Result<()> {
         // Open a connection to the temporary database
         let conn = Connection::open(":memory:")?;
-        conn.execute_batch(include_str!("../schema.sql"))?;
+        migrate(&conn)?;
 
         // Create a new library item event
         let item_id = Uuid::new_v4();
-        let create_event = EventWithMetadata::new(
-            item_id,
-            Event::LibraryItemCreatedEvent {
-                name: "Test Item".to_string(),
-                file_path: "test/path.mp3".to_string(),
-                artist: None,
-                album: None,
-                track_number: None,
-            },
-        )?;
+        let create_event = EventWithMetadata::new(item_id, created("Test Item"))?;
 
         // Save the event to the database
         save_event_to_db(&conn, &create_event)?;
@@ -435,6 +1091,7 @@ mod tests {
             Event::LibraryItemBookmarkAddedEvent {
                 bookmark_id,
                 position: Duration::from_secs(60),
+                end_position: None,
                 label: None,
             },
         )?;
@@ -454,27 +1111,66 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn unknown_events_are_skipped_and_round_trip_unchanged() -> Result<()> {
+        let conn = Connection::open_in_memory()?;
+        migrate(&conn)?;
+
+        let item_id = Uuid::new_v4();
+        let created = EventWithMetadata::new(item_id, created("Known"))?;
+        save_event_to_db(&conn, &created)?;
+
+        // as if written by a newer version
+        let raw = serde_json::json!({ "$type": "LibraryItemRatedEvent", "Rating": 5 });
+        let mut rated = EventWithMetadata::new(
+            item_id,
+            Event::LibraryItemPlayedEvent(PlayDetails::default()),
+        )?
+        .to_raw()?;
+        rated.event = raw.clone();
+        save_raw_event_to_db(&conn, &rated)?;
+
+        let library = load_library_from_db(&conn)?;
+        assert_eq!(library.items[&item_id].name, "Known");
+
+        let events = load_all_events_from_db::<Event>(&conn)?;
+        assert_eq!(
+            events[1].event,
+            Event::Unknown {
+                type_name: "LibraryItemRatedEvent".to_string(),
+                raw: raw.clone()
+            }
+        );
+        assert_eq!(serde_json::to_value(&events[1])?["event"], raw);
+        assert_eq!(events[1].to_raw()?, rated);
+
+        Ok(())
+    }
+
+    #[test]
+    fn known_event_types_with_bad_fields_are_errors() {
+        let garbled = serde_json::json!({ "$type": "LibraryItemNameChangedEvent", "NewName": 5 });
+        assert!(serde_json::from_value::<Event>(garbled).is_err());
+        let untyped = serde_json::json!({ "NewName": "Untyped" });
+        assert!(serde_json::from_value::<Event>(untyped).is_err());
+
+        let garbled = serde_json::json!({ "$type": "PlaylistItemAddedEvent", "Position": 0 });
+        assert!(serde_json::from_value::<crate::playlist::PlaylistEvent>(garbled).is_err());
+    }
+
     #[test]
     fn bookmark_events_support_labels_and_deletion() -> Result<()> {
         let item_id = Uuid::new_v4();
         let bookmark_id = Uuid::new_v4();
         let mut library = Library::new();
 
-        library.apply(&EventWithMetadata::new(
-            item_id,
-            Event::LibraryItemCreatedEvent {
-                name: "Test Item".to_string(),
-                artist: None,
-                album: None,
-                track_number: None,
-                file_path: "test.mp3".to_string(),
-            },
-        )?);
+        library.apply(&EventWithMetadata::new(item_id, created("Test Item"))?);
         library.apply(&EventWithMetadata::new(
             item_id,
             Event::LibraryItemBookmarkAddedEvent {
                 bookmark_id,
                 position: Duration::from_secs(42),
+                end_position: None,
                 label: Some("Intro".to_string()),
             },
         )?);
@@ -510,6 +1206,162 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn compensating_events_undo_edits_and_deletes() -> Result<()> {
+        let item_id = Uuid::new_v4();
+        let bookmark_id = Uuid::new_v4();
+        let mut library = Library::build_from_events(vec![
+            EventWithMetadata::new(
+                item_id,
+                Event::LibraryItemCreatedEvent {
+                    name: "Original".to_string(),
+                    artist: Some("Artist".to_string()),
+                    album: None,
+                    track_number: Some(3),
+                    file_path: "original.mp3".to_string(),
+                    duration: None,
+                    year: None,
+                    genre: None,
+                },
+            )?,
+            EventWithMetadata::new(
+                item_id,
+                Event::LibraryItemBookmarkAddedEvent {
+                    bookmark_id,
+                    position: Duration::from_secs(10),
+                    end_position: Some(Duration::from_secs(30)),
+                    label: Some("Intro".to_string()),
+                },
+            )?,
+            EventWithMetadata::new(
+                item_id,
+                Event::LibraryItemBookmarkSetEmojiEvent {
+                    bookmark_id,
+                    emoji: "🔥".to_string(),
+                },
+            )?,
+        ]);
+
+        let edits = [
+            Event::LibraryItemNameChangedEvent {
+                new_name: "Renamed".to_string(),
+            },
+            Event::LibraryItemTrackNumberChangedEvent {
+                new_track_number: None,
+            },
+            Event::LibraryItemDurationChangedEvent {
+                new_duration: Some(Duration::from_secs(215)),
+            },
+            Event::LibraryItemYearChangedEvent {
+                new_year: Some(2009),
+            },
+            Event::LibraryItemGenreChangedEvent {
+                new_genre: "Mashup".to_string(),
+            },
+            Event::LibraryItemFavoritedEvent,
+            Event::LibraryItemRatingChangedEvent { new_rating: 4 },
+            Event::LibraryItemTagAddedEvent {
+                tag: "workout".to_string(),
+            },
+            Event::LibraryItemBookmarkLabelChangedEvent {
+                bookmark_id,
+                label: None,
+            },
+            Event::LibraryItemBookmarkMovedEvent {
+                bookmark_id,
+                position: Duration::from_secs(12),
+                end_position: None,
+            },
+            Event::LibraryItemBookmarkDeletedEvent { bookmark_id },
+            Event::LibraryItemDeletedEvent,
+        ];
+        for edit in edits {
+            let before = library.items[&item_id].clone();
+            let edit = EventWithMetadata::new(item_id, edit)?;
+            let compensation = library.compensating_events(&edit);
+            assert!(!compensation.is_empty(), "{:?}", edit.event);
+
+            library.apply(&edit);
+            assert_ne!(library.items.get(&item_id), Some(&before));
+            for event in compensation {
+                library.apply(&EventWithMetadata::new(item_id, event)?);
+            }
+
+            // a re-added bookmark is otherwise identical, but gets a new creation time
+            let mut after = library.items[&item_id].clone();
+            for (id, bookmark) in &mut after.bookmarks {
+                bookmark.created_time_utc = before.bookmarks[id].created_time_utc;
+            }
+            assert_eq!(after, before, "{:?}", edit.event);
+        }
+
+        let restored = EventWithMetadata::new(item_id, Event::LibraryItemRestoredEvent)?;
+        assert!(library.compensating_events(&restored).is_empty());
+        let played = EventWithMetadata::new(
+            item_id,
+            Event::LibraryItemPlayedEvent(PlayDetails::default()),
+        )?;
+        assert!(library.compensating_events(&played).is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn item_history_describes_each_change() -> Result<()> {
+        let item_id = Uuid::new_v4();
+        let bookmark_id = Uuid::new_v4();
+        let events = [
+            created("Play Your Part"),
+            Event::LibraryItemArtistChangedEvent {
+                new_artist: "Girl Talk".to_string(),
+            },
+            Event::LibraryItemBookmarkAddedEvent {
+                bookmark_id,
+                position: Duration::from_secs(62),
+                end_position: None,
+                label: None,
+            },
+            Event::LibraryItemBookmarkLabelChangedEvent {
+                bookmark_id,
+                label: Some("Drop".to_string()),
+            },
+            Event::LibraryItemBookmarkMovedEvent {
+                bookmark_id,
+                position: Duration::from_secs(60),
+                end_position: Some(Duration::from_secs(150)),
+            },
+            Event::LibraryItemDeletedEvent,
+            Event::LibraryItemRestoredEvent,
+        ]
+        .into_iter()
+        .map(|event| EventWithMetadata::new(item_id, event))
+        .chain([EventWithMetadata::new(
+            Uuid::new_v4(),
+            Event::LibraryItemPlayedEvent(PlayDetails::default()),
+        )])
+        .collect::<Result<_>>()?;
+
+        let history = Library::item_history(events, item_id);
+        let changes: Vec<_> = history.iter().map(ItemHistoryEntry::changes).collect();
+        let emoji = &history[2].after.as_ref().unwrap().bookmarks[&bookmark_id].emoji;
+        assert_eq!(
+            changes,
+            vec![
+                vec!["created: 'Play Your Part'".to_string()],
+                vec!["artist: '' → 'Girl Talk'".to_string()],
+                vec![format!("bookmark added: {emoji} 1:02")],
+                vec![format!("bookmark {emoji} 1:02 label: '' → 'Drop'")],
+                vec![format!(
+                    "bookmark {emoji} 1:02 'Drop' position: '1:02' → '1:00–2:30'"
+                )],
+                vec!["deleted".to_string()],
+                vec!["restored".to_string()],
+            ]
+        );
+
+        Ok(())
+    }
+
     #[test]
     fn old_bookmark_events_without_labels_still_deserialize() -> Result<()> {
         let bookmark_id = Uuid::new_v4();
@@ -523,10 +1375,157 @@ mod tests {
             Event::LibraryItemBookmarkAddedEvent {
                 bookmark_id,
                 position: Duration::from_secs(62),
+                end_position: None,
                 label: None,
             }
         );
 
         Ok(())
     }
+
+    #[test]
+    fn retagging_renames_and_merges_tags() -> Result<()> {
+        let [gym, run, both, neither] = [1, 2, 3, 4].map(Uuid::from_u128);
+        let mut events = Vec::new();
+        for (id, tags) in [
+            (gym, vec!["gym"]),
+            (run, vec!["running"]),
+            (both, vec!["gym", "workout"]),
+            (neither, vec!["podcast"]),
+        ] {
+            events.push(EventWithMetadata::new(id, created(&id.to_string()))?);
+            for tag in tags {
+                let tag = tag.to_string();
+                events.push(EventWithMetadata::new(
+                    id,
+                    Event::LibraryItemTagAddedEvent { tag },
+                )?);
+            }
+        }
+        let mut library = Library::build_from_events(events);
+
+        let from = ["gym".to_string(), "running".to_string()];
+        let retag = library.retag_events(&from, "workout");
+        assert_eq!(
+            retag.iter().map(|(id, _)| *id).collect::<Vec<_>>(),
+            vec![gym, run, both]
+        );
+        for (id, events) in retag {
+            for event in events {
+                library.apply(&EventWithMetadata::new(id, event)?);
+            }
+        }
+
+        let counts: Vec<_> = library.tag_counts().into_iter().collect();
+        assert_eq!(
+            counts,
+            vec![("podcast".to_string(), 1), ("workout".to_string(), 3)]
+        );
+        assert!(library.retag_events(&from, "workout").is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn resume_positions_are_throttled_and_cleared_near_the_ends() -> Result<()> {
+        let id = Uuid::new_v4();
+        let mut library = Library::build_from_events(vec![EventWithMetadata::new(
+            id,
+            Event::LibraryItemCreatedEvent {
+                name: "Long Mix".to_string(),
+                artist: None,
+                album: None,
+                track_number: None,
+                file_path: "mix.mp3".to_string(),
+                duration: Some(Duration::from_secs(3600)),
+                year: None,
+                genre: None,
+            },
+        )?]);
+        let resume_at = |library: &Library, seconds| {
+            library.items[&id].resume_position_event(Duration::from_secs(seconds))
+        };
+
+        assert_eq!(resume_at(&library, 5), None);
+        let event = resume_at(&library, 600).unwrap();
+        assert_eq!(
+            event,
+            Event::LibraryItemResumePositionChangedEvent {
+                new_position: Some(Duration::from_secs(600))
+            }
+        );
+        library.apply(&EventWithMetadata::new(id, event)?);
+        assert_eq!(resume_at(&library, 615), None);
+        assert!(resume_at(&library, 630).is_some());
+        assert_eq!(
+            resume_at(&library, 3595),
+            Some(Event::LibraryItemResumePositionChangedEvent { new_position: None })
+        );
+
+        library.apply(&EventWithMetadata::new(
+            id,
+            Event::LibraryItemPlayedEvent(PlayDetails {
+                completed: Some(true),
+                ..Default::default()
+            }),
+        )?);
+        assert_eq!(library.items[&id].resume_position, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn old_created_events_without_tag_details_still_deserialize() -> Result<()> {
+        let serialized = r#"{"$type":"LibraryItemCreatedEvent","Name":"Once Again","Artist":null,"Album":null,"TrackNumber":null,"FilePath":"once.mp3"}"#;
+        let Event::LibraryItemCreatedEvent {
+            duration,
+            year,
+            genre,
+            ..
+        } = serde_json::from_str(serialized)?
+        else {
+            panic!("expected a created event");
+        };
+        assert_eq!((duration, year, genre), (None, None, None));
+
+        let changed = Event::LibraryItemDurationChangedEvent {
+            new_duration: Some(Duration::from_millis(215_500)),
+        };
+        let serialized = serde_json::to_string(&changed)?;
+        assert_eq!(
+            serialized,
+            r#"{"$type":"LibraryItemDurationChangedEvent","NewDuration":"00:03:35.500000000"}"#
+        );
+        assert_eq!(serde_json::from_str::<Event>(&serialized)?, changed);
+
+        Ok(())
+    }
+
+    #[test]
+    fn played_events_keep_their_old_shape_and_accept_details() -> Result<()> {
+        let old = r#"{"$type":"LibraryItemPlayedEvent"}"#;
+        let event: Event = serde_json::from_str(old)?;
+        assert_eq!(event, Event::LibraryItemPlayedEvent(PlayDetails::default()));
+        assert_eq!(serde_json::to_string(&event)?, old);
+
+        let detailed = r#"{"$type":"LibraryItemPlayedEvent","Source":"Sonos","SecondsListened":95,"Completed":true}"#;
+        let event: Event = serde_json::from_str(detailed)?;
+        assert_eq!(
+            event,
+            Event::LibraryItemPlayedEvent(PlayDetails {
+                source: Some(PlaySource::Sonos),
+                seconds_listened: Some(95),
+                completed: Some(true),
+            })
+        );
+        assert_eq!(serde_json::to_string(&event)?, detailed);
+
+        let from_the_future = r#"{"$type":"LibraryItemPlayedEvent","Source":"Car"}"#;
+        assert_eq!(
+            serde_json::from_str::<Event>(from_the_future)?,
+            Event::LibraryItemPlayedEvent(PlayDetails::from_source(PlaySource::Other))
+        );
+
+        Ok(())
+    }
 }
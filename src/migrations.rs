@@ -0,0 +1,196 @@
+use anyhow::{bail, Context, Result};
+use rusqlite::Connection;
+use tracing::info;
+
+/// A step that moves the database schema forward by one version
+struct Migration {
+    description: &'static str,
+    sql: &'static str,
+}
+
+/// Every schema change, in order. Migration N (counting from 1) upgrades a database at
+/// `user_version` N-1 to N. Never edit or reorder migrations that have shipped; add a new one.
+const MIGRATIONS: &[Migration] = &[
+    // Databases created before migrations existed are at version 0 but may already have these
+    // tables, which is why schema.sql only uses CREATE TABLE IF NOT EXISTS
+    Migration {
+        description: "baseline schema",
+        sql: include_str!("../schema.sql"),
+    },
+    Migration {
+        description: "index events by aggregate",
+        sql: "CREATE INDEX IF NOT EXISTS events_by_aggregate_type_and_time
+                  ON events(AggregateType, CreatedTimeUtc);
+              CREATE INDEX IF NOT EXISTS events_by_aggregate_id ON events(AggregateId);",
+    },
+    Migration {
+        description: "hybrid logical clocks",
+        sql: "ALTER TABLE events ADD COLUMN HlcMillis INTEGER NOT NULL DEFAULT 0;
+              ALTER TABLE events ADD COLUMN HlcCounter INTEGER NOT NULL DEFAULT 0;
+              -- existing events get their creation time (see Hlc::from_created_time)
+              UPDATE events
+                  SET HlcMillis = CAST(ROUND((julianday(CreatedTimeUtc) - 2440587.5) * 86400000) AS INTEGER);
+              DROP INDEX events_by_aggregate_type_and_time;
+              CREATE INDEX events_by_aggregate_type_and_hlc
+                  ON events(AggregateType, HlcMillis, HlcCounter);
+              -- snapshots are only a cache and the existing ones were built in CreatedTimeUtc order
+              DROP TABLE snapshots;
+              CREATE TABLE snapshots(
+                  Id INTEGER PRIMARY KEY AUTOINCREMENT,
+                  Kind TEXT NOT NULL,
+                  SchemaVersion INTEGER NOT NULL,
+                  LastEventRowId INTEGER NOT NULL,
+                  LastEventHlcMillis INTEGER NOT NULL,
+                  LastEventHlcCounter INTEGER NOT NULL,
+                  CreatedTimeUtc TEXT NOT NULL,
+                  Serialized TEXT NOT NULL
+              );",
+    },
+    Migration {
+        description: "explicit event sequence numbers",
+        // Sync cursors and snapshots point at events by position in the log. Implicit rowids
+        // can be renumbered by VACUUM, so give events an INTEGER PRIMARY KEY (which can't be)
+        // that starts out equal to the old rowid so existing cursors stay valid. AUTOINCREMENT
+        // stops deleted sequence numbers from being reused.
+        sql: "CREATE TABLE events_with_seq(
+                  Seq INTEGER PRIMARY KEY AUTOINCREMENT,
+                  Id TEXT NOT NULL UNIQUE,
+                  AggregateId TEXT NOT NULL,
+                  AggregateType TEXT NOT NULL,
+                  CreatedTimeUtc TEXT NOT NULL,
+                  MachineName TEXT NOT NULL,
+                  Serialized TEXT NOT NULL,
+                  HlcMillis INTEGER NOT NULL DEFAULT 0,
+                  HlcCounter INTEGER NOT NULL DEFAULT 0
+              );
+              INSERT INTO events_with_seq
+                  (Seq, Id, AggregateId, AggregateType, CreatedTimeUtc, MachineName, Serialized,
+                   HlcMillis, HlcCounter)
+                  SELECT rowid, Id, AggregateId, AggregateType, CreatedTimeUtc, MachineName,
+                         Serialized, HlcMillis, HlcCounter
+                  FROM events ORDER BY rowid;
+              DROP TABLE events;
+              ALTER TABLE events_with_seq RENAME TO events;
+              CREATE INDEX events_by_aggregate_type_and_hlc
+                  ON events(AggregateType, HlcMillis, HlcCounter);
+              CREATE INDEX events_by_aggregate_id ON events(AggregateId);",
+    },
+];
+
+/// The schema version that this binary expects
+pub const SCHEMA_VERSION: i64 = MIGRATIONS.len() as i64;
+
+/// Bring the database schema up to date, refusing to touch databases created by a newer
+/// version of reitunes (we'd have no idea what the extra migrations did).
+pub fn migrate(conn: &Connection) -> Result<()> {
+    let current: i64 = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    if current > SCHEMA_VERSION {
+        bail!(
+            "Database schema version {current} is newer than the latest version this build \
+             supports ({SCHEMA_VERSION}); upgrade reitunes before opening it"
+        );
+    }
+
+    for (version, migration) in (1..).zip(MIGRATIONS).skip(current as usize) {
+        // Each migration and its version bump are committed together, so a failed migration
+        // leaves the database at the previous version
+        let tx = conn.unchecked_transaction()?;
+        tx.execute_batch(migration.sql).with_context(|| {
+            format!(
+                "Failed to apply migration {version} ({})",
+                migration.description
+            )
+        })?;
+        tx.pragma_update(None, "user_version", version)?;
+        tx.commit()?;
+        info!(
+            version,
+            description = migration.description,
+            "Applied migration"
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn index_names(conn: &Connection) -> Result<Vec<String>> {
+        let mut stmt = conn.prepare(
+            "SELECT name FROM sqlite_master WHERE type = 'index' AND tbl_name = 'events'
+             AND name NOT LIKE 'sqlite_%' ORDER BY name",
+        )?;
+        let names = stmt.query_map([], |row| row.get(0))?;
+        Ok(names.collect::<rusqlite::Result<_>>()?)
+    }
+
+    #[test]
+    fn databases_from_before_migrations_are_upgraded() -> Result<()> {
+        let conn = Connection::open_in_memory()?;
+        conn.execute_batch(include_str!("../schema.sql"))?;
+        conn.execute(
+            "INSERT INTO events VALUES ('id', 'aggregate', 'LibraryItem', '2024-01-01T00:00:00', 'machine', '{}')",
+            [],
+        )?;
+
+        migrate(&conn)?;
+        // running again is a no-op
+        migrate(&conn)?;
+
+        let version: i64 = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        assert_eq!(version, SCHEMA_VERSION);
+        assert_eq!(
+            index_names(&conn)?,
+            vec!["events_by_aggregate_id", "events_by_aggregate_type_and_hlc"]
+        );
+        let hlc_millis: i64 =
+            conn.query_row("SELECT HlcMillis FROM events", [], |row| row.get(0))?;
+        assert_eq!(hlc_millis, 1_704_067_200_000);
+        let seq: i64 = conn.query_row("SELECT Seq FROM events", [], |row| row.get(0))?;
+        assert_eq!(seq, 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn sequence_numbers_keep_the_old_rowids_and_are_never_reused() -> Result<()> {
+        let conn = Connection::open_in_memory()?;
+        conn.execute_batch(include_str!("../schema.sql"))?;
+        conn.execute_batch(
+            "INSERT INTO events VALUES ('a', 'aggregate', 'LibraryItem', '2024-01-01T00:00:00', 'machine', '{}');
+             INSERT INTO events VALUES ('b', 'aggregate', 'LibraryItem', '2024-01-01T00:00:00', 'machine', '{}');
+             INSERT INTO events VALUES ('c', 'aggregate', 'LibraryItem', '2024-01-01T00:00:00', 'machine', '{}');
+             DELETE FROM events WHERE Id = 'b';",
+        )?;
+
+        migrate(&conn)?;
+        conn.execute_batch(
+            "DELETE FROM events WHERE Id = 'c';
+             VACUUM;
+             INSERT INTO events (Id, AggregateId, AggregateType, CreatedTimeUtc, MachineName, Serialized)
+                 VALUES ('d', 'aggregate', 'LibraryItem', '2024-01-01T00:00:00', 'machine', '{}');",
+        )?;
+
+        let mut stmt = conn.prepare("SELECT Id, Seq FROM events ORDER BY Seq")?;
+        let seqs = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<rusqlite::Result<Vec<(String, i64)>>>()?;
+        assert_eq!(seqs, vec![("a".to_string(), 1), ("d".to_string(), 4)]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn databases_from_newer_versions_are_refused() -> Result<()> {
+        let conn = Connection::open_in_memory()?;
+        migrate(&conn)?;
+        conn.pragma_update(None, "user_version", SCHEMA_VERSION + 1)?;
+
+        let error = migrate(&conn).unwrap_err();
+        assert!(error.to_string().contains("newer"));
+
+        Ok(())
+    }
+}
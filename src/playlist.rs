@@ -1,23 +1,32 @@
-use anyhow::{Context, Result};
+use anyhow::Result;
 use indexmap::IndexMap;
 use jiff::civil::DateTime;
 use rusqlite::Connection;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use tracing::warn;
 use uuid::Uuid;
 
-use crate::database::load_all_playlist_events_from_db;
-use crate::library::EventRow;
+use crate::database::load_events_after_seq;
+use crate::envelope::{deserialize_event, Envelope};
+use crate::snapshot::{replay_with_snapshot, PLAYLISTS_SNAPSHOT};
+use crate::utils::describe_change;
 
-/// Load and rebuild playlists from their stored events.
+/// Load and rebuild playlists from their stored events, starting from the newest snapshot if
+/// there is one.
 pub fn load_playlists_from_db(conn: &Connection) -> Result<PlaylistStore> {
-    let events = load_all_playlist_events_from_db(conn)?;
-    Ok(PlaylistStore::build_from_events(events))
+    replay_with_snapshot(
+        conn,
+        PLAYLISTS_SNAPSHOT,
+        PlaylistStore::new,
+        |after_seq| load_events_after_seq(conn, after_seq),
+        PlaylistStore::apply,
+        |event| event.hlc,
+    )
 }
 
-/// Playlist event types
+/// Playlist event types. Unknown events pass through unchanged, like library `Event`s.
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
-#[serde(tag = "$type", rename_all_fields = "PascalCase")]
+#[serde(remote = "Self", tag = "$type", rename_all_fields = "PascalCase")]
 pub enum PlaylistEvent {
     PlaylistCreatedEvent {
         name: String,
@@ -37,17 +46,54 @@ pub enum PlaylistEvent {
         library_item_id: Uuid,
         new_position: u32,
     },
+    /// An event this version doesn't understand (probably written by a newer version), kept
+    /// as raw JSON so that it's saved and served back out unchanged
+    #[serde(skip)]
+    Unknown {
+        type_name: String,
+        raw: serde_json::Value,
+    },
+}
+
+/// Every `$type` in `PlaylistEvent` (other than `Unknown`)
+const PLAYLIST_EVENT_TYPES: &[&str] = &[
+    "PlaylistCreatedEvent",
+    "PlaylistRenamedEvent",
+    "PlaylistDeletedEvent",
+    "PlaylistItemAddedEvent",
+    "PlaylistItemRemovedEvent",
+    "PlaylistItemMovedEvent",
+];
+
+impl Serialize for PlaylistEvent {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            PlaylistEvent::Unknown { raw, .. } => raw.serialize(serializer),
+            event => PlaylistEvent::serialize(event, serializer),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for PlaylistEvent {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserialize_event(
+            deserializer,
+            PLAYLIST_EVENT_TYPES,
+            |raw| PlaylistEvent::deserialize(raw),
+            |type_name, raw| PlaylistEvent::Unknown { type_name, raw },
+        )
+    }
 }
 
 /// Playlist item (reference to a library item)
-#[derive(Debug, Clone, PartialEq, Serialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct PlaylistItem {
     pub library_item_id: Uuid,
     pub position: u32,
 }
 
 /// Playlist representation
-#[derive(Debug, Clone, PartialEq, Serialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Playlist {
     pub id: Uuid,
     pub name: String,
@@ -106,12 +152,68 @@ impl Playlist {
                     self.items.sort_by(|_, a, _, b| a.position.cmp(&b.position));
                 }
             }
+            PlaylistEvent::Unknown { type_name, .. } => {
+                warn!(playlist_id = %self.id, type_name, "Skipping unknown playlist event");
+            }
+        }
+    }
+
+    /// Describe every difference between this and a later version of the same playlist
+    fn changes_to(&self, after: &Playlist) -> Vec<String> {
+        let mut changes: Vec<_> = [
+            describe_change("name", &self.name, &after.name),
+            describe_change(
+                "is_deleted",
+                &self.is_deleted.to_string(),
+                &after.is_deleted.to_string(),
+            ),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+
+        for (id, item) in &self.items {
+            match after.items.get(id) {
+                Some(new) => changes.extend(describe_change(
+                    &format!("item {id} position"),
+                    &item.position.to_string(),
+                    &new.position.to_string(),
+                )),
+                None => changes.push(format!("item removed: {id}")),
+            }
+        }
+        for (id, item) in &after.items {
+            if !self.items.contains_key(id) {
+                changes.push(format!("item added: {id} at position {}", item.position));
+            }
+        }
+
+        changes
+    }
+}
+
+/// One event in a playlist's history, with the playlist as it was just before and after it.
+/// The playlist is `None` before it was created.
+#[derive(Debug)]
+pub struct PlaylistHistoryEntry {
+    pub event: PlaylistEventWithMetadata,
+    pub before: Option<Playlist>,
+    pub after: Option<Playlist>,
+}
+
+impl PlaylistHistoryEntry {
+    /// What the event changed, in words (e.g. `name: 'Mix' → 'Party mix'`)
+    pub fn changes(&self) -> Vec<String> {
+        match (&self.before, &self.after) {
+            (_, None) => vec![],
+            (None, Some(after)) => vec![format!("created: '{}'", after.name)],
+            (Some(before), Some(after)) => before.changes_to(after),
         }
     }
 }
 
 /// In-memory collection of all playlists
-#[derive(Clone, Default)]
+#[derive(Clone, Default, Serialize, Deserialize)]
 pub struct PlaylistStore {
     pub playlists: IndexMap<Uuid, Playlist>,
 }
@@ -126,91 +228,106 @@ impl PlaylistStore {
     /// Rebuild all playlists from events in chronological order.
     pub fn build_from_events(events: Vec<PlaylistEventWithMetadata>) -> Self {
         let mut store = PlaylistStore::new();
-
         for event in events {
-            match &event.event {
-                PlaylistEvent::PlaylistCreatedEvent { name } => {
-                    store.playlists.insert(
-                        event.aggregate_id,
-                        Playlist::new(event.aggregate_id, name.clone(), event.created_time_utc),
-                    );
-                }
-                playlist_event => {
-                    if let Some(playlist) = store.playlists.get_mut(&event.aggregate_id) {
-                        playlist.apply(playlist_event);
-                    } else {
-                        warn!(
-                            playlist_id = %event.aggregate_id,
-                            ?playlist_event,
-                            "Ignoring playlist event without a creation event"
-                        );
-                    }
-                }
-            }
+            store.apply(&event);
         }
-
         store
     }
 
-    /// Get non-deleted playlists
-    pub fn active_playlists(&self) -> Vec<&Playlist> {
-        self.playlists.values().filter(|p| !p.is_deleted).collect()
+    /// Replace one playlist with the result of replaying just its events (in HLC order),
+    /// keeping its place in the list. For events that arrive out of order.
+    pub fn rebuild_playlist(&mut self, playlist_id: Uuid, events: &[PlaylistEventWithMetadata]) {
+        let mut rebuilt = PlaylistStore::new();
+        for event in events
+            .iter()
+            .filter(|event| event.aggregate_id == playlist_id)
+        {
+            rebuilt.apply(event);
+        }
+        match rebuilt.playlists.shift_remove(&playlist_id) {
+            Some(playlist) => {
+                self.playlists.insert(playlist_id, playlist);
+            }
+            None => {
+                self.playlists.shift_remove(&playlist_id);
+            }
+        }
     }
-}
 
-/// Event with metadata wrapper for playlists
-#[derive(Debug, Serialize, Deserialize)]
-#[allow(dead_code)]
-pub struct PlaylistEventWithMetadata {
-    pub id: Uuid,
-    pub aggregate_id: Uuid,
-    pub aggregate_type: String,
-    pub created_time_utc: DateTime,
-    pub machine_name: String,
-    pub event: PlaylistEvent,
-}
+    /// Build the playlists as they were at `until`, by applying only the events created at or
+    /// before then
+    pub fn build_until(events: Vec<PlaylistEventWithMetadata>, until: DateTime) -> Self {
+        let mut store = PlaylistStore::new();
+        for event in events.iter().filter(|event| event.created_time_utc <= until) {
+            store.apply(event);
+        }
+        store
+    }
 
-impl PlaylistEventWithMetadata {
-    pub fn new(playlist_id: Uuid, event: PlaylistEvent) -> Result<Self> {
-        use jiff::tz::TimeZone;
-        use jiff::Zoned;
+    /// Replay one playlist's events (in order), recording its state around each of them
+    pub fn playlist_history(
+        events: Vec<PlaylistEventWithMetadata>,
+        playlist_id: Uuid,
+    ) -> Vec<PlaylistHistoryEntry> {
+        let mut store = PlaylistStore::new();
+        events
+            .into_iter()
+            .filter(|event| event.aggregate_id == playlist_id)
+            .map(|event| {
+                let before = store.playlists.get(&playlist_id).cloned();
+                store.apply(&event);
+                let after = store.playlists.get(&playlist_id).cloned();
+                PlaylistHistoryEntry {
+                    event,
+                    before,
+                    after,
+                }
+            })
+            .collect()
+    }
 
-        let created_time_utc = Zoned::now().with_time_zone(TimeZone::UTC).datetime();
-        let event_with_metadata = PlaylistEventWithMetadata {
-            id: Uuid::new_v4(),
-            aggregate_id: playlist_id,
-            aggregate_type: "Playlist".to_string(),
-            created_time_utc,
-            machine_name: hostname::get()?.to_string_lossy().into(),
-            event,
-        };
-        Ok(event_with_metadata)
+    /// Apply an event to the playlist it belongs to, creating the playlist if needed
+    pub fn apply(&mut self, event: &PlaylistEventWithMetadata) {
+        match &event.event {
+            PlaylistEvent::PlaylistCreatedEvent { name } => {
+                self.playlists.insert(
+                    event.aggregate_id,
+                    Playlist::new(event.aggregate_id, name.clone(), event.created_time_utc),
+                );
+            }
+            playlist_event => {
+                if let Some(playlist) = self.playlists.get_mut(&event.aggregate_id) {
+                    playlist.apply(playlist_event);
+                } else {
+                    warn!(
+                        playlist_id = %event.aggregate_id,
+                        ?playlist_event,
+                        "Ignoring playlist event without a creation event"
+                    );
+                }
+            }
+        }
     }
 
-    pub fn from_row(row: EventRow) -> Result<Self> {
-        let event = serde_json::from_str(&row.serialized)
-            .context("Failed to deserialize playlist event")?;
-
-        Ok(PlaylistEventWithMetadata {
-            id: row.id,
-            aggregate_id: row.aggregate_id,
-            aggregate_type: row.aggregate_type,
-            created_time_utc: row.created_time_utc,
-            machine_name: row.machine_name,
-            event,
-        })
+    /// Get non-deleted playlists
+    pub fn active_playlists(&self) -> Vec<&Playlist> {
+        self.playlists.values().filter(|p| !p.is_deleted).collect()
     }
 }
 
+/// A playlist event with metadata
+pub type PlaylistEventWithMetadata = Envelope<PlaylistEvent>;
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::database::save_playlist_event_to_db;
+    use crate::database::save_event_to_db;
+    use crate::migrations::migrate;
 
     #[test]
     fn playlists_survive_database_reload() -> Result<()> {
         let conn = Connection::open_in_memory()?;
-        conn.execute_batch(include_str!("../schema.sql"))?;
+        migrate(&conn)?;
 
         let playlist_id = Uuid::new_v4();
         let item_id = Uuid::new_v4();
@@ -226,7 +343,7 @@ mod tests {
                 new_name: "Morning bangers".to_string(),
             },
         ] {
-            save_playlist_event_to_db(&conn, &PlaylistEventWithMetadata::new(playlist_id, event)?)?;
+            save_event_to_db(&conn, &PlaylistEventWithMetadata::new(playlist_id, event)?)?;
         }
 
         let reloaded = load_playlists_from_db(&conn)?;
@@ -238,10 +355,53 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn playlist_history_describes_each_change() -> Result<()> {
+        let playlist_id = Uuid::new_v4();
+        let item_id = Uuid::new_v4();
+        let events = [
+            PlaylistEvent::PlaylistCreatedEvent {
+                name: "Mix".to_string(),
+            },
+            PlaylistEvent::PlaylistItemAddedEvent {
+                library_item_id: item_id,
+                position: 0,
+            },
+            PlaylistEvent::PlaylistItemMovedEvent {
+                library_item_id: item_id,
+                new_position: 2,
+            },
+            PlaylistEvent::PlaylistRenamedEvent {
+                new_name: "Party mix".to_string(),
+            },
+            PlaylistEvent::PlaylistDeletedEvent,
+        ]
+        .into_iter()
+        .map(|event| PlaylistEventWithMetadata::new(playlist_id, event))
+        .collect::<Result<_>>()?;
+
+        let changes: Vec<_> = PlaylistStore::playlist_history(events, playlist_id)
+            .iter()
+            .map(PlaylistHistoryEntry::changes)
+            .collect();
+        assert_eq!(
+            changes,
+            vec![
+                vec!["created: 'Mix'".to_string()],
+                vec![format!("item added: {item_id} at position 0")],
+                vec![format!("item {item_id} position: '0' → '2'")],
+                vec!["name: 'Mix' → 'Party mix'".to_string()],
+                vec!["is_deleted: 'false' → 'true'".to_string()],
+            ]
+        );
+
+        Ok(())
+    }
+
     #[test]
     fn deleted_playlists_remain_deleted_after_reload() -> Result<()> {
         let conn = Connection::open_in_memory()?;
-        conn.execute_batch(include_str!("../schema.sql"))?;
+        migrate(&conn)?;
 
         let playlist_id = Uuid::new_v4();
         for event in [
@@ -250,7 +410,7 @@ mod tests {
             },
             PlaylistEvent::PlaylistDeletedEvent,
         ] {
-            save_playlist_event_to_db(&conn, &PlaylistEventWithMetadata::new(playlist_id, event)?)?;
+            save_event_to_db(&conn, &PlaylistEventWithMetadata::new(playlist_id, event)?)?;
         }
 
         let reloaded = load_playlists_from_db(&conn)?;
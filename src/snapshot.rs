@@ -0,0 +1,362 @@
+use anyhow::Result;
+use jiff::{tz::TimeZone, Zoned};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{de::DeserializeOwned, Serialize};
+use tracing::{info, warn};
+
+use crate::envelope::AggregateType;
+use crate::hlc::Hlc;
+use crate::library::load_library_from_db;
+use crate::playlist::load_playlists_from_db;
+
+/// Bump this whenever `Library`/`PlaylistStore` change shape or events start being applied
+/// differently (including new event types, which older versions skip as unknown). Snapshots
+/// from other versions are ignored (and eventually replaced), so the next load does a full
+/// replay.
+pub const SNAPSHOT_VERSION: i64 = 8;
+
+/// Write a new snapshot once at least this many events have been saved since the newest
+/// snapshot, either while loading or (see `snapshot_if_due`) while the server is running.
+pub const SNAPSHOT_INTERVAL: usize = 1000;
+
+pub(crate) const LIBRARY_SNAPSHOT: &str = "Library";
+pub(crate) const PLAYLISTS_SNAPSHOT: &str = "Playlists";
+
+/// Materialized state as of a particular point in the event log
+pub(crate) struct Snapshot<T> {
+    pub state: T,
+    /// Every event of this kind with a `Seq` up to and including this one has been applied
+    pub last_event_seq: i64,
+    /// The latest HLC of the applied events. Events are replayed in HLC order, so a tail event
+    /// with an earlier HLC means snapshot+tail would differ from a full replay.
+    pub last_event_hlc: Hlc,
+}
+
+/// Load the newest snapshot of `kind` written by this version of the code
+pub(crate) fn load_snapshot<T: DeserializeOwned>(
+    conn: &Connection,
+    kind: &str,
+) -> Result<Option<Snapshot<T>>> {
+    let row = conn
+        .query_row(
+            "SELECT LastEventRowId, LastEventHlcMillis, LastEventHlcCounter, Serialized FROM snapshots
+             WHERE Kind = ?1 AND SchemaVersion = ?2 ORDER BY Id DESC LIMIT 1",
+            params![kind, SNAPSHOT_VERSION],
+            |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    Hlc {
+                        millis: row.get(1)?,
+                        counter: row.get(2)?,
+                    },
+                    row.get::<_, String>(3)?,
+                ))
+            },
+        )
+        .optional()?;
+
+    let Some((last_event_seq, last_event_hlc, serialized)) = row else {
+        return Ok(None);
+    };
+
+    // A snapshot is only a cache, so a bad one means a slower startup, not a failed one
+    let state = match serde_json::from_str(&serialized) {
+        Ok(state) => state,
+        Err(e) => {
+            warn!(kind, error = ?e, "Ignoring snapshot that failed to deserialize");
+            return Ok(None);
+        }
+    };
+
+    Ok(Some(Snapshot {
+        state,
+        last_event_seq,
+        last_event_hlc,
+    }))
+}
+
+/// Save a snapshot of `kind`, replacing any older ones
+pub(crate) fn save_snapshot<T: Serialize>(
+    conn: &Connection,
+    kind: &str,
+    snapshot: &Snapshot<T>,
+) -> Result<()> {
+    let created_time_utc = Zoned::now().with_time_zone(TimeZone::UTC).datetime();
+    let serialized = serde_json::to_string(&snapshot.state)?;
+
+    let tx = conn.unchecked_transaction()?;
+    tx.execute("DELETE FROM snapshots WHERE Kind = ?1", params![kind])?;
+    tx.execute(
+        "INSERT INTO snapshots (Kind, SchemaVersion, LastEventRowId, LastEventHlcMillis, LastEventHlcCounter, CreatedTimeUtc, Serialized)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        params![
+            kind,
+            SNAPSHOT_VERSION,
+            snapshot.last_event_seq,
+            snapshot.last_event_hlc.millis,
+            snapshot.last_event_hlc.counter,
+            created_time_utc.to_string(),
+            serialized,
+        ],
+    )?;
+    tx.commit()?;
+
+    info!(
+        kind,
+        last_event_seq = snapshot.last_event_seq,
+        size = serialized.len(),
+        "Saved snapshot"
+    );
+    Ok(())
+}
+
+/// Replay the events loaded by `load_tail` on top of the newest snapshot (or from scratch if
+/// there is none), falling back to a full replay if the tail contains events that should have
+/// been applied before the snapshot was taken. That happens when sync delivers an event with
+/// an older HLC than events we already had.
+///
+/// Writes a fresh snapshot when the replayed tail was long.
+pub(crate) fn replay_with_snapshot<T, E>(
+    conn: &Connection,
+    kind: &str,
+    empty: impl Fn() -> T,
+    load_tail: impl Fn(i64) -> Result<Vec<(i64, E)>>,
+    apply: impl Fn(&mut T, &E),
+    hlc: impl Fn(&E) -> Hlc,
+) -> Result<T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    let snapshot_and_tail = match load_snapshot::<T>(conn, kind)? {
+        Some(snapshot) => {
+            let tail = load_tail(snapshot.last_event_seq)?;
+            // <= rather than < because events with equal HLCs are ordered by machine and ID,
+            // and it's simpler to replay everything than to compare those too
+            if tail
+                .iter()
+                .any(|(_, event)| hlc(event) <= snapshot.last_event_hlc)
+            {
+                warn!(
+                    kind,
+                    "Snapshot is older than events synced since; replaying everything"
+                );
+                None
+            } else {
+                Some((snapshot, tail))
+            }
+        }
+        None => None,
+    };
+
+    let (mut snapshot, tail) = match snapshot_and_tail {
+        Some(snapshot_and_tail) => snapshot_and_tail,
+        None => (
+            Snapshot {
+                state: empty(),
+                last_event_seq: 0,
+                last_event_hlc: Hlc::default(),
+            },
+            load_tail(0)?,
+        ),
+    };
+
+    for (seq, event) in &tail {
+        apply(&mut snapshot.state, event);
+        snapshot.last_event_seq = snapshot.last_event_seq.max(*seq);
+        snapshot.last_event_hlc = snapshot.last_event_hlc.max(hlc(event));
+    }
+
+    info!(kind, tail_length = tail.len(), "Replayed events");
+    if tail.len() >= SNAPSHOT_INTERVAL {
+        if let Err(e) = save_snapshot(conn, kind, &snapshot) {
+            warn!(kind, error = ?e, "Failed to save snapshot");
+        }
+    }
+
+    Ok(snapshot.state)
+}
+
+/// Write fresh snapshots of whatever has had `SNAPSHOT_INTERVAL` events saved since its
+/// newest snapshot, so that a server that stays up for a long time doesn't leave a long replay
+/// for the next startup. The database writer calls this after each commit.
+pub(crate) fn snapshot_if_due(conn: &Connection) -> Result<()> {
+    // loading replays the tail and saves a snapshot because the tail is long
+    if tail_length(conn, LIBRARY_SNAPSHOT, AggregateType::LibraryItem)? >= SNAPSHOT_INTERVAL {
+        load_library_from_db(conn)?;
+    }
+    if tail_length(conn, PLAYLISTS_SNAPSHOT, AggregateType::Playlist)? >= SNAPSHOT_INTERVAL {
+        load_playlists_from_db(conn)?;
+    }
+    Ok(())
+}
+
+/// How many events of `aggregate_type` were saved after the newest snapshot of `kind`
+fn tail_length(conn: &Connection, kind: &str, aggregate_type: AggregateType) -> Result<usize> {
+    let last_event_seq: i64 = conn.query_row(
+        "SELECT COALESCE(MAX(LastEventRowId), 0) FROM snapshots
+         WHERE Kind = ?1 AND SchemaVersion = ?2",
+        params![kind, SNAPSHOT_VERSION],
+        |row| row.get(0),
+    )?;
+    // NOT INDEXED so that SQLite walks the (short) tail by Seq instead of every event of the
+    // aggregate type
+    let tail_length: i64 = conn.query_row(
+        "SELECT COUNT(*) FROM events NOT INDEXED WHERE Seq > ?1 AND AggregateType = ?2",
+        params![last_event_seq, aggregate_type.as_str()],
+        |row| row.get(0),
+    )?;
+    Ok(tail_length as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::{load_all_events_from_db, save_event_to_db};
+    use crate::library::{
+        created, load_library_from_db, Event, EventWithMetadata, Library, PlayDetails,
+    };
+    use crate::migrations::migrate;
+    use std::time::Duration;
+    use uuid::Uuid;
+
+    fn created_event(item_id: Uuid, name: &str) -> Result<EventWithMetadata> {
+        EventWithMetadata::new(item_id, created(name))
+    }
+
+    fn snapshot_library(conn: &Connection) -> Result<()> {
+        let library = load_library_from_db(conn)?;
+        let last_event_seq: i64 =
+            conn.query_row("SELECT MAX(Seq) FROM events", [], |row| row.get(0))?;
+        let last_event_hlc = load_all_events_from_db::<Event>(conn)?.last().unwrap().hlc;
+        save_snapshot(
+            conn,
+            LIBRARY_SNAPSHOT,
+            &Snapshot {
+                state: library,
+                last_event_seq,
+                last_event_hlc,
+            },
+        )
+    }
+
+    #[test]
+    fn snapshot_plus_tail_matches_full_replay() -> Result<()> {
+        let conn = Connection::open_in_memory()?;
+        migrate(&conn)?;
+
+        let first_id = Uuid::new_v4();
+        let second_id = Uuid::new_v4();
+        let bookmark_id = Uuid::new_v4();
+        for event in [
+            created_event(first_id, "First")?,
+            EventWithMetadata::new(
+                first_id,
+                Event::LibraryItemPlayedEvent(PlayDetails::default()),
+            )?,
+            EventWithMetadata::new(
+                first_id,
+                Event::LibraryItemBookmarkAddedEvent {
+                    bookmark_id,
+                    position: Duration::from_secs_f64(754.321),
+                    end_position: None,
+                    label: Some("The good bit".to_string()),
+                },
+            )?,
+        ] {
+            save_event_to_db(&conn, &event)?;
+        }
+        snapshot_library(&conn)?;
+
+        for event in [
+            created_event(second_id, "Second")?,
+            EventWithMetadata::new(first_id, Event::LibraryItemFavoritedEvent)?,
+            EventWithMetadata::new(
+                first_id,
+                Event::LibraryItemNameChangedEvent {
+                    new_name: "Renamed".to_string(),
+                },
+            )?,
+        ] {
+            save_event_to_db(&conn, &event)?;
+        }
+
+        let from_snapshot = load_library_from_db(&conn)?;
+        let full_replay = Library::build_from_events(load_all_events_from_db(&conn)?);
+        assert_eq!(from_snapshot.items, full_replay.items);
+        assert_eq!(from_snapshot.items[&first_id].name, "Renamed");
+        assert!(from_snapshot.items.contains_key(&second_id));
+
+        Ok(())
+    }
+
+    #[test]
+    fn snapshots_are_written_once_enough_events_have_been_saved() -> Result<()> {
+        let conn = Connection::open_in_memory()?;
+        migrate(&conn)?;
+
+        let item_id = Uuid::new_v4();
+        save_event_to_db(&conn, &created_event(item_id, "Played a lot")?)?;
+        for _ in 1..SNAPSHOT_INTERVAL - 1 {
+            let played = EventWithMetadata::new(
+                item_id,
+                Event::LibraryItemPlayedEvent(PlayDetails::default()),
+            )?;
+            save_event_to_db(&conn, &played)?;
+        }
+        snapshot_if_due(&conn)?;
+        assert!(load_snapshot::<Library>(&conn, LIBRARY_SNAPSHOT)?.is_none());
+
+        let played = EventWithMetadata::new(
+            item_id,
+            Event::LibraryItemPlayedEvent(PlayDetails::default()),
+        )?;
+        save_event_to_db(&conn, &played)?;
+        snapshot_if_due(&conn)?;
+        let snapshot = load_snapshot::<Library>(&conn, LIBRARY_SNAPSHOT)?.unwrap();
+        assert_eq!(snapshot.last_event_seq, SNAPSHOT_INTERVAL as i64);
+        assert_eq!(
+            snapshot.state.items[&item_id].play_count,
+            SNAPSHOT_INTERVAL as u32 - 1
+        );
+        // nothing has been saved since, so there's nothing to do
+        assert_eq!(
+            tail_length(&conn, LIBRARY_SNAPSHOT, AggregateType::LibraryItem)?,
+            0
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn stale_snapshots_are_ignored() -> Result<()> {
+        let conn = Connection::open_in_memory()?;
+        migrate(&conn)?;
+
+        let item_id = Uuid::new_v4();
+        save_event_to_db(&conn, &created_event(item_id, "Original")?)?;
+        snapshot_library(&conn)?;
+
+        // A snapshot from another version of the code
+        conn.execute("UPDATE snapshots SET SchemaVersion = SchemaVersion + 1", [])?;
+        conn.execute("UPDATE snapshots SET Serialized = '{}'", [])?;
+        assert!(load_snapshot::<Library>(&conn, LIBRARY_SNAPSHOT)?.is_none());
+        assert!(load_library_from_db(&conn)?.items.contains_key(&item_id));
+
+        // An event that sorts before the snapshot arrives via sync. Replaying it after the
+        // snapshot would count a play that a full replay ignores (it predates the item).
+        snapshot_library(&conn)?;
+        let mut late_event = EventWithMetadata::new(
+            item_id,
+            Event::LibraryItemPlayedEvent(PlayDetails::default()),
+        )?;
+        late_event.hlc = Hlc::default();
+        save_event_to_db(&conn, &late_event)?;
+
+        let library = load_library_from_db(&conn)?;
+        let full_replay = Library::build_from_events(load_all_events_from_db(&conn)?);
+        assert_eq!(library.items, full_replay.items);
+        assert_eq!(library.items[&item_id].play_count, 0);
+
+        Ok(())
+    }
+}
@@ -11,6 +11,15 @@ pub fn init_tracing() {
         .init();
 }
 
+/// Initialize tracing for command-line tools whose output goes to stdout: only warnings and
+/// errors, written to stderr
+pub fn init_cli_tracing() {
+    tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
+        .with_max_level(tracing::Level::WARN)
+        .init();
+}
+
 /// IMO the v3 style was nice and it's dumb that clap removed colour in v4
 pub fn clap_v3_style() -> Styles {
     use clap::builder::styling::AnsiColor;
@@ -21,6 +30,11 @@ pub fn clap_v3_style() -> Styles {
         .placeholder(AnsiColor::Green.on_default())
 }
 
+/// Describe a field that changed, e.g. `artist: '' → 'Girl Talk'`. `None` if it didn't.
+pub(crate) fn describe_change(field: &str, before: &str, after: &str) -> Option<String> {
+    (before != after).then(|| format!("{field}: '{before}' → '{after}'"))
+}
+
 /// Hash the password with a salt that changes every quarter. Forces users to re-login every quarter.
 /// The nice thing about personal projects is that I don't have to gaf about best practices 😎
 pub fn hash_with_rotating_salt(password: &str) -> String {